@@ -1,91 +1,104 @@
+use data_encoding::BASE32_NOPAD;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use std::fs::{self, File};
-use std::io::prelude::*;
-use std::io::{BufReader, Error, ErrorKind, Result};
-use std::path::PathBuf;
-
-use crate::crypto::{decrypt_pw, decrypt_string, encrypt_string};
-
-const FILE_NAME: &str = "accounts.txt";
-const SECRETS_FILE_NAME: &str = "secrets.txt";
-enum FileType {
-    Accounts,
-    Secrets,
-}
-
-fn get_path(file_type: FileType) -> Result<PathBuf> {
-    let home = dirs::home_dir().expect("Unable to find home directory");
-    let directory = home.join(".otp");
-    fs::create_dir_all(&directory).expect("Unable to create .otp directory");
-
-    let filename = match file_type {
-        FileType::Accounts => FILE_NAME,
-        FileType::Secrets => SECRETS_FILE_NAME,
-    };
-
-    Ok([directory, PathBuf::from(filename)].iter().collect())
-}
-
-fn load_file_to_string(path: &PathBuf) -> Result<String> {
-    if !path.exists() {
-        File::create(&path)?;
-    }
-
-    let file = File::open(path)?;
-    let mut buf_reader = BufReader::new(file);
-    let mut contents = String::new();
-    buf_reader.read_to_string(&mut contents)?;
-
-    Ok(contents)
-}
-
-fn load_file_to_vec(path: &PathBuf) -> Result<Vec<u8>> {
-    if !path.exists() {
-        File::create(&path)?;
-    }
-
-    let file = File::open(path)?;
-    let mut buf_reader = BufReader::new(file);
-    let mut contents: Vec<u8> = Vec::new();
-    buf_reader.read_to_end(&mut contents)?;
-
-    Ok(contents)
-}
-
-fn decrypt_accounts(encrypted_account_contents: &Vec<u8>, secrets: &Secrets) -> Result<String> {
+use std::io::{Error, ErrorKind, Result};
+
+use crate::audit::{append_events, read_log, AuditEvent, AuditReport, Outcome};
+use crate::crypto::{
+    decrypt_key, decrypt_pw, decrypt_string, derive_key, encrypt_key, encrypt_pw, encrypt_string,
+    generate_key, generate_salt, get_public_key, keyed_digest, open_sealed_key, seal_key,
+    KdfParams, SealedKey, NONCE_LENGTH, TAG_LENGTH,
+};
+use crate::hotp::Codes;
+use crate::migration::{get_version, migrate, SCHEMA_VERSION};
+use crate::policy::{unlock_delay, Listing, PinPolicy};
+use crate::secret::{Secret, SecretBytes, SecretKey};
+use crate::storage::{self, get_directory, Record, StorageBackend};
+use crate::utils::{format_timestamp, generate_secret_32, percent_encode};
+use crate::verify::Finding;
+
+fn decrypt_accounts(
+    encrypted_account_contents: &Vec<u8>,
+    key: &[u8],
+    secrets: &Secrets,
+) -> Result<Secret<String>> {
     match encrypted_account_contents {
-        contents if contents.is_empty() => {
-            let empty_string = String::from_utf8(contents.to_vec());
-            match empty_string {
-                Ok(empty_string) => return Ok(empty_string),
-                Err(err) => return Err(Error::new(ErrorKind::InvalidData, err)),
-            }
-        }
+        contents if contents.is_empty() => Ok(Secret::new(String::new())),
         encrypted_contents => {
-            let salt = secrets.get_salt();
-            let nonce = secrets.nonce.clone();
-            let decrypted_contents = match (salt, nonce) {
-                (Some(salt), Some(nonce)) => {
-                    let content = decrypt_string(&encrypted_contents, &salt, &nonce);
+            let decrypted_contents = match &secrets.nonce {
+                Some(nonce) => {
+                    let content = decrypt_string(encrypted_contents, key, nonce);
 
                     match content {
-                        Ok(content) => Ok(content),
+                        Ok(mut content) => {
+                            content.lock();
+                            Ok(content)
+                        }
                         Err(_) => Err(Error::new(ErrorKind::InvalidData, "Decryption failed")),
                     }
                 }
-                _ => Err(Error::new(ErrorKind::InvalidData, "No salt or nonce found")),
+                _ => Err(Error::new(ErrorKind::InvalidData, "No nonce found")),
             }?;
 
-            return Ok(decrypted_contents);
+            Ok(decrypted_contents)
         }
-    };
+    }
+}
+
+// Each record holds a single account, as a table named after it like in the vault
+fn decrypt_record(record: &Record, key: &[u8]) -> Result<(BTreeMap<String, Account>, String)> {
+    let contents = decrypt_string(&record.contents, key, &record.nonce)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "Decryption failed"))?;
+    let accounts = toml::from_str(contents.expose()).map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Deserialization failure: {}", err),
+        )
+    })?;
+    Ok((accounts, keyed_digest(key, contents.expose().as_bytes())))
+}
+
+// The records to write, the ids of those to delete and the digest of every account
+type RecordChanges = (Vec<Record>, Vec<String>, BTreeMap<String, String>);
+
+// Encrypts the accounts that changed since they were loaded or saved, and finds the records
+// of those that are gone. Records are kept under a digest of the name, so both change with
+// the key and a new key rewrites every record.
+fn changed_records(
+    accounts: &BTreeMap<String, Account>,
+    key: &[u8],
+    saved: &BTreeMap<String, String>,
+) -> Result<RecordChanges> {
+    let mut changed = Vec::new();
+    let mut digests = BTreeMap::new();
+    for (name, account) in accounts {
+        let id = keyed_digest(key, name.as_bytes());
+        let contents = toml::to_string(&BTreeMap::from([(name, account)]))
+            .map(Secret::new)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Account serialization failure"))?;
+        let digest = keyed_digest(key, contents.expose().as_bytes());
+        if saved.get(&id) != Some(&digest) {
+            let (contents, nonce) = encrypt_string(contents.expose(), key)
+                .map_err(|_| Error::new(ErrorKind::InvalidData, "Encryption failure"))?;
+            changed.push(Record {
+                id: id.clone(),
+                nonce,
+                contents,
+            });
+        }
+        digests.insert(id, digest);
+    }
+    let removed = saved
+        .keys()
+        .filter(|id| !digests.contains_key(*id))
+        .cloned()
+        .collect();
+    Ok((changed, removed, digests))
 }
 
-fn deserialize_accounts(account_contents: String) -> Result<BTreeMap<String, Account>> {
-    let accounts = toml::from_str(&account_contents);
-    match accounts {
-        Ok(accounts) => Ok(accounts),
+fn deserialize_accounts(account_contents: &str) -> Result<Vault> {
+    match toml::from_str::<Vault>(account_contents) {
+        Ok(vault) => Ok(vault),
         Err(err) => Err(Error::new(
             ErrorKind::InvalidData,
             format!("Deserialization failure: {}", err),
@@ -93,369 +106,2619 @@ fn deserialize_accounts(account_contents: String) -> Result<BTreeMap<String, Acc
     }
 }
 
-fn load_accounts() -> Result<Vec<u8>> {
-    let account_path = get_path(FileType::Accounts)?;
+// Events recorded while the store is locked, such as failed unlocks, wait here until they
+// can be sealed into the log. Only the time, operation and outcome are kept in the clear.
+fn format_pending_event(event: &AuditEvent) -> String {
+    format!(
+        "{} {} {}\n",
+        event.timestamp,
+        event.operation,
+        event.outcome.as_str()
+    )
+}
+
+fn parse_pending_events(contents: &str) -> Vec<AuditEvent> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let timestamp = fields.next()?.parse::<u64>().ok()?;
+            let operation = String::from(fields.next()?);
+            let outcome = match fields.next()? {
+                "success" => Outcome::Success,
+                _ => Outcome::Failure,
+            };
+            Some(AuditEvent {
+                timestamp,
+                operation,
+                account: None,
+                outcome,
+            })
+        })
+        .collect()
+}
 
-    let attempt = load_file_to_vec(&account_path);
-    let encrypted_account_contents = match attempt {
-        Ok(contents) => contents,
-        Err(err) => {
-            eprintln!("Error: {}", err);
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "Oh no! Couldn't load the accounts",
-            ));
-        }
-    };
-    Ok(encrypted_account_contents)
+fn decode_audit_key(encoded: &Secret<String>) -> Result<SecretBytes> {
+    BASE32_NOPAD
+        .decode(encoded.expose().as_bytes())
+        .map(Secret::new)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid audit log key"))
+}
+
+// The audit key, created on first use. Returns whether it was just created.
+fn get_audit_key(vault: &mut Vault) -> Result<(SecretBytes, bool)> {
+    let created = vault.audit_key.is_none();
+    let encoded = vault
+        .audit_key
+        .get_or_insert_with(|| Secret::new(generate_secret_32()));
+    Ok((decode_audit_key(encoded)?, created))
+}
+
+// Retires the audit key for a new one, kept so the entries it sealed can still be read
+fn rotate_audit_key(vault: &mut Vault) {
+    let key = Secret::new(generate_secret_32());
+    if let Some(retired) = vault.audit_key.replace(key) {
+        vault.retired_audit_keys.push(retired);
+    }
 }
 
-fn load_secrets() -> Result<Secrets> {
-    let secrets_path = get_path(FileType::Secrets)?;
-    let secrets_content = load_file_to_string(&secrets_path)?;
-    let secrets: Secrets = toml::from_str(&secrets_content)?;
-    Ok(secrets)
+fn read_audit(vault: &Vault, log: &str) -> Result<AuditReport> {
+    let encoded = match &vault.audit_key {
+        Some(encoded) => encoded,
+        None => return Ok(AuditReport::default()),
+    };
+    let keys = std::iter::once(encoded)
+        .chain(vault.retired_audit_keys.iter().rev())
+        .map(decode_audit_key)
+        .collect::<Result<Vec<SecretBytes>>>()?;
+    let keys: Vec<&[u8]> = keys.iter().map(|key| key.expose().as_slice()).collect();
+    Ok(read_log(log, &keys))
 }
 
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
-#[serde(tag = "type")]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "counter")]
 pub enum OtpType {
     HOTP(Option<i32>),
     TOTP,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
-pub struct Account {
-    pub key: String,
-    pub otp_type: OtpType,
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Algorithm {
+    SHA1,
+    SHA256,
+    SHA512,
 }
 
-impl Account {
-    pub fn new(key: String, otp_type: OtpType) -> Self {
-        Account { key, otp_type }
+impl Algorithm {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Algorithm::SHA1 => "sha1",
+            Algorithm::SHA256 => "sha256",
+            Algorithm::SHA512 => "sha512",
+        }
+    }
+
+    pub fn from_str(algorithm: &str) -> Option<Self> {
+        match algorithm {
+            "sha1" => Some(Algorithm::SHA1),
+            "sha256" => Some(Algorithm::SHA256),
+            "sha512" => Some(Algorithm::SHA512),
+            _ => None,
+        }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Secrets {
-    hash: Option<String>,
-    nonce: Option<Vec<u8>>,
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_PERIOD: u64 = 30;
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Account {
+    pub key: SecretKey,
+    pub issuer: Option<String>,
+    pub label: Option<String>,
+    pub notes: Option<String>,
+    // Unix timestamps in seconds
+    pub created_at: Option<u64>,
+    pub last_used_at: Option<u64>,
+    #[serde(default)]
+    pub use_count: u64,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Code settings, left unset to use the defaults for the otp type
+    pub digits: Option<u32>,
+    pub algorithm: Option<Algorithm>,
+    pub period: Option<u64>,
+    // TOML tables have to follow plain values, so these stay last
+    #[serde(default)]
+    pub recovery_codes: Vec<RecoveryCode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_key: Option<PreviousKey>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub credentials: Vec<Credential>,
+    pub otp_type: OtpType,
 }
 
-impl Secrets {
-    fn get_salt(&self) -> Option<Vec<u8>> {
-        let salt = match &self.hash {
-            Some(hash) => {
-                let salt = hash.clone().into_bytes()[..32].to_vec();
-                Some(salt)
-            }
-            None => None,
-        };
-        salt
-    }
+// The name the account's own key goes by next to its other credentials
+pub const PRIMARY_CREDENTIAL: &str = "primary";
+
+// Another key the account is enrolled with, such as a hardware token next to a phone app
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Credential {
+    pub name: String,
+    pub key: SecretKey,
+    pub otp_type: OtpType,
 }
 
-pub struct AccountStore {
-    accounts: BTreeMap<String, Account>,
-    secrets: Secrets,
+// The key replaced by rotate, still accepted by validate until it expires
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct PreviousKey {
+    pub key: SecretKey,
+    // Unix timestamp in seconds
+    pub expires_at: u64,
+    // The algorithm the account was left on before rotating, which decides its codes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<Algorithm>,
 }
 
-pub trait AccountStoreOperations {
-    fn get(&self, key: &str) -> Option<&Account>;
-    fn list(&self) -> Vec<String>;
-    fn add(&mut self, account_name: String, account: Account);
-    fn delete(&mut self, account_name: &str) -> Option<Account>;
-    fn is_initialized(&self) -> bool;
-    fn save(&self) -> Result<()>;
-    fn set_counter(&mut self, key: &str, counter: i32);
-    fn set_secrets(&mut self, hash: &str);
-    fn validate_pin(&self, pin: &str) -> bool;
+// One-time recovery code issued by the service alongside the key
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct RecoveryCode {
+    pub code: SecretKey,
+    pub used_at: Option<u64>,
 }
 
-impl AccountStore {
-    pub fn new() -> Result<AccountStore> {
-        let secrets = load_secrets()?;
-        let encrypted_account_contents = load_accounts()?;
+impl Account {
+    pub fn new(key: String, otp_type: OtpType) -> Self {
+        Account {
+            key: Secret::new(key),
+            issuer: None,
+            label: None,
+            notes: None,
+            created_at: None,
+            last_used_at: None,
+            use_count: 0,
+            tags: Vec::new(),
+            digits: None,
+            algorithm: None,
+            period: None,
+            recovery_codes: Vec::new(),
+            previous_key: None,
+            credentials: Vec::new(),
+            otp_type,
+        }
+    }
 
-        let account_contents = decrypt_accounts(&encrypted_account_contents, &secrets)?;
-        let accounts = deserialize_accounts(account_contents)?;
+    // Another key with the code settings of this account
+    fn with_key(&self, key: &SecretKey, otp_type: &OtpType) -> Account {
+        let mut account = Account::new(key.expose().clone(), otp_type.clone());
+        account.digits = self.digits;
+        account.algorithm = self.algorithm;
+        account.period = self.period;
+        account
+    }
 
-        Ok(AccountStore { accounts, secrets })
+    pub fn get_digits(&self) -> u32 {
+        self.digits.unwrap_or(DEFAULT_DIGITS)
     }
-}
 
-macro_rules! account_store_read {
-    () => {
-        fn get(&self, account_name: &str) -> Option<&Account> {
-            self.accounts.get(account_name)
+    // HOTP accounts have always used SHA-1 and TOTP accounts SHA-256
+    pub fn get_algorithm(&self) -> Algorithm {
+        match (self.algorithm, &self.otp_type) {
+            (Some(algorithm), _) => algorithm,
+            (None, OtpType::HOTP(_)) => Algorithm::SHA1,
+            (None, OtpType::TOTP) => Algorithm::SHA256,
         }
+    }
 
-        fn list(&self) -> Vec<String> {
-            self.accounts.keys().cloned().collect()
+    pub fn get_codes(&self) -> Codes {
+        match self.algorithm {
+            Some(_) => Codes::Rfc,
+            None => Codes::Legacy,
         }
-    };
-}
+    }
 
-macro_rules! account_store_mutate {
-    () => {
-        fn add(&mut self, account_name: String, account: Account) {
-            self.accounts.insert(account_name, account);
-        }
+    pub fn get_period(&self) -> u64 {
+        self.period.unwrap_or(DEFAULT_PERIOD)
+    }
 
-        fn delete(&mut self, account_name: &str) -> Option<Account> {
-            self.accounts.remove(account_name)
-        }
+    // The current key stays valid alongside the new one until the given time
+    // The new key gets an explicit algorithm, so its codes follow the RFCs like those of the
+    // apps enrolled from its URI. The old key keeps the codes it had.
+    pub fn rotate_key(&mut self, key: String, expires_at: u64) {
+        let previous = std::mem::replace(&mut self.key, Secret::new(key));
+        let algorithm = self.algorithm.replace(self.get_algorithm());
+        self.previous_key = Some(PreviousKey {
+            key: previous,
+            expires_at,
+            algorithm,
+        });
+    }
 
-        fn set_counter(&mut self, account_name: &str, counter: i32) {
-            let account = self.accounts.get_mut(account_name);
-            match account {
-                Some(account) => account.otp_type = OtpType::HOTP(Some(counter)),
-                None => println!("Account not found: {}", account_name),
-            }
-        }
+    // The account as it was before rotating its key, while that key is still accepted
+    pub fn get_previous(&self, timestamp: u64) -> Option<Account> {
+        let previous_key = self
+            .previous_key
+            .as_ref()
+            .filter(|previous_key| timestamp < previous_key.expires_at)?;
+        let mut previous = self.with_key(&previous_key.key, &self.otp_type);
+        previous.algorithm = previous_key.algorithm;
+        Some(previous)
+    }
 
-        fn set_secrets(&mut self, hash: &str) {
-            self.secrets = Secrets {
-                hash: Some(String::from(hash)),
-                nonce: None,
-            };
-        }
-    };
-}
+    // The account as seen through one of its other credentials
+    pub fn get_credential(&self, name: &str) -> Option<Account> {
+        self.credentials
+            .iter()
+            .find(|credential| credential.name == name)
+            .map(|credential| self.with_key(&credential.key, &credential.otp_type))
+    }
 
-macro_rules! account_store_password {
-    () => {
-        fn is_initialized(&self) -> bool {
-            self.secrets.hash.is_some()
+    pub fn add_credential(&mut self, name: &str, key: String, otp_type: OtpType) -> Result<()> {
+        if name == PRIMARY_CREDENTIAL || self.get_credential(name).is_some() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Credential already exists: {}", name),
+            ));
         }
+        self.credentials.push(Credential {
+            name: String::from(name),
+            key: Secret::new(key),
+            otp_type,
+        });
+        Ok(())
+    }
 
-        fn validate_pin(&self, pin: &str) -> bool {
-            let stored_pin = match self.secrets.hash.clone() {
-                Some(pin) => pin,
-                None => return false,
-            };
-            let matches = decrypt_pw(&stored_pin, pin);
-            matches
+    pub fn remove_credential(&mut self, name: &str) -> Result<()> {
+        match self
+            .credentials
+            .iter()
+            .position(|credential| credential.name == name)
+        {
+            Some(index) => {
+                self.credentials.remove(index);
+                Ok(())
+            }
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Credential not found: {}", name),
+            )),
         }
-    };
-}
-
-impl AccountStoreOperations for AccountStore {
-    account_store_read!();
+    }
 
-    account_store_mutate!();
+    pub fn set_credential_counter(&mut self, name: &str, counter: i32) {
+        if let Some(credential) = self
+            .credentials
+            .iter_mut()
+            .find(|credential| credential.name == name)
+        {
+            credential.otp_type = OtpType::HOTP(Some(counter));
+        }
+    }
 
-    account_store_password!();
+    // Every key the account is enrolled with by credential name, the primary first
+    pub fn keys(&self) -> Vec<(&str, &SecretKey)> {
+        let mut keys = vec![(PRIMARY_CREDENTIAL, &self.key)];
+        keys.extend(
+            self.credentials
+                .iter()
+                .map(|credential| (credential.name.as_str(), &credential.key)),
+        );
+        keys
+    }
 
-    fn save(&self) -> Result<()> {
-        // Encrypt and serialize accounts
-        let account_contents = match toml::to_string(&self.accounts) {
-            Ok(content) => content,
-            Err(err) => {
-                println!("Oh no! Couldn't save the accounts: {}", err);
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "Account serialization failure",
-                ));
-            }
-        };
-        let salt = match self.secrets.get_salt() {
-            Some(salt) => salt,
-            None => {
-                println!("No password found");
-                return Err(Error::new(ErrorKind::InvalidData, "Missing salt"));
-            }
+    // Key URI for enrolling the account in an authenticator app, as read from QR codes. Apps
+    // decode the secret from Base32 and key the HMAC with the result, while otp keys it with
+    // the text of the key, so the secret is the Base32 of that text. Apps only compute the same
+    // codes for accounts with an explicit algorithm, as rotate leaves them.
+    pub fn get_uri(&self, account_name: &str) -> String {
+        let name = self.label.as_deref().unwrap_or(account_name);
+        let (otp_type, moving_factor) = match self.otp_type {
+            OtpType::TOTP => ("totp", format!("period={}", self.get_period())),
+            OtpType::HOTP(counter) => ("hotp", format!("counter={}", counter.unwrap_or(0))),
         };
-        let (encrypted_content, nonce) = match encrypt_string(&account_contents, &salt) {
-            Ok(result) => result,
-            Err(err) => {
-                println!("Oh no! Couldn't save the accounts {}", err);
-                return Err(Error::new(ErrorKind::InvalidData, "Encryption failure"));
-            }
+        let mut uri = match &self.issuer {
+            Some(issuer) => format!(
+                "otpauth://{}/{}:{}?issuer={}&",
+                otp_type,
+                percent_encode(issuer),
+                percent_encode(name),
+                percent_encode(issuer)
+            ),
+            None => format!("otpauth://{}/{}?", otp_type, percent_encode(name)),
         };
+        uri.push_str(&format!(
+            "secret={}&algorithm={}&digits={}&{}",
+            BASE32_NOPAD.encode(self.key.expose().as_bytes()),
+            self.get_algorithm().as_str().to_uppercase(),
+            self.get_digits(),
+            moving_factor
+        ));
+        uri
+    }
 
-        let path = get_path(FileType::Accounts)?;
+    // Tags are kept sorted and without duplicates
+    pub fn add_tags(&mut self, tags: &[&str]) {
+        for tag in tags {
+            if !self.tags.iter().any(|existing| existing == tag) {
+                self.tags.push(String::from(*tag));
+            }
+        }
+        self.tags.sort();
+    }
 
-        // Serialize secrets
-        let secrets = Secrets {
-            hash: self.secrets.hash.clone(),
-            nonce: Some(nonce),
-        };
+    pub fn remove_tags(&mut self, tags: &[&str]) {
+        self.tags
+            .retain(|existing| !tags.contains(&existing.as_str()));
+    }
 
-        let secrets_content = match toml::to_string(&secrets) {
-            Ok(content) => content,
-            Err(err) => {
-                println!("Oh no! Couldn't save {}", err);
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "Secrets serialization failure",
-                ));
+    // Returns how many of the codes were new
+    pub fn add_recovery_codes(&mut self, codes: &[&str]) -> usize {
+        let mut added = 0;
+        for code in codes {
+            if !self
+                .recovery_codes
+                .iter()
+                .any(|existing| existing.code.expose() == code)
+            {
+                self.recovery_codes.push(RecoveryCode {
+                    code: SecretKey::from(*code),
+                    used_at: None,
+                });
+                added += 1;
             }
-        };
+        }
+        added
+    }
 
-        // Save
-        let secrets_path = get_path(FileType::Secrets)?;
-        if !secrets_path.exists() {
-            File::create(&secrets_path)?;
+    pub fn remaining_recovery_codes(&self) -> usize {
+        self.recovery_codes
+            .iter()
+            .filter(|code| code.used_at.is_none())
+            .count()
+    }
+
+    // Mark the given code as used, or the first unused one when no code is given
+    pub fn use_recovery_code(&mut self, code: Option<&str>, timestamp: u64) -> Result<SecretKey> {
+        let recovery_code = match code {
+            Some(code) => self
+                .recovery_codes
+                .iter_mut()
+                .find(|existing| existing.code.expose() == code)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, "Recovery code not found"))?,
+            None => self
+                .recovery_codes
+                .iter_mut()
+                .find(|existing| existing.used_at.is_none())
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, "No recovery codes left"))?,
+        };
+        if let Some(used_at) = recovery_code.used_at {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Recovery code was already used on {}",
+                    format_timestamp(used_at)
+                ),
+            ));
         }
-        fs::write(secrets_path, secrets_content)?;
-        fs::write(path, encrypted_content)?;
 
-        Ok(())
+        recovery_code.used_at = Some(timestamp);
+        Ok(recovery_code.code.clone())
     }
 }
 
-#[cfg(test)]
-pub struct MockAccountStore {
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct TrashedAccount {
+    pub name: String,
+    pub deleted_at: u64,
+    pub account: Account,
+}
+
+// Everything in the encrypted accounts file
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Vault {
+    // Schema of this TOML, stamped on every save. Older stores are migrated when unlocked.
+    #[serde(default)]
+    version: u32,
+    // Base32 key for the audit log, kept here so changing the pin doesn't orphan the log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    audit_key: Option<Secret<String>>,
+    // Audit keys replaced when a member was removed, oldest first, for reading earlier entries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    retired_audit_keys: Vec<Secret<String>>,
+    #[serde(default)]
     accounts: BTreeMap<String, Account>,
-    secrets: Secrets,
-    should_save_error: bool,
+    // An empty array would be a plain value after the accounts table, which TOML rejects
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    trash: Vec<TrashedAccount>,
 }
 
-#[cfg(test)]
-impl MockAccountStore {
-    pub fn set_should_save_error(&mut self, should_save_error: bool) {
-        self.should_save_error = should_save_error;
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Secrets {
+    hash: Option<String>,
+    nonce: Option<Vec<u8>>,
+    // Stores created before the key was derived from the pin have no salt or kdf params
+    salt: Option<Vec<u8>>,
+    // Whether a keyfile is mixed into the key. Plain values come before the tables below.
+    #[serde(default)]
+    keyfile: bool,
+    #[serde(default)]
+    listing: Listing,
+    // Failed unlocks since the last success, kept here so they count across runs
+    #[serde(default)]
+    failed_unlocks: u32,
+    last_failed_unlock: Option<u64>,
+    // Destroy the vault once this many unlocks in a row have failed
+    wipe_after: Option<u32>,
+    // Digest of the members keyed with the store key, so none can be slipped into this file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    members_digest: Option<String>,
+    kdf: Option<KdfParams>,
+    policy: Option<PinPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    index: Option<NameIndex>,
+    // Once the store is shared its accounts have a random key, wrapped for the pin and each member
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wrapped_key: Option<WrappedKey>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    members: Vec<Member>,
+}
+
+// The key of a shared store, encrypted with the key derived from the pin
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct WrappedKey {
+    nonce: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl WrappedKey {
+    fn new(key: &SecretBytes, pin_key: &SecretBytes) -> Result<WrappedKey> {
+        let (key, nonce) = encrypt_key(key.expose(), pin_key.expose())
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        Ok(WrappedKey { nonce, key })
+    }
+
+    fn open(&self, pin_key: &SecretBytes) -> Result<SecretBytes> {
+        decrypt_key(&self.key, pin_key.expose(), &self.nonce)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Decryption failed"))
     }
 }
 
-#[cfg(test)]
-impl AccountStoreOperations for MockAccountStore {
-    account_store_read!();
+// Someone the store is shared with, who unlocks it with the X25519 identity of their public key
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Member {
+    pub name: String,
+    // Base32 like the account keys
+    pub public_key: String,
+    sealed_key: SealedKey,
+}
 
-    account_store_mutate!();
+fn decode_public_key(public_key: &str) -> Result<Vec<u8>> {
+    BASE32_NOPAD
+        .decode(public_key.as_bytes())
+        .ok()
+        .filter(|key| key.len() == 32)
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid public key: {}", public_key),
+            )
+        })
+}
 
-    account_store_password!();
+pub fn encode_public_key(public_key: &[u8]) -> String {
+    BASE32_NOPAD.encode(public_key)
+}
 
-    fn save(&self) -> Result<()> {
-        println!("MockAccountStore saving");
-        if self.should_save_error {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "MockAccountStore failed to save",
-            ));
-        }
-        Ok(())
+// Names are length prefixed, so no two lists of members run together the same way
+fn digest_members(members: &[Member], key: &[u8]) -> Option<String> {
+    if members.is_empty() {
+        return None;
     }
+    let contents: String = members
+        .iter()
+        .map(|member| {
+            format!(
+                "{} {} {}\n",
+                member.name.len(),
+                member.name,
+                member.public_key
+            )
+        })
+        .collect();
+    Some(keyed_digest(key, contents.as_bytes()))
 }
 
-#[cfg(test)]
-pub mod tests {
-    use super::*;
-    use crate::crypto::encrypt_pw;
-    use crate::tests::constants::{ACCOUNT_NAME_1, ACCOUNT_NAME_2, PIN};
-
-    pub fn create_empty_store() -> MockAccountStore {
-        MockAccountStore {
-            accounts: BTreeMap::new(),
-            secrets: Secrets {
-                hash: None,
-                nonce: None,
-            },
-            should_save_error: false,
-        }
-    }
-
-    pub fn get_mock_store() -> MockAccountStore {
-        let mut store = MockAccountStore {
-            accounts: BTreeMap::new(),
-            secrets: Secrets {
-                hash: None,
-                nonce: None,
-            },
-            should_save_error: false,
+// Account names kept in the clear with the secrets, so they can be listed without the pin.
+// Any key able to open them without the pin would have to sit beside them, so there is none:
+// anyone who can read the secrets can read the names, but never the accounts.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct NameIndex {
+    names: Vec<String>,
+}
+
+impl Secrets {
+    fn new(
+        pin: &str,
+        kdf: KdfParams,
+        policy: Option<PinPolicy>,
+        keyfile: Option<&[u8]>,
+    ) -> Result<(Secrets, SecretBytes)> {
+        let to_error = |err| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Error encrypting password {}", err),
+            )
         };
-        let hash = encrypt_pw(PIN).expect("Failed to encrypt pin");
-        store.set_secrets(&hash);
-        store.add(
-            String::from(ACCOUNT_NAME_1),
-            Account::new(String::from("key-1"), OtpType::HOTP(Some(0))),
-        );
-        store.add(
-            String::from(ACCOUNT_NAME_2),
-            Account::new(String::from("key-2"), OtpType::TOTP),
-        );
-        store
+        let hash = encrypt_pw(pin, &kdf, keyfile).map_err(to_error)?;
+        let salt = generate_salt();
+        let key = derive_key(pin, &salt, &kdf, keyfile).map_err(to_error)?;
+
+        let secrets = Secrets {
+            hash: Some(hash),
+            nonce: None,
+            salt: Some(salt),
+            keyfile: keyfile.is_some(),
+            listing: Listing::default(),
+            failed_unlocks: 0,
+            last_failed_unlock: None,
+            wipe_after: None,
+            members_digest: None,
+            kdf: Some(kdf),
+            policy,
+            index: None,
+            wrapped_key: None,
+            members: Vec::new(),
+        };
+        Ok((secrets, key))
     }
 
-    #[test]
-    fn adds_an_account() {
-        let mut store = get_mock_store();
-        let account = Account::new(String::from("some-key"), OtpType::TOTP);
-        store.add(String::from("pets.com"), account);
+    fn derive_key(&self, pin: &str, keyfile: Option<&[u8]>) -> Result<SecretBytes> {
+        match (self.keyfile, keyfile) {
+            (true, None) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "A keyfile is required to unlock the account store",
+                ))
+            }
+            (false, Some(_)) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "The account store does not use a keyfile",
+                ))
+            }
+            _ => (),
+        }
+        match (&self.salt, &self.kdf, &self.hash) {
+            (Some(salt), Some(kdf), _) => derive_key(pin, salt, kdf, keyfile)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err)),
+            // Legacy stores used the start of the stored hash as the key
+            (_, _, Some(hash)) => Ok(Secret::new(hash.as_bytes()[..32].to_vec())),
+            _ => Err(Error::new(ErrorKind::InvalidData, "No pin found")),
+        }
+    }
 
-        assert_eq!(
-            store.get("pets.com"),
-            Some(&Account {
-                key: String::from("some-key"),
-                otp_type: OtpType::TOTP,
-            })
-        );
+    fn is_legacy(&self) -> bool {
+        self.kdf.is_none()
     }
+}
 
-    #[test]
-    fn deletes_an_account() {
-        let mut store = get_mock_store();
-        store.delete(ACCOUNT_NAME_1);
+pub struct AccountStore {
+    vault: Vault,
+    secrets: Secrets,
+    key: Option<SecretBytes>,
+    // Derived from the pin when it was given, which sharing the store needs to wrap a new key
+    pin_key: Option<SecretBytes>,
+    // Digest of the keyfile given to unlock the store or to set a new pin with
+    keyfile: Option<SecretBytes>,
+    // Digests of the records as last loaded or saved, by record id
+    record_digests: BTreeMap<String, String>,
+    backend: Box<dyn StorageBackend>,
+}
 
-        assert_eq!(store.get(ACCOUNT_NAME_1), None);
+pub trait AccountStoreOperations {
+    fn get(&self, key: &str) -> Option<&Account>;
+    fn list(&self) -> Vec<String>;
+    fn add(&mut self, account_name: String, account: Account);
+    fn get_mut(&mut self, account_name: &str) -> Option<&mut Account>;
+    fn delete(&mut self, account_name: &str, deleted_at: u64) -> bool;
+    fn list_trash(&self) -> &[TrashedAccount];
+    fn restore(&mut self, account_name: &str, new_name: &str) -> Result<()>;
+    fn purge(&mut self, deleted_before: Option<u64>) -> usize;
+    fn rename(&mut self, account_name: &str, new_name: &str) -> Result<()>;
+    fn is_initialized(&self) -> bool;
+    fn save(&mut self) -> Result<()>;
+    fn set_counter(&mut self, key: &str, counter: i32);
+    fn record_use(&mut self, account_name: &str, timestamp: u64);
+    fn set_pin(&mut self, pin: &str, kdf: KdfParams) -> Result<()>;
+    fn set_keyfile(&mut self, keyfile: Option<SecretBytes>);
+    fn has_keyfile(&self) -> bool;
+    fn get_kdf_params(&self) -> KdfParams;
+    fn get_pin_policy(&self) -> PinPolicy;
+    fn set_pin_policy(&mut self, policy: PinPolicy);
+    fn get_listing(&self) -> Listing;
+    fn set_listing(&mut self, listing: Listing);
+    fn list_names(&self) -> Result<Vec<String>>;
+    fn get_wipe_after(&self) -> Option<u32>;
+    fn set_wipe_after(&mut self, failures: Option<u32>);
+    fn get_unlock_delay(&self, timestamp: u64) -> u64;
+    fn record_failed_unlock(&mut self, timestamp: u64) -> Result<bool>;
+    fn reset_failed_unlocks(&mut self) -> Result<()>;
+    fn validate_pin(&self, pin: &str) -> bool;
+    fn unlock(&mut self, pin: &str) -> Result<()>;
+    fn unlock_with_key(&mut self, key: SecretBytes) -> Result<()>;
+    fn get_key(&self) -> Option<&SecretBytes>;
+    fn unlock_with_identity(&mut self, identity: &[u8]) -> Result<()>;
+    fn list_members(&self) -> &[Member];
+    fn add_member(&mut self, name: &str, public_key: &str) -> Result<()>;
+    fn remove_member(&mut self, name: &str) -> Result<()>;
+    fn audit(&mut self, event: AuditEvent) -> Result<()>;
+    fn read_audit_log(&self) -> Result<AuditReport>;
+}
+
+impl AccountStore {
+    pub fn new() -> Result<AccountStore> {
+        AccountStore::open(storage::open(&get_directory())?)
     }
 
-    #[test]
-    fn lists_accounts() {
-        let store = get_mock_store();
-        let accounts = store.list();
+    pub fn open(backend: Box<dyn StorageBackend>) -> Result<AccountStore> {
+        let secrets: Secrets = toml::from_str(&backend.load_secrets()?)?;
+
+        // Accounts stay encrypted in the backend until the store is unlocked with the pin
+        Ok(AccountStore {
+            vault: Vault::default(),
+            secrets,
+            key: None,
+            pin_key: None,
+            keyfile: None,
+            record_digests: BTreeMap::new(),
+            backend,
+        })
+    }
 
-        assert_eq!(accounts, vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]);
+    // A fresh key for the accounts, wrapped for the pin and every member still in the store
+    fn rekey(&mut self) -> Result<()> {
+        let pin_key = self.pin_key.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::PermissionDenied,
+                "Changing who can unlock the account store needs the pin, rather than an identity or the agent",
+            )
+        })?;
+        let mut key = generate_key();
+        key.lock();
+        let wrapped_key = WrappedKey::new(&key, pin_key)?;
+        let members = self
+            .secrets
+            .members
+            .iter()
+            .map(|member| {
+                let sealed_key = seal_key(key.expose(), &decode_public_key(&member.public_key)?)
+                    .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+                Ok(Member {
+                    sealed_key,
+                    ..member.clone()
+                })
+            })
+            .collect::<Result<Vec<Member>>>()?;
+        self.secrets.wrapped_key = Some(wrapped_key);
+        self.secrets.members_digest = digest_members(&members, key.expose());
+        self.secrets.members = members;
+        self.key = Some(key);
+        Ok(())
     }
 
-    #[test]
-    fn is_initialized_true() {
-        let store = get_mock_store();
+    // The members are kept in the clear so identities can find their key, but only someone
+    // with the store key can change them. Anyone else could add a key to be sealed for next.
+    fn check_members(&self) -> Result<()> {
+        let key = self
+            .key
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Account store is locked"))?;
+        match digest_members(&self.secrets.members, key.expose()) == self.secrets.members_digest {
+            true => Ok(()),
+            false => Err(Error::new(
+                ErrorKind::InvalidData,
+                "The members were changed outside of otp, restore the secrets from a backup",
+            )),
+        }
+    }
 
-        assert_eq!(store.is_initialized(), true);
+    // Rewrites the secrets next to the accounts already stored, which needs no key
+    fn commit_secrets(&mut self) -> Result<()> {
+        let secrets = toml::to_string(&self.secrets).map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Secrets serialization failure: {}", err),
+            )
+        })?;
+        let accounts = self.backend.load_accounts()?;
+        self.backend.commit(&secrets, &accounts)
     }
 
-    #[test]
-    fn is_initialized_false() {
+    // Checks what can be checked without the pin, without changing anything
+    pub fn inspect(backend: &dyn StorageBackend) -> Vec<Finding> {
+        let restore = "Restore the accounts and secrets together from the same backup";
+        let secrets = match backend.load_secrets() {
+            Ok(contents) => contents,
+            Err(err) => {
+                return vec![Finding::error(
+                    &format!("Unable to read the secrets: {}", err),
+                    restore,
+                )]
+            }
+        };
+        let secrets: Secrets = match toml::from_str(&secrets) {
+            Ok(secrets) => secrets,
+            Err(err) => {
+                return vec![Finding::error(
+                    &format!("The secrets are not valid: {}", err),
+                    restore,
+                )]
+            }
+        };
+        let mut findings = vec![Finding::ok("The secrets are valid TOML")];
+        if secrets.hash.is_none() {
+            findings.push(Finding::error(
+                "No pin is set",
+                "Run 'otp init' if this is a new account store, otherwise restore it from a backup",
+            ));
+            return findings;
+        }
+        match (&secrets.kdf, &secrets.salt) {
+            (None, _) => findings.push(Finding::warning(
+                "The key is taken from the pin hash instead of derived from the pin",
+                "Unlock the account store once to move it to a derived key",
+            )),
+            (Some(_), None) => findings.push(Finding::error(
+                "The key derivation settings have no salt",
+                restore,
+            )),
+            _ => (),
+        }
+
+        let accounts = match backend.load_accounts() {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                findings.push(Finding::error(
+                    &format!("Unable to read the accounts: {}", err),
+                    restore,
+                ));
+                return findings;
+            }
+        };
+        match (&secrets.nonce, accounts.len()) {
+            (_, 0) => findings.push(Finding::ok("No accounts have been saved yet")),
+            (None, _) => findings.push(Finding::error(
+                "The accounts are encrypted but the secrets hold no nonce",
+                restore,
+            )),
+            (Some(nonce), _) if nonce.len() != NONCE_LENGTH => findings.push(Finding::error(
+                &format!(
+                    "The nonce is {} bytes long instead of {}",
+                    nonce.len(),
+                    NONCE_LENGTH
+                ),
+                restore,
+            )),
+            (_, length) if length < TAG_LENGTH => findings.push(Finding::error(
+                "The accounts are too short to hold an authentication tag",
+                restore,
+            )),
+            _ => findings.push(Finding::ok("The nonce matches the encrypted accounts")),
+        }
+
+        match (secrets.listing, &secrets.index) {
+            (Listing::Index, None) => findings.push(Finding::warning(
+                "Account names can be listed without the pin, but there is no index",
+                "Save the account store once, for example with 'otp passwd --listing index'",
+            )),
+            (Listing::Index, Some(_)) => findings.push(Finding::ok(
+                "Account names are kept in the clear so they can be listed without the pin",
+            )),
+            _ => (),
+        }
+        findings
+    }
+}
+
+impl AccountStoreOperations for AccountStore {
+    fn get(&self, account_name: &str) -> Option<&Account> {
+        self.vault.accounts.get(account_name)
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.vault.accounts.keys().cloned().collect()
+    }
+
+    fn list_trash(&self) -> &[TrashedAccount] {
+        &self.vault.trash
+    }
+
+    fn add(&mut self, account_name: String, account: Account) {
+        self.vault.accounts.insert(account_name, account);
+    }
+
+    fn get_mut(&mut self, account_name: &str) -> Option<&mut Account> {
+        self.vault.accounts.get_mut(account_name)
+    }
+
+    // Deleted accounts are kept in the trash until they are purged
+    fn delete(&mut self, account_name: &str, deleted_at: u64) -> bool {
+        match self.vault.accounts.remove(account_name) {
+            Some(account) => {
+                self.vault.trash.push(TrashedAccount {
+                    name: String::from(account_name),
+                    deleted_at,
+                    account,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Restores the most recently deleted account with the name
+    fn restore(&mut self, account_name: &str, new_name: &str) -> Result<()> {
+        if self.vault.accounts.contains_key(new_name) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Account already exists: {}", new_name),
+            ));
+        }
+        match self
+            .vault
+            .trash
+            .iter()
+            .rposition(|trashed| trashed.name == account_name)
+        {
+            Some(index) => {
+                let trashed = self.vault.trash.remove(index);
+                self.vault
+                    .accounts
+                    .insert(String::from(new_name), trashed.account);
+                Ok(())
+            }
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Account not found in the trash: {}", account_name),
+            )),
+        }
+    }
+
+    fn purge(&mut self, deleted_before: Option<u64>) -> usize {
+        let count = self.vault.trash.len();
+        match deleted_before {
+            Some(timestamp) => self
+                .vault
+                .trash
+                .retain(|trashed| trashed.deleted_at >= timestamp),
+            None => self.vault.trash.clear(),
+        }
+        count - self.vault.trash.len()
+    }
+
+    fn rename(&mut self, account_name: &str, new_name: &str) -> Result<()> {
+        if self.vault.accounts.contains_key(new_name) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Account already exists: {}", new_name),
+            ));
+        }
+        match self.vault.accounts.remove(account_name) {
+            Some(account) => {
+                self.vault.accounts.insert(String::from(new_name), account);
+                Ok(())
+            }
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Account not found: {}", account_name),
+            )),
+        }
+    }
+
+    fn set_counter(&mut self, account_name: &str, counter: i32) {
+        let account = self.vault.accounts.get_mut(account_name);
+        match account {
+            Some(account) => account.otp_type = OtpType::HOTP(Some(counter)),
+            None => println!("Account not found: {}", account_name),
+        }
+    }
+
+    fn record_use(&mut self, account_name: &str, timestamp: u64) {
+        if let Some(account) = self.vault.accounts.get_mut(account_name) {
+            account.last_used_at = Some(timestamp);
+            account.use_count += 1;
+        }
+    }
+
+    fn set_pin(&mut self, pin: &str, kdf: KdfParams) -> Result<()> {
+        let (secrets, mut pin_key) = Secrets::new(
+            pin,
+            kdf,
+            self.secrets.policy.clone(),
+            self.keyfile
+                .as_ref()
+                .map(|keyfile| keyfile.expose().as_slice()),
+        )?;
+        pin_key.lock();
+        // A shared store keeps its key and members, only the wrapping for the pin changes
+        let (key, wrapped_key) = match (&self.secrets.wrapped_key, &self.key) {
+            (Some(_), Some(key)) => (key.clone(), Some(WrappedKey::new(key, &pin_key)?)),
+            (Some(_), None) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Account store is locked",
+                ))
+            }
+            (None, _) => (pin_key.clone(), None),
+        };
+        self.secrets = Secrets {
+            listing: self.secrets.listing,
+            wipe_after: self.secrets.wipe_after,
+            wrapped_key,
+            members_digest: self.secrets.members_digest.clone(),
+            members: self.secrets.members.clone(),
+            ..secrets
+        };
+        self.key = Some(key);
+        self.pin_key = Some(pin_key);
+        // New stores get their audit key now so it is written with the first save
+        get_audit_key(&mut self.vault)?;
+        Ok(())
+    }
+
+    fn set_keyfile(&mut self, keyfile: Option<SecretBytes>) {
+        self.keyfile = keyfile;
+    }
+
+    fn has_keyfile(&self) -> bool {
+        self.secrets.keyfile
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.secrets.hash.is_some()
+    }
+
+    fn get_kdf_params(&self) -> KdfParams {
+        self.secrets.kdf.clone().unwrap_or_default()
+    }
+
+    fn get_pin_policy(&self) -> PinPolicy {
+        self.secrets.policy.clone().unwrap_or_default()
+    }
+
+    fn set_pin_policy(&mut self, policy: PinPolicy) {
+        self.secrets.policy = Some(policy);
+    }
+
+    fn get_wipe_after(&self) -> Option<u32> {
+        self.secrets.wipe_after
+    }
+
+    fn set_wipe_after(&mut self, failures: Option<u32>) {
+        self.secrets.wipe_after = failures;
+    }
+
+    // Seconds left before the pin may be tried again
+    fn get_unlock_delay(&self, timestamp: u64) -> u64 {
+        match self.secrets.last_failed_unlock {
+            Some(last) => {
+                (last + unlock_delay(self.secrets.failed_unlocks)).saturating_sub(timestamp)
+            }
+            None => 0,
+        }
+    }
+
+    // Returns whether this failure used up the last attempt and the vault was destroyed
+    fn record_failed_unlock(&mut self, timestamp: u64) -> Result<bool> {
+        self.secrets.failed_unlocks += 1;
+        self.secrets.last_failed_unlock = Some(timestamp);
+        let failures = self.secrets.failed_unlocks;
+        if self
+            .secrets
+            .wipe_after
+            .is_some_and(|limit| failures >= limit)
+        {
+            self.backend.destroy()?;
+            self.vault = Vault::default();
+            self.secrets = Secrets::default();
+            self.key = None;
+            self.pin_key = None;
+            self.record_digests.clear();
+            return Ok(true);
+        }
+        self.commit_secrets()?;
+        Ok(false)
+    }
+
+    fn reset_failed_unlocks(&mut self) -> Result<()> {
+        if self.secrets.failed_unlocks == 0 {
+            return Ok(());
+        }
+        self.secrets.failed_unlocks = 0;
+        self.secrets.last_failed_unlock = None;
+        self.commit_secrets()
+    }
+
+    fn get_listing(&self) -> Listing {
+        self.secrets.listing
+    }
+
+    fn set_listing(&mut self, listing: Listing) {
+        self.secrets.listing = listing;
+    }
+
+    // The names of a locked store come from its index, when it keeps one
+    fn list_names(&self) -> Result<Vec<String>> {
+        if self.key.is_some() {
+            return Ok(self.list());
+        }
+        match (&self.secrets.listing, &self.secrets.index) {
+            (Listing::Index, Some(index)) => Ok(index.names.clone()),
+            _ => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Listing accounts requires unlocking this account store",
+            )),
+        }
+    }
+
+    // Without the keyfile the hash can't tell a right pin from a wrong one
+    fn validate_pin(&self, pin: &str) -> bool {
+        let stored_pin = match self.secrets.hash.clone() {
+            Some(pin) => pin,
+            None => return false,
+        };
+        let keyfile = self
+            .keyfile
+            .as_ref()
+            .map(|keyfile| keyfile.expose().as_slice());
+        self.secrets.keyfile == keyfile.is_some() && decrypt_pw(&stored_pin, pin, keyfile)
+    }
+
+    fn unlock(&mut self, pin: &str) -> Result<()> {
+        let keyfile = self
+            .keyfile
+            .as_ref()
+            .map(|keyfile| keyfile.expose().as_slice());
+        let mut pin_key = self.secrets.derive_key(pin, keyfile)?;
+        pin_key.lock();
+        let key = match &self.secrets.wrapped_key {
+            Some(wrapped_key) => {
+                wrapped_key
+                    .open(&pin_key)
+                    .map_err(|err| match self.secrets.keyfile {
+                        true => Error::new(err.kind(), format!("{} with this keyfile", err)),
+                        false => err,
+                    })?
+            }
+            None => pin_key.clone(),
+        };
+        self.unlock_with_key(key)?;
+        self.pin_key = Some(pin_key);
+
+        // Legacy stores are keyed with the start of the pin hash stored beside them, so anyone
+        // could read them. They move to a pin-derived key and are written again at once.
+        if self.secrets.is_legacy() {
+            self.set_pin(pin, KdfParams::default())?;
+            if !self.backend.is_read_only() {
+                self.save()?;
+            }
+        }
+        Ok(())
+    }
+
+    // For a key that was derived earlier, such as the one held by the agent
+    fn unlock_with_key(&mut self, mut key: SecretBytes) -> Result<()> {
+        let encrypted_accounts = match self.backend.load_accounts() {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Oh no! Couldn't load the accounts",
+                ));
+            }
+        };
+        key.lock();
+        let account_contents = decrypt_accounts(&encrypted_accounts, key.expose(), &self.secrets)
+            .map_err(|err| match self.secrets.keyfile {
+            // The pin was already checked, so the keyfile is what's wrong
+            true => Error::new(err.kind(), format!("{} with this keyfile", err)),
+            false => err,
+        })?;
+
+        // Older schemas are copied aside before they are migrated and written back. A read-only
+        // store is only migrated in memory, which shows that it still can be.
+        let version = get_version(account_contents.expose())?;
+        let migrating = version < SCHEMA_VERSION && !self.backend.is_read_only();
+        let backup = match migrating {
+            true => Some(self.backend.backup(&format!("schema-{}", version))?),
+            false => None,
+        };
+        // The copy is kept in case the upgrade goes wrong, but one of a legacy store can be read
+        // without the pin, so it is for the user to remove
+        match (&backup, self.secrets.is_legacy()) {
+            (Some(backup), false) => eprintln!(
+                "Upgrading the account store from schema version {} to {}, a backup was saved to {}",
+                version, SCHEMA_VERSION, backup
+            ),
+            (Some(backup), true) => eprintln!(
+                "Upgrading the account store from schema version {} to {}, a backup was saved to {}. \
+                 It can be read without the pin, remove it once the upgraded store works",
+                version, SCHEMA_VERSION, backup
+            ),
+            (None, _) => (),
+        }
+        let account_contents = match version < SCHEMA_VERSION {
+            true => migrate(account_contents.expose(), version)?,
+            false => account_contents,
+        };
+        let mut vault = deserialize_accounts(account_contents.expose())?;
+        let mut record_digests = BTreeMap::new();
+        for record in self.backend.load_records()? {
+            let (accounts, digest) = decrypt_record(&record, key.expose())?;
+            vault.accounts.extend(accounts);
+            record_digests.insert(record.id, digest);
+        }
+        self.vault = vault;
+        self.record_digests = record_digests;
+        self.key = Some(key);
+        if backup.is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn get_key(&self) -> Option<&SecretBytes> {
+        self.key.as_ref()
+    }
+
+    fn unlock_with_identity(&mut self, identity: &[u8]) -> Result<()> {
+        let public_key =
+            get_public_key(identity).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+        let public_key = encode_public_key(&public_key);
+        let member = self
+            .secrets
+            .members
+            .iter()
+            .find(|member| member.public_key == public_key)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::PermissionDenied,
+                    "This identity is not a member of the account store",
+                )
+            })?;
+        let key = open_sealed_key(&member.sealed_key, identity)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Decryption failed"))?;
+        self.unlock_with_key(key)
+    }
+
+    fn list_members(&self) -> &[Member] {
+        &self.secrets.members
+    }
+
+    fn add_member(&mut self, name: &str, public_key: &str) -> Result<()> {
+        self.check_members()?;
+        if self
+            .secrets
+            .members
+            .iter()
+            .any(|member| member.name == name)
+        {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Member already exists: {}", name),
+            ));
+        }
+        let decoded = decode_public_key(public_key)?;
+        let public_key = encode_public_key(&decoded);
+        if let Some(member) = self
+            .secrets
+            .members
+            .iter()
+            .find(|member| member.public_key == public_key)
+        {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("The key is already used by member \"{}\"", member.name),
+            ));
+        }
+        // The key derived from the pin is never handed out, so sharing starts with a new one
+        if self.secrets.wrapped_key.is_none() {
+            self.rekey()?;
+        }
+        let key = self
+            .key
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Account store is locked"))?;
+        let sealed_key = seal_key(key.expose(), &decoded)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+        self.secrets.members.push(Member {
+            name: String::from(name),
+            public_key,
+            sealed_key,
+        });
+        self.secrets.members_digest = digest_members(&self.secrets.members, key.expose());
+        Ok(())
+    }
+
+    // A member who kept a copy of the store can still open it, but not anything saved after this
+    fn remove_member(&mut self, name: &str) -> Result<()> {
+        self.check_members()?;
+        let index = self
+            .secrets
+            .members
+            .iter()
+            .position(|member| member.name == name)
+            .ok_or_else(|| {
+                Error::new(ErrorKind::NotFound, format!("Member not found: {}", name))
+            })?;
+        let member = self.secrets.members.remove(index);
+        self.rekey()
+            .inspect_err(|_| self.secrets.members.insert(index, member))?;
+        // The member may have kept the audit key, so entries from now on are sealed with another
+        rotate_audit_key(&mut self.vault);
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<()> {
+        // Encrypt and serialize accounts
+        self.vault.version = SCHEMA_VERSION;
+        // Backends with a record per account keep the accounts out of the blob
+        let stores_records = self.backend.stores_records();
+        let accounts = match stores_records {
+            true => std::mem::take(&mut self.vault.accounts),
+            false => BTreeMap::new(),
+        };
+        let serialized = toml::to_string(&self.vault);
+        self.vault.accounts.extend(accounts);
+        let account_contents = match serialized {
+            Ok(content) => Secret::new(content),
+            Err(err) => {
+                println!("Oh no! Couldn't save the accounts: {}", err);
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Account serialization failure",
+                ));
+            }
+        };
+        let key = match &self.key {
+            Some(key) => key,
+            None => {
+                println!("No password found");
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Account store is locked",
+                ));
+            }
+        };
+        let (encrypted_content, nonce) =
+            match encrypt_string(account_contents.expose(), key.expose()) {
+                Ok(result) => result,
+                Err(err) => {
+                    println!("Oh no! Couldn't save the accounts {}", err);
+                    return Err(Error::new(ErrorKind::InvalidData, "Encryption failure"));
+                }
+            };
+
+        let index = match self.secrets.listing {
+            Listing::Index => Some(NameIndex { names: self.list() }),
+            Listing::Unlock => None,
+        };
+
+        // Serialize secrets
+        let secrets = Secrets {
+            nonce: Some(nonce),
+            index,
+            ..self.secrets.clone()
+        };
+
+        let secrets_content = match toml::to_string(&secrets) {
+            Ok(content) => content,
+            Err(err) => {
+                println!("Oh no! Couldn't save {}", err);
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Secrets serialization failure",
+                ));
+            }
+        };
+
+        // Save both together so the accounts are never paired with stale secrets
+        match stores_records {
+            true => {
+                let (changed, removed, digests) =
+                    changed_records(&self.vault.accounts, key.expose(), &self.record_digests)?;
+                self.backend.commit_records(
+                    &secrets_content,
+                    &encrypted_content,
+                    &changed,
+                    &removed,
+                )?;
+                self.record_digests = digests;
+            }
+            false => self.backend.commit(&secrets_content, &encrypted_content)?,
+        }
+        self.secrets = secrets;
+        Ok(())
+    }
+
+    fn audit(&mut self, event: AuditEvent) -> Result<()> {
+        if self.key.is_none() {
+            return self
+                .backend
+                .append_pending_audit(&format_pending_event(&event));
+        }
+
+        // Stores from before the audit log get their key on the first event
+        let (key, created) = get_audit_key(&mut self.vault)?;
+        if created {
+            self.save()?;
+        }
+        let mut events = parse_pending_events(&self.backend.load_pending_audit()?);
+        events.push(event);
+
+        let mut log = self.backend.load_audit_log()?;
+        let lines = append_events(&mut log, key.expose(), events)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        self.backend.append_audit_log(&lines)?;
+        self.backend.clear_pending_audit()
+    }
+
+    fn read_audit_log(&self) -> Result<AuditReport> {
+        if self.key.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Account store is locked",
+            ));
+        }
+        read_audit(&self.vault, &self.backend.load_audit_log()?)
+    }
+}
+
+// Stands in for a backend whose disk or database has gone away
+#[cfg(test)]
+struct FailingBackend(Box<dyn StorageBackend>);
+
+#[cfg(test)]
+impl StorageBackend for FailingBackend {
+    fn load_secrets(&self) -> Result<String> {
+        self.0.load_secrets()
+    }
+
+    fn load_accounts(&self) -> Result<Vec<u8>> {
+        self.0.load_accounts()
+    }
+
+    fn commit(&mut self, _secrets: &str, _accounts: &[u8]) -> Result<()> {
+        Err(Error::other("Storage backend failed to save"))
+    }
+
+    fn stores_records(&self) -> bool {
+        self.0.stores_records()
+    }
+
+    fn load_records(&self) -> Result<Vec<Record>> {
+        self.0.load_records()
+    }
+
+    fn commit_records(
+        &mut self,
+        _secrets: &str,
+        _accounts: &[u8],
+        _changed: &[Record],
+        _removed: &[String],
+    ) -> Result<()> {
+        Err(Error::other("Storage backend failed to save"))
+    }
+
+    fn load_audit_log(&self) -> Result<String> {
+        self.0.load_audit_log()
+    }
+
+    fn append_audit_log(&mut self, lines: &str) -> Result<()> {
+        self.0.append_audit_log(lines)
+    }
+
+    fn load_pending_audit(&self) -> Result<String> {
+        self.0.load_pending_audit()
+    }
+
+    fn append_pending_audit(&mut self, lines: &str) -> Result<()> {
+        self.0.append_pending_audit(lines)
+    }
+
+    fn clear_pending_audit(&mut self) -> Result<()> {
+        self.0.clear_pending_audit()
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        self.0.destroy()
+    }
+
+    fn paths(&self) -> Vec<std::path::PathBuf> {
+        self.0.paths()
+    }
+
+    fn backup(&mut self, name: &str) -> Result<String> {
+        self.0.backup(name)
+    }
+}
+
+#[cfg(test)]
+impl AccountStore {
+    pub fn fail_saves(&mut self) {
+        let backend = std::mem::replace(&mut self.backend, Box::new(storage::MemoryBackend::new()));
+        self.backend = Box::new(FailingBackend(backend));
+    }
+
+    // The same store as another process would find it, before unlocking
+    pub fn reopen(self) -> AccountStore {
+        AccountStore::open(self.backend).unwrap()
+    }
+
+    pub fn into_backend(self) -> Box<dyn StorageBackend> {
+        self.backend
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::crypto::{hash_keyfile, KdfVariant};
+    use crate::hotp::{get_code, get_hotp};
+    use crate::policy::PinMode;
+    use crate::storage::MemoryBackend;
+    use crate::tests::constants::{
+        test_kdf_params, ACCOUNT_NAME_1, ACCOUNT_NAME_2, ACCOUNT_NAME_3, NEW_PIN, PIN,
+    };
+    use crate::totp::get_totp;
+
+    pub fn create_empty_store() -> AccountStore {
+        AccountStore::open(Box::new(MemoryBackend::new())).unwrap()
+    }
+
+    pub fn get_mock_store() -> AccountStore {
+        let mut store = create_empty_store();
+        store
+            .set_pin(PIN, test_kdf_params())
+            .expect("Failed to encrypt pin");
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from("key-1"), OtpType::HOTP(Some(0))),
+        );
+        store.add(
+            String::from(ACCOUNT_NAME_2),
+            Account::new(String::from("key-2"), OtpType::TOTP),
+        );
+        store.save().expect("Failed to save");
+        store
+    }
+
+    #[test]
+    fn adds_an_account() {
+        let mut store = get_mock_store();
+        let account = Account::new(String::from("some-key"), OtpType::TOTP);
+        store.add(String::from("pets.com"), account);
+
+        assert_eq!(
+            store.get("pets.com"),
+            Some(&Account::new(String::from("some-key"), OtpType::TOTP))
+        );
+    }
+
+    #[test]
+    fn deletes_an_account_into_the_trash() {
+        let mut store = get_mock_store();
+        assert!(store.delete(ACCOUNT_NAME_1, 90));
+        assert!(!store.delete(ACCOUNT_NAME_1, 90));
+
+        assert_eq!(store.get(ACCOUNT_NAME_1), None);
+        assert_eq!(
+            store.list_trash(),
+            &[TrashedAccount {
+                name: String::from(ACCOUNT_NAME_1),
+                deleted_at: 90,
+                account: Account::new(String::from("key-1"), OtpType::HOTP(Some(0))),
+            }]
+        );
+    }
+
+    #[test]
+    fn restores_the_most_recently_deleted_account() {
+        let mut store = get_mock_store();
+        store.delete(ACCOUNT_NAME_1, 90);
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from("key-3"), OtpType::TOTP),
+        );
+        store.delete(ACCOUNT_NAME_1, 120);
+
+        store.restore(ACCOUNT_NAME_1, ACCOUNT_NAME_1).unwrap();
+
+        assert_eq!(store.get(ACCOUNT_NAME_1).unwrap().key.expose(), "key-3");
+        assert_eq!(store.list_trash().len(), 1);
+        assert_eq!(store.list_trash()[0].deleted_at, 90);
+    }
+
+    #[test]
+    fn restores_under_a_new_name() {
+        let mut store = get_mock_store();
+        store.delete(ACCOUNT_NAME_1, 90);
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from("key-3"), OtpType::TOTP),
+        );
+
+        let err = store.restore(ACCOUNT_NAME_1, ACCOUNT_NAME_1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+        store.restore(ACCOUNT_NAME_1, ACCOUNT_NAME_3).unwrap();
+        assert_eq!(store.get(ACCOUNT_NAME_3).unwrap().key.expose(), "key-1");
+        assert!(store.list_trash().is_empty());
+    }
+
+    #[test]
+    fn does_not_restore_accounts_missing_from_the_trash() {
+        let mut store = get_mock_store();
+        let err = store.restore(ACCOUNT_NAME_3, ACCOUNT_NAME_3).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn purges_the_trash() {
+        let mut store = get_mock_store();
+        store.delete(ACCOUNT_NAME_1, 90);
+        store.delete(ACCOUNT_NAME_2, 120);
+
+        assert_eq!(store.purge(Some(100)), 1);
+        assert_eq!(store.list_trash()[0].name, ACCOUNT_NAME_2);
+        assert_eq!(store.purge(None), 1);
+        assert!(store.list_trash().is_empty());
+    }
+
+    #[test]
+    fn lists_accounts() {
+        let store = get_mock_store();
+        let accounts = store.list();
+
+        assert_eq!(accounts, vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]);
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn is_initialized_true() {
+        let store = get_mock_store();
+
+        assert_eq!(store.is_initialized(), true);
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn is_initialized_false() {
         let store = create_empty_store();
 
-        assert_eq!(store.is_initialized(), false);
+        assert_eq!(store.is_initialized(), false);
+    }
+
+    #[test]
+    fn sets_counter_value() {
+        let mut store = get_mock_store();
+        store.set_counter(ACCOUNT_NAME_1, 101);
+
+        assert_eq!(
+            store.get(ACCOUNT_NAME_1),
+            Some(&Account::new(
+                String::from("key-1"),
+                OtpType::HOTP(Some(101))
+            ))
+        );
+    }
+
+    #[test]
+    fn renames_an_account() {
+        let mut store = get_mock_store();
+        store.rename(ACCOUNT_NAME_1, ACCOUNT_NAME_3).unwrap();
+
+        assert_eq!(store.get(ACCOUNT_NAME_1), None);
+        assert_eq!(
+            store.get(ACCOUNT_NAME_3),
+            Some(&Account::new(String::from("key-1"), OtpType::HOTP(Some(0))))
+        );
+    }
+
+    #[test]
+    fn does_not_rename_over_an_existing_account() {
+        let mut store = get_mock_store();
+        let err = store.rename(ACCOUNT_NAME_1, ACCOUNT_NAME_2).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(store.get(ACCOUNT_NAME_1).unwrap().key.expose(), "key-1");
+        assert_eq!(store.get(ACCOUNT_NAME_2).unwrap().key.expose(), "key-2");
+    }
+
+    #[test]
+    fn does_not_rename_a_missing_account() {
+        let mut store = get_mock_store();
+        let err = store.rename("not_an_account", ACCOUNT_NAME_3).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(store.get(ACCOUNT_NAME_3), None);
+    }
+
+    #[test]
+    fn edits_an_account_in_place() {
+        let mut store = get_mock_store();
+        let account = store.get_mut(ACCOUNT_NAME_2).unwrap();
+        account.digits = Some(8);
+        account.otp_type = OtpType::HOTP(Some(5));
+
+        let account = store.get(ACCOUNT_NAME_2).unwrap();
+        assert_eq!(account.get_digits(), 8);
+        assert_eq!(account.otp_type, OtpType::HOTP(Some(5)));
+        assert_eq!(account.key.expose(), "key-2");
+    }
+
+    #[test]
+    fn defaults_code_settings_by_otp_type() {
+        let hotp = Account::new(String::from("key-1"), OtpType::HOTP(Some(0)));
+        assert_eq!(hotp.get_algorithm(), Algorithm::SHA1);
+        assert_eq!(hotp.get_digits(), 6);
+
+        let mut totp = Account::new(String::from("key-2"), OtpType::TOTP);
+        assert_eq!(totp.get_algorithm(), Algorithm::SHA256);
+        assert_eq!(totp.get_period(), 30);
+
+        totp.algorithm = Some(Algorithm::SHA512);
+        assert_eq!(totp.get_algorithm(), Algorithm::SHA512);
+    }
+
+    #[test]
+    fn builds_a_key_uri_for_hotp() {
+        let mut hotp = Account::new(String::from("abcd"), OtpType::HOTP(Some(7)));
+        hotp.digits = Some(8);
+
+        assert_eq!(
+            hotp.get_uri("my vpn"),
+            "otpauth://hotp/my%20vpn?secret=MFRGGZA&algorithm=SHA1&digits=8&counter=7"
+        );
+    }
+
+    fn get_uri_params(uri: &str) -> BTreeMap<&str, &str> {
+        let (_, query) = uri.split_once('?').unwrap();
+        query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .collect()
+    }
+
+    // An RFC 4226 or 6238 implementation, as in an authenticator app, fed the secret, algorithm,
+    // digits and moving factor of the URI
+    fn get_app_code(uri: &str, time: u64) -> u32 {
+        let params = get_uri_params(uri);
+        let secret = BASE32_NOPAD.decode(params["secret"].as_bytes()).unwrap();
+        let algorithm = Algorithm::from_str(&params["algorithm"].to_lowercase()).unwrap();
+        let digits = params["digits"].parse().unwrap();
+        let moving_factor = match params.get("period") {
+            Some(period) => time / period.parse::<u64>().unwrap(),
+            None => params["counter"].parse().unwrap(),
+        };
+        get_code(
+            &secret,
+            algorithm,
+            &moving_factor.to_be_bytes(),
+            digits,
+            Codes::Rfc,
+        )
+    }
+
+    #[test]
+    fn gives_the_codes_of_its_uri_once_rotated() {
+        let time = 1234567890;
+        for algorithm in [Algorithm::SHA1, Algorithm::SHA256, Algorithm::SHA512] {
+            let mut totp = Account::new(String::from("key-1"), OtpType::TOTP);
+            totp.rotate_key(generate_secret_32(), 100);
+            totp.algorithm = Some(algorithm);
+            totp.digits = Some(8);
+            let code = get_app_code(&totp.get_uri("vpn"), time);
+            assert_eq!(get_totp(&totp, time / 30), code);
+        }
+
+        let mut hotp = Account::new(String::from("key-1"), OtpType::HOTP(Some(5)));
+        hotp.rotate_key(generate_secret_32(), 100);
+        let code = get_app_code(&hotp.get_uri("vpn"), 0);
+        assert_eq!(get_hotp(&hotp, 5), code);
+    }
+
+    #[test]
+    fn keeps_the_settings_of_a_rotated_account() {
+        let mut account = Account::new(String::from("key-1"), OtpType::HOTP(Some(3)));
+        account.digits = Some(8);
+        account.rotate_key(String::from("key-2"), 100);
+
+        let previous = account.get_previous(99).unwrap();
+        assert_eq!(account.key.expose(), "key-2");
+        assert_eq!(account.get_codes(), Codes::Rfc);
+        assert_eq!(previous.key.expose(), "key-1");
+        assert_eq!(previous.otp_type, OtpType::HOTP(Some(3)));
+        assert_eq!(previous.get_digits(), 8);
+        assert_eq!(previous.get_codes(), Codes::Legacy);
+        assert!(account.get_previous(100).is_none());
+    }
+
+    #[test]
+    fn adds_recovery_codes_once() {
+        let mut account = Account::new(String::from("key-1"), OtpType::TOTP);
+
+        assert_eq!(account.add_recovery_codes(&["aaaa", "bbbb"]), 2);
+        assert_eq!(account.add_recovery_codes(&["bbbb", "cccc"]), 1);
+        assert_eq!(account.recovery_codes.len(), 3);
+        assert_eq!(account.remaining_recovery_codes(), 3);
+    }
+
+    #[test]
+    fn uses_recovery_codes() {
+        let mut account = Account::new(String::from("key-1"), OtpType::TOTP);
+        account.add_recovery_codes(&["aaaa", "bbbb", "cccc"]);
+
+        let used = account.use_recovery_code(Some("bbbb"), 60).unwrap();
+        assert_eq!(used.expose(), "bbbb");
+        let used = account.use_recovery_code(None, 90).unwrap();
+        assert_eq!(used.expose(), "aaaa");
+
+        assert_eq!(account.recovery_codes[0].used_at, Some(90));
+        assert_eq!(account.recovery_codes[1].used_at, Some(60));
+        assert_eq!(account.recovery_codes[2].used_at, None);
+        assert_eq!(account.remaining_recovery_codes(), 1);
+    }
+
+    #[test]
+    fn does_not_reuse_recovery_codes() {
+        let mut account = Account::new(String::from("key-1"), OtpType::TOTP);
+        account.add_recovery_codes(&["aaaa"]);
+        account.use_recovery_code(Some("aaaa"), 60).unwrap();
+
+        let err = account.use_recovery_code(Some("aaaa"), 90).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Recovery code was already used on 1970-01-01 00:01:00 UTC"
+        );
+        let err = account.use_recovery_code(None, 90).unwrap_err();
+        assert_eq!(err.to_string(), "No recovery codes left");
+        let err = account.use_recovery_code(Some("bbbb"), 90).unwrap_err();
+        assert_eq!(err.to_string(), "Recovery code not found");
+        assert_eq!(account.recovery_codes[0].used_at, Some(60));
+    }
+
+    #[test]
+    fn adds_and_removes_tags() {
+        let mut account = Account::new(String::from("key-1"), OtpType::TOTP);
+        account.add_tags(&["prod", "billing", "prod"]);
+        assert_eq!(account.tags, vec!["billing", "prod"]);
+
+        account.remove_tags(&["prod", "personal"]);
+        assert_eq!(account.tags, vec!["billing"]);
     }
 
     #[test]
-    fn sets_counter_value() {
+    fn records_account_use() {
         let mut store = get_mock_store();
-        store.set_counter(ACCOUNT_NAME_1, 101);
+        store.record_use(ACCOUNT_NAME_2, 90);
+        store.record_use(ACCOUNT_NAME_2, 120);
+
+        let account = store.get(ACCOUNT_NAME_2).unwrap();
+        assert_eq!(account.last_used_at, Some(120));
+        assert_eq!(account.use_count, 2);
+    }
+
+    #[test]
+    fn serializes_and_deserializes_accounts() {
+        let mut account = Account::new(String::from("key-1"), OtpType::HOTP(Some(3)));
+        account.issuer = Some(String::from("Pets"));
+        account.created_at = Some(90);
+        account.algorithm = Some(Algorithm::SHA512);
+        account.digits = Some(8);
+        account.add_tags(&["prod"]);
+        account.add_recovery_codes(&["aaaa-bbbb", "cccc-dddd"]);
+        account.use_recovery_code(None, 100).unwrap();
+        let mut vault = Vault::default();
+        vault.accounts.insert(String::from(ACCOUNT_NAME_1), account);
+        vault.accounts.insert(
+            String::from(ACCOUNT_NAME_2),
+            Account::new(String::from("key-2"), OtpType::TOTP),
+        );
+        vault.trash.push(TrashedAccount {
+            name: String::from(ACCOUNT_NAME_3),
+            deleted_at: 120,
+            account: Account::new(String::from("key-3"), OtpType::HOTP(Some(7))),
+        });
+
+        let serialized = Secret::new(toml::to_string(&vault).unwrap());
+        let deserialized = deserialize_accounts(serialized.expose()).unwrap();
+
+        assert_eq!(deserialized, vault);
+    }
+
+    // otp_type as the baseline wrote it, internally tagged
+    #[allow(clippy::upper_case_acronyms)]
+    #[derive(Serialize)]
+    #[serde(tag = "type")]
+    enum BaselineOtpType {
+        HOTP(Option<i32>),
+        TOTP,
+    }
 
+    #[derive(Serialize)]
+    struct BaselineAccount {
+        key: String,
+        otp_type: BaselineOtpType,
+    }
+
+    #[test]
+    fn round_trips_the_baseline_otp_type_format() {
+        let baseline = toml::to_string(&BaselineAccount {
+            key: String::from("key-1"),
+            otp_type: BaselineOtpType::TOTP,
+        })
+        .unwrap();
+        assert_eq!(baseline, "key = \"key-1\"\n\n[otp_type]\ntype = \"TOTP\"\n");
+
+        let account: Account = toml::from_str(&baseline).unwrap();
+        assert_eq!(account, Account::new(String::from("key-1"), OtpType::TOTP));
+        assert!(toml::to_string(&account)
+            .unwrap()
+            .ends_with("[otp_type]\ntype = \"TOTP\"\n"));
+
+        // The baseline could never write an HOTP account, so no store holds one in its format
+        assert!(toml::to_string(&BaselineAccount {
+            key: String::from("key-1"),
+            otp_type: BaselineOtpType::HOTP(Some(4)),
+        })
+        .is_err());
+        let account = Account::new(String::from("key-1"), OtpType::HOTP(Some(4)));
+        let serialized = toml::to_string(&account).unwrap();
+        assert_eq!(toml::from_str::<Account>(&serialized).unwrap(), account);
+    }
+
+    #[test]
+    fn serializes_a_vault_with_an_empty_trash() {
+        let mut vault = Vault::default();
+        vault.accounts.insert(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from("key-1"), OtpType::TOTP),
+        );
+
+        let serialized = Secret::new(toml::to_string(&vault).unwrap());
+        let deserialized = deserialize_accounts(serialized.expose()).unwrap();
+
+        assert_eq!(deserialized, vault);
         assert_eq!(
-            store.get(ACCOUNT_NAME_1),
-            Some(&Account {
-                key: String::from("key-1"),
-                otp_type: OtpType::HOTP(Some(101)),
-            })
+            toml::to_string(&Vault::default()).unwrap(),
+            "version = 0\n\n[accounts]\n"
+        );
+    }
+
+    #[test]
+    fn migrates_accounts_stored_before_the_trash() {
+        let mut accounts = BTreeMap::new();
+        accounts.insert(
+            String::from("accounts"),
+            Account::new(String::from("key-1"), OtpType::TOTP),
+        );
+        let serialized = toml::to_string(&accounts).unwrap();
+
+        let migrated = migrate(&serialized, get_version(&serialized).unwrap()).unwrap();
+        let vault = deserialize_accounts(migrated.expose()).unwrap();
+
+        assert_eq!(vault.accounts, accounts);
+        assert!(vault.trash.is_empty());
+        assert_eq!(vault.version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn migrates_accounts_without_metadata() {
+        let contents = "[pets]\nkey = \"key-1\"\n\n[pets.otp_type]\ntype = \"TOTP\"\n";
+        let migrated = migrate(contents, 0).unwrap();
+        let vault = deserialize_accounts(migrated.expose()).unwrap();
+
+        assert_eq!(
+            vault.accounts.get("pets"),
+            Some(&Account::new(String::from("key-1"), OtpType::TOTP))
+        );
+    }
+
+    // A store holding accounts as an older version of otp wrote them, before unlocking
+    fn store_with_contents(backend: Box<dyn StorageBackend>, contents: &str) -> AccountStore {
+        let mut store = AccountStore::open(backend).unwrap();
+        store.set_pin(PIN, test_kdf_params()).unwrap();
+        let (encrypted, nonce) =
+            encrypt_string(contents, store.key.as_ref().unwrap().expose()).unwrap();
+        store.secrets.nonce = Some(nonce);
+        let secrets = toml::to_string(&store.secrets).unwrap();
+        store.backend.commit(&secrets, &encrypted).unwrap();
+        store.reopen()
+    }
+
+    fn stored_version(store: &AccountStore) -> u32 {
+        let encrypted = store.backend.load_accounts().unwrap();
+        let contents = decrypt_accounts(
+            &encrypted,
+            store.key.as_ref().unwrap().expose(),
+            &store.secrets,
+        )
+        .unwrap();
+        get_version(contents.expose()).unwrap()
+    }
+
+    #[test]
+    fn loads_schema_0_fixture() {
+        let mut store = store_with_contents(
+            Box::new(MemoryBackend::new()),
+            include_str!("tests/fixtures/schema-0.toml"),
+        );
+
+        store.unlock(PIN).unwrap();
+
+        assert_eq!(store.list(), vec!["bank", "github"]);
+        assert_eq!(
+            store.get("github").unwrap().key.expose(),
+            "NDVP6W4K6HKVUQJUY4F627PCSYUVQSNJF4BBTH2BQT24LONOLSXQ"
+        );
+        assert_eq!(stored_version(&store), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn loads_schema_1_fixture() {
+        let mut store = store_with_contents(
+            Box::new(MemoryBackend::new()),
+            include_str!("tests/fixtures/schema-1.toml"),
+        );
+
+        store.unlock(PIN).unwrap();
+
+        let bank = store.get("bank").unwrap();
+        assert_eq!(bank.issuer, Some(String::from("Bank")));
+        assert_eq!(bank.get_digits(), 8);
+        assert_eq!(bank.otp_type, OtpType::HOTP(Some(2)));
+        assert_eq!(bank.remaining_recovery_codes(), 1);
+        let github = store.get("github").unwrap();
+        assert_eq!(github.previous_key.as_ref().unwrap().expires_at, 120);
+        assert_eq!(github.credentials[0].name, "yubikey");
+        assert_eq!(store.list_trash()[0].name, "old");
+        assert!(store.read_audit_log().is_ok());
+        assert_eq!(stored_version(&store), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn backs_up_the_store_before_migrating() {
+        let directory = crate::storage::tests::get_test_directory("migrate");
+        let backend = storage::create(storage::BackendType::File, &directory).unwrap();
+        let mut store = store_with_contents(backend, include_str!("tests/fixtures/schema-0.toml"));
+        let accounts = store.backend.load_accounts().unwrap();
+        let secrets = store.backend.load_secrets().unwrap();
+
+        store.unlock(PIN).unwrap();
+
+        let backup = directory.join("backups").join("schema-0");
+        assert_eq!(
+            std::fs::read(backup.join("accounts.txt")).unwrap(),
+            accounts
         );
+        assert_eq!(
+            std::fs::read_to_string(backup.join("secrets.txt")).unwrap(),
+            secrets
+        );
+        assert_ne!(store.backend.load_accounts().unwrap(), accounts);
+
+        // Unlocking again finds nothing left to migrate
+        let mut store = store.reopen();
+        store.unlock(PIN).unwrap();
+        assert!(!directory.join("backups").join("schema-0-2").exists());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn migrates_in_memory_alone_when_read_only() {
+        let directory = crate::storage::tests::get_test_directory("migrate-read-only");
+        let backend = storage::create(storage::BackendType::File, &directory).unwrap();
+        let store = store_with_contents(backend, include_str!("tests/fixtures/schema-0.toml"));
+        let accounts = store.backend.load_accounts().unwrap();
+
+        let backend = storage::open_read_only(&directory).unwrap();
+        let mut store = AccountStore::open(backend).unwrap();
+        store.unlock(PIN).unwrap();
+
+        assert_eq!(store.list(), vec!["bank", "github"]);
+        assert_eq!(store.backend.load_accounts().unwrap(), accounts);
+        assert!(!directory.join("backups").exists());
+        assert_eq!(
+            store.save().unwrap_err().to_string(),
+            "The account store was opened read-only"
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    // A store from before the key was derived from the pin, keyed with the start of its hash
+    fn legacy_store_with_contents(
+        backend: Box<dyn StorageBackend>,
+        contents: &str,
+    ) -> AccountStore {
+        let mut store = AccountStore::open(backend).unwrap();
+        let hash = crate::crypto::encrypt_pw(PIN, &test_kdf_params(), None).unwrap();
+        let (encrypted, nonce) = encrypt_string(contents, &hash.as_bytes()[..32]).unwrap();
+        store.secrets = Secrets {
+            hash: Some(hash),
+            nonce: Some(nonce),
+            ..Secrets::default()
+        };
+        let secrets = toml::to_string(&store.secrets).unwrap();
+        store.backend.commit(&secrets, &encrypted).unwrap();
+        store.reopen()
+    }
+
+    #[test]
+    fn keeps_the_backup_of_a_legacy_store_once_it_is_migrated() {
+        let directory = crate::storage::tests::get_test_directory("migrate-legacy");
+        let backend = storage::create(storage::BackendType::File, &directory).unwrap();
+        let mut store =
+            legacy_store_with_contents(backend, include_str!("tests/fixtures/schema-0.toml"));
+        assert!(store.secrets.is_legacy());
+
+        store.unlock(PIN).unwrap();
+
+        // The copy outlasts the migrated store being saved, in case it has to be restored
+        let backup = directory.join("backups").join("schema-0");
+        let secrets = std::fs::read_to_string(backup.join("secrets.txt")).unwrap();
+        assert!(toml::from_str::<Secrets>(&secrets).unwrap().is_legacy());
+        let mut store = store.reopen();
+        assert!(!store.secrets.is_legacy());
+        store.unlock(PIN).unwrap();
+        assert_eq!(store.list(), vec!["bank", "github"]);
+        assert_eq!(stored_version(&store), SCHEMA_VERSION);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn sqlite_store(name: &str) -> (AccountStore, std::path::PathBuf) {
+        let directory = crate::storage::tests::get_test_directory(name);
+        let backend = storage::create(storage::BackendType::Sqlite, &directory).unwrap();
+        let mut store = AccountStore::open(backend).unwrap();
+        store.set_pin(PIN, test_kdf_params()).unwrap();
+        (store, directory)
+    }
+
+    #[test]
+    fn saves_only_the_accounts_that_changed() {
+        let (mut store, directory) = sqlite_store("records");
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from("key-1"), OtpType::TOTP),
+        );
+        store.add(
+            String::from(ACCOUNT_NAME_2),
+            Account::new(String::from("key-2"), OtpType::TOTP),
+        );
+        store.save().unwrap();
+        let records = store.backend.load_records().unwrap();
+        assert_eq!(records.len(), 2);
+        // Neither the names nor the keys are stored in the clear
+        let blob = store.backend.load_accounts().unwrap();
+        for record in &records {
+            assert!(!record.id.contains(ACCOUNT_NAME_1) && !record.id.contains(ACCOUNT_NAME_2));
+            assert!(!record.contents.windows(5).any(|bytes| bytes == b"key-1"));
+        }
+        assert!(!blob.windows(5).any(|bytes| bytes == b"key-1"));
+
+        store.record_use(ACCOUNT_NAME_1, 90);
+        store.delete(ACCOUNT_NAME_2, 90);
+        store.save().unwrap();
+
+        let saved = store.backend.load_records().unwrap();
+        assert_eq!(saved.len(), 1);
+        assert!(!records.contains(&saved[0]));
+        // The record left alone keeps its ciphertext and nonce
+        store.record_use(ACCOUNT_NAME_1, 100);
+        store.add(
+            String::from(ACCOUNT_NAME_3),
+            Account::new(String::from("key-3"), OtpType::TOTP),
+        );
+        let before = store.backend.load_records().unwrap();
+        store.save().unwrap();
+        let after = store.backend.load_records().unwrap();
+        assert_eq!(after.len(), 2);
+        assert!(!after.contains(&before[0]));
+
+        let mut store = store.reopen();
+        store.unlock(PIN).unwrap();
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1, ACCOUNT_NAME_3]);
+        assert_eq!(store.get(ACCOUNT_NAME_1).unwrap().use_count, 2);
+        assert_eq!(store.list_trash()[0].name, ACCOUNT_NAME_2);
+        std::fs::remove_dir_all(directory).unwrap();
     }
 
     #[test]
-    fn sets_secrets() {
+    fn refuses_a_record_with_a_truncated_nonce() {
+        let (mut store, directory) = sqlite_store("records-nonce");
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from("key-1"), OtpType::TOTP),
+        );
+        store.save().unwrap();
+        let mut record = store.backend.load_records().unwrap().remove(0);
+        record.nonce.truncate(12);
+        let secrets = store.backend.load_secrets().unwrap();
+        let accounts = store.backend.load_accounts().unwrap();
+        store
+            .backend
+            .commit_records(&secrets, &accounts, &[record], &[])
+            .unwrap();
+
+        let err = store.reopen().unlock(PIN).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn rewrites_every_record_under_a_new_pin() {
+        let (mut store, directory) = sqlite_store("records-pin");
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from("key-1"), OtpType::TOTP),
+        );
+        store.save().unwrap();
+        let records = store.backend.load_records().unwrap();
+
+        store.set_pin(NEW_PIN, test_kdf_params()).unwrap();
+        store.save().unwrap();
+
+        let saved = store.backend.load_records().unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_ne!(saved[0].id, records[0].id);
+        let mut store = store.reopen();
+        store.unlock(NEW_PIN).unwrap();
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1]);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn moves_accounts_out_of_an_sqlite_blob() {
+        let directory = crate::storage::tests::get_test_directory("records-blob");
+        let backend = storage::create(storage::BackendType::Sqlite, &directory).unwrap();
+        let mut store = store_with_contents(backend, include_str!("tests/fixtures/schema-1.toml"));
+        store.unlock(PIN).unwrap();
+        let names = store.list();
+        assert!(store.backend.load_records().unwrap().is_empty());
+
+        store.save().unwrap();
+
+        assert_eq!(store.backend.load_records().unwrap().len(), names.len());
+        let mut store = store.reopen();
+        store.unlock(PIN).unwrap();
+        assert_eq!(store.list(), names);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_store_from_a_newer_version() {
+        let mut store = store_with_contents(Box::new(MemoryBackend::new()), "version = 99\n");
+
+        let err = store.unlock(PIN).unwrap_err();
+
+        assert!(err
+            .to_string()
+            .starts_with("The account store uses schema version 99"));
+        assert_eq!(store.get_key(), None);
+    }
+
+    #[test]
+    fn sets_pin() {
         let mut store = create_empty_store();
-        let hash = encrypt_pw("123456").expect("Failed to encrypt pin");
-        store.set_secrets(&hash);
+        store.set_pin("123456", test_kdf_params()).unwrap();
 
-        assert_eq!(store.secrets.hash, Some(hash));
+        assert!(store.secrets.hash.is_some());
         assert_eq!(store.secrets.nonce, None);
+        assert_eq!(store.secrets.kdf, Some(test_kdf_params()));
+        assert_eq!(store.key.as_ref().map(|key| key.expose().len()), Some(32));
+    }
+
+    #[test]
+    fn keeps_pin_policy_when_pin_changes() {
+        let mut store = get_mock_store();
+        let policy = PinPolicy::new(PinMode::Passphrase);
+        store.set_pin_policy(policy.clone());
+        store
+            .set_pin("a much longer passphrase", test_kdf_params())
+            .unwrap();
+
+        assert_eq!(store.get_pin_policy(), policy);
+    }
+
+    #[test]
+    fn defaults_to_pin_policy() {
+        let store = create_empty_store();
+        assert_eq!(store.get_pin_policy(), PinPolicy::default());
     }
 
     #[test]
+    fn gets_kdf_params() {
+        let store = get_mock_store();
+        assert_eq!(store.get_kdf_params(), test_kdf_params());
+
+        let store = create_empty_store();
+        assert_eq!(store.get_kdf_params().variant, KdfVariant::Argon2id);
+    }
+
+    #[test]
+    fn derives_the_store_key_from_the_pin() {
+        let mut store = create_empty_store();
+        store.set_pin(PIN, test_kdf_params()).unwrap();
+
+        assert_eq!(
+            store.secrets.derive_key(PIN, None).unwrap(),
+            store.key.unwrap()
+        );
+        assert!(
+            store.secrets.derive_key("000000", None).unwrap()
+                != store.secrets.derive_key(PIN, None).unwrap()
+        );
+    }
+
+    #[test]
+    fn decrypts_accounts_with_the_derived_key() {
+        let mut store = create_empty_store();
+        store.set_pin(PIN, test_kdf_params()).unwrap();
+        let (encrypted, nonce) = encrypt_string("", store.key.as_ref().unwrap().expose()).unwrap();
+        store.secrets.nonce = Some(nonce);
+
+        let key = store.secrets.derive_key(PIN, None).unwrap();
+        assert!(decrypt_accounts(&encrypted, key.expose(), &store.secrets).is_ok());
+
+        let wrong_key = store.secrets.derive_key("000000", None).unwrap();
+        let err = decrypt_accounts(&encrypted, wrong_key.expose(), &store.secrets).unwrap_err();
+        assert_eq!(err.to_string(), "Decryption failed");
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn validates_correct_pin() {
         let store = get_mock_store();
         assert_eq!(store.validate_pin("123456"), true);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn validates_incorrect_pin() {
         let store = get_mock_store();
         assert_eq!(store.validate_pin("000000"), false);
     }
+
+    #[test]
+    fn unlocks_accounts_saved_to_the_backend() {
+        let mut store = get_mock_store();
+        store.delete(ACCOUNT_NAME_2, 90);
+        store.save().unwrap();
+        store.add(
+            String::from(ACCOUNT_NAME_3),
+            Account::new(String::from("key-3"), OtpType::TOTP),
+        );
+
+        store.unlock(PIN).unwrap();
+
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1]);
+        assert_eq!(store.list_trash()[0].name, ACCOUNT_NAME_2);
+        assert!(store.unlock("000000").is_err());
+    }
+
+    #[test]
+    fn reopens_a_saved_store() {
+        let mut store = get_mock_store();
+        store.save().unwrap();
+        let AccountStore { backend, .. } = store;
+
+        let mut store = AccountStore::open(backend).unwrap();
+        assert!(store.is_initialized());
+        assert_eq!(store.get(ACCOUNT_NAME_1), None);
+
+        store.unlock(PIN).unwrap();
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]);
+    }
+
+    #[test]
+    fn unlocks_with_a_derived_key() {
+        let store = get_mock_store();
+        let key = store.get_key().unwrap().clone();
+
+        let mut store = store.reopen();
+        assert!(store
+            .unlock_with_key(SecretBytes::new(vec![0; 32]))
+            .is_err());
+        store.unlock_with_key(key).unwrap();
+
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]);
+    }
+
+    #[test]
+    fn lists_names_from_the_index_while_locked() {
+        let mut store = get_mock_store();
+        assert!(store.reopen().list_names().is_err());
+
+        store = get_mock_store();
+        store.set_listing(Listing::Index);
+        store.set_pin(PIN, test_kdf_params()).unwrap();
+        store.save().unwrap();
+        let store = store.reopen();
+
+        assert_eq!(store.get_listing(), Listing::Index);
+        assert_eq!(store.list(), Vec::<String>::new());
+        assert_eq!(
+            store.list_names().unwrap(),
+            vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]
+        );
+    }
+
+    #[test]
+    fn keeps_account_details_out_of_the_index() {
+        let mut store = get_mock_store();
+        store.set_listing(Listing::Index);
+        store.save().unwrap();
+
+        let secrets = store.backend.load_secrets().unwrap();
+        assert!(secrets.contains("[index]"));
+        assert!(secrets.contains(ACCOUNT_NAME_1));
+        assert!(!secrets.contains("key-1"));
+
+        store.set_listing(Listing::Unlock);
+        store.save().unwrap();
+        assert!(store.secrets.index.is_none());
+        let secrets = store.backend.load_secrets().unwrap();
+        assert!(!secrets.contains(ACCOUNT_NAME_1));
+    }
+
+    #[test]
+    fn counts_failed_unlocks_across_runs() {
+        let mut store = get_mock_store();
+        for timestamp in [100, 110, 120] {
+            assert!(!store.record_failed_unlock(timestamp).unwrap());
+        }
+
+        let mut store = store.reopen();
+        assert_eq!(store.get_unlock_delay(120), 5);
+        assert_eq!(store.get_unlock_delay(124), 1);
+        assert_eq!(store.get_unlock_delay(125), 0);
+
+        store.reset_failed_unlocks().unwrap();
+        let store = store.reopen();
+        assert_eq!(store.get_unlock_delay(120), 0);
+        assert!(store.is_initialized());
+    }
+
+    #[test]
+    fn wipes_the_vault_after_the_last_attempt() {
+        let mut store = get_mock_store();
+        store.set_wipe_after(Some(3));
+        store.save().unwrap();
+
+        let mut store = store.reopen();
+        assert!(!store.record_failed_unlock(100).unwrap());
+        assert!(!store.record_failed_unlock(110).unwrap());
+        assert!(store.record_failed_unlock(120).unwrap());
+
+        assert!(!store.is_initialized());
+        assert!(!store.reopen().is_initialized());
+    }
+
+    #[test]
+    fn requires_the_keyfile_to_unlock() {
+        let mut store = create_empty_store();
+        store.set_keyfile(Some(hash_keyfile(b"keyfile")));
+        store.set_pin(PIN, test_kdf_params()).unwrap();
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from("key-1"), OtpType::TOTP),
+        );
+        store.save().unwrap();
+        assert!(store.has_keyfile());
+
+        store.set_keyfile(None);
+        assert!(!store.validate_pin(PIN));
+        let err = store.unlock(PIN).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "A keyfile is required to unlock the account store"
+        );
+
+        store.set_keyfile(Some(hash_keyfile(b"another keyfile")));
+        assert!(!store.validate_pin(PIN));
+        let err = store.unlock(PIN).unwrap_err();
+        assert_eq!(err.to_string(), "Decryption failed with this keyfile");
+
+        store.set_keyfile(Some(hash_keyfile(b"keyfile")));
+        assert!(store.validate_pin(PIN));
+        store.unlock(PIN).unwrap();
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1]);
+    }
+
+    #[test]
+    fn removes_the_keyfile_with_a_new_pin() {
+        let mut store = get_mock_store();
+        store.set_keyfile(Some(hash_keyfile(b"keyfile")));
+        let err = store.unlock(PIN).unwrap_err();
+        assert_eq!(err.to_string(), "The account store does not use a keyfile");
+
+        store.set_pin(PIN, test_kdf_params()).unwrap();
+        store.save().unwrap();
+        store.set_keyfile(None);
+        store.set_pin(NEW_PIN, test_kdf_params()).unwrap();
+        store.save().unwrap();
+
+        assert!(!store.has_keyfile());
+        store.unlock(NEW_PIN).unwrap();
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]);
+    }
+
+    fn add_member(store: &mut AccountStore, name: &str) -> SecretBytes {
+        let (identity, public_key) = crate::crypto::generate_identity();
+        store
+            .add_member(name, &encode_public_key(&public_key))
+            .unwrap();
+        store.save().unwrap();
+        identity
+    }
+
+    #[test]
+    fn shares_the_store_under_a_new_key() {
+        let mut store = get_mock_store();
+        let pin_key = store.get_key().unwrap().clone();
+        let identity = add_member(&mut store, "alice");
+
+        assert_ne!(store.get_key(), Some(&pin_key));
+        let mut store = store.reopen();
+        store.unlock_with_identity(identity.expose()).unwrap();
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]);
+
+        let mut store = store.reopen();
+        store.unlock(PIN).unwrap();
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]);
+    }
+
+    #[test]
+    fn keeps_members_when_the_pin_changes() {
+        let mut store = get_mock_store();
+        let identity = add_member(&mut store, "alice");
+
+        store.set_pin(NEW_PIN, test_kdf_params()).unwrap();
+        store.save().unwrap();
+
+        let mut store = store.reopen();
+        store.unlock_with_identity(identity.expose()).unwrap();
+        let mut store = store.reopen();
+        store.unlock(NEW_PIN).unwrap();
+        assert_eq!(store.list_members()[0].name, "alice");
+    }
+
+    #[test]
+    fn locks_out_removed_members() {
+        let mut store = get_mock_store();
+        let alice = add_member(&mut store, "alice");
+        let bob = add_member(&mut store, "bob");
+        let key = store.get_key().unwrap().clone();
+
+        store.remove_member("alice").unwrap();
+        store.save().unwrap();
+
+        assert_ne!(store.get_key(), Some(&key));
+        let mut store = store.reopen();
+        assert_eq!(
+            store
+                .unlock_with_identity(alice.expose())
+                .unwrap_err()
+                .kind(),
+            ErrorKind::PermissionDenied
+        );
+        // The old key no longer opens what was saved since
+        assert!(store.unlock_with_key(key).is_err());
+        store.unlock_with_identity(bob.expose()).unwrap();
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]);
+    }
+
+    fn audit_event(operation: &str) -> AuditEvent {
+        AuditEvent {
+            timestamp: 90,
+            operation: String::from(operation),
+            account: None,
+            outcome: Outcome::Success,
+        }
+    }
+
+    #[test]
+    fn rotates_the_audit_key_of_removed_members() {
+        let mut store = get_mock_store();
+        add_member(&mut store, "alice");
+        store.audit(audit_event("member-add")).unwrap();
+        let (audit_key, _) = get_audit_key(&mut store.vault).unwrap();
+
+        store.remove_member("alice").unwrap();
+        store.save().unwrap();
+        store.audit(audit_event("member-remove")).unwrap();
+
+        // The key alice could have kept stops at the entries written before she was removed
+        let log = store.backend.load_audit_log().unwrap();
+        let report = read_log(&log, &[audit_key.expose()]);
+        assert_eq!(report.entries.last().unwrap().operation, "member-add");
+        assert_eq!(
+            report.error,
+            Some(format!(
+                "entry {} is unreadable: decryption failed",
+                report.entries.len() + 1
+            ))
+        );
+
+        let mut store = store.reopen();
+        store.unlock(PIN).unwrap();
+        let report = store.read_audit_log().unwrap();
+        assert_eq!(report.error, None);
+        let operations: Vec<&str> = report
+            .entries
+            .iter()
+            .map(|entry| entry.operation.as_str())
+            .collect();
+        assert!(operations.ends_with(&["member-add", "member-remove"]));
+    }
+
+    #[test]
+    fn refuses_members_added_beside_the_vault() {
+        let mut store = get_mock_store();
+        add_member(&mut store, "alice");
+        store.save().unwrap();
+
+        // Someone able to write the secrets, but without the key, adds a member of their own
+        let mut secrets: Secrets = toml::from_str(&store.backend.load_secrets().unwrap()).unwrap();
+        let (_, public_key) = crate::crypto::generate_identity();
+        let mallory = Member {
+            name: String::from("mallory"),
+            public_key: encode_public_key(&public_key),
+            ..secrets.members[0].clone()
+        };
+        secrets.members.push(mallory);
+        let accounts = store.backend.load_accounts().unwrap();
+        store
+            .backend
+            .commit(&toml::to_string(&secrets).unwrap(), &accounts)
+            .unwrap();
+
+        let mut store = store.reopen();
+        store.unlock(PIN).unwrap();
+        let (_, bob) = crate::crypto::generate_identity();
+        for err in [
+            store.remove_member("alice").unwrap_err(),
+            store
+                .add_member("bob", &encode_public_key(&bob))
+                .unwrap_err(),
+        ] {
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(
+                err.to_string(),
+                "The members were changed outside of otp, restore the secrets from a backup"
+            );
+        }
+        assert_eq!(store.list_members().len(), 2);
+    }
+
+    #[test]
+    fn needs_the_pin_to_start_sharing() {
+        let store = get_mock_store();
+        let key = store.get_key().unwrap().clone();
+        let mut store = store.reopen();
+        store.unlock_with_key(key).unwrap();
+        let (_, public_key) = crate::crypto::generate_identity();
+
+        let err = store
+            .add_member("alice", &encode_public_key(&public_key))
+            .unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(store.list_members().is_empty());
+    }
 }
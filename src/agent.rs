@@ -0,0 +1,473 @@
+use data_encoding::BASE32_NOPAD;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::account::AccountStoreOperations;
+use crate::secret::{Secret, SecretBytes};
+use crate::storage::get_directory;
+use crate::writer::OutErr;
+
+pub const AGENT_SOCK_VAR: &str = "OTP_AGENT_SOCK";
+const SOCKET_NAME: &str = "agent.sock";
+
+// Holds the derived key between commands, like ssh-agent. Requests and responses are single
+// lines: "key" answers "ok <base32 key>" or "locked", and "lock" wipes the key.
+pub struct Agent {
+    key: Option<SecretBytes>,
+    idle_timeout: Duration,
+    last_used: Instant,
+}
+
+impl Agent {
+    pub fn new(mut key: SecretBytes, idle_timeout: Duration, now: Instant) -> Agent {
+        key.lock();
+        Agent {
+            key: Some(key),
+            idle_timeout,
+            last_used: now,
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.key.is_none()
+    }
+
+    pub fn handle(&mut self, request: &str, now: Instant) -> Secret<String> {
+        match (request.trim(), &self.key) {
+            ("key", Some(key)) => {
+                self.last_used = now;
+                Secret::new(format!("ok {}\n", BASE32_NOPAD.encode(key.expose())))
+            }
+            ("key", None) => Secret::new(String::from("locked\n")),
+            ("lock", _) => {
+                self.key = None;
+                Secret::new(String::from("ok\n"))
+            }
+            _ => Secret::new(String::from("error unknown request\n")),
+        }
+    }
+
+    // Wipes the key once nobody has asked for it within the timeout
+    pub fn check_idle(&mut self, now: Instant) {
+        if now.duration_since(self.last_used) >= self.idle_timeout {
+            self.key = None;
+        }
+    }
+}
+
+// An explicit path wins over OTP_AGENT_SOCK, which wins over ~/.otp/agent.sock
+pub fn get_socket_path(given: Option<&str>, var: impl Fn(&str) -> Option<String>) -> PathBuf {
+    match given.map(String::from).or_else(|| var(AGENT_SOCK_VAR)) {
+        Some(path) => PathBuf::from(path),
+        None => get_directory().join(SOCKET_NAME),
+    }
+}
+
+fn invalid_response() -> Error {
+    Error::new(ErrorKind::InvalidData, "Invalid response from the agent")
+}
+
+// The key held by the agent, or None once it has locked
+pub fn request_key(path: &Path) -> Result<Option<SecretBytes>> {
+    let response = unix::request(path, "key")?;
+    let response = response.expose().trim_end();
+    if response == "locked" {
+        return Ok(None);
+    }
+    let encoded = response.strip_prefix("ok ").ok_or_else(invalid_response)?;
+    BASE32_NOPAD
+        .decode(encoded.as_bytes())
+        .map(|key| Some(Secret::new(key)))
+        .map_err(|_| invalid_response())
+}
+
+pub fn lock(path: &Path) -> Result<()> {
+    match unix::request(path, "lock")?.expose().trim_end() {
+        "ok" => Ok(()),
+        _ => Err(invalid_response()),
+    }
+}
+
+// Whether the agent couldn't be reached because it isn't running. An agent that locked
+// removes its socket, and one that was killed leaves it behind with nobody listening.
+pub fn is_not_running(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::NotFound | ErrorKind::ConnectionRefused
+    )
+}
+
+// Unlocks with the key held by the agent at OTP_AGENT_SOCK. Returns false when the pin has to
+// be asked for instead, after a warning when the agent is there but couldn't be used.
+pub fn unlock_with_agent(
+    account_store: &mut impl AccountStoreOperations,
+    var: impl Fn(&str) -> Option<String>,
+    writer: &mut impl OutErr,
+) -> bool {
+    let path = match var(AGENT_SOCK_VAR) {
+        Some(path) => PathBuf::from(path),
+        None => return false,
+    };
+    if !account_store.is_initialized() {
+        return false;
+    }
+
+    let result = request_key(&path).and_then(|key| match key {
+        Some(key) => account_store.unlock_with_key(key).map(|_| true),
+        None => Ok(false),
+    });
+    match result {
+        Ok(unlocked) => unlocked,
+        Err(err) if is_not_running(&err) => false,
+        Err(err) => {
+            writer.write_err(&format!(
+                "Unable to use the agent at {}: {}\n",
+                path.display(),
+                err
+            ));
+            false
+        }
+    }
+}
+
+pub use unix::{bind, detach, serve};
+
+#[cfg(unix)]
+mod unix {
+    use std::fs::{self, OpenOptions, Permissions};
+    use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::Agent;
+    use crate::secret::Secret;
+
+    // How long a client may take to send its request or the agent to answer
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+    // How often an idle agent checks whether it is time to lock
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+    // Longer than any request, so a client can't make the agent buffer without limit
+    const MAX_REQUEST_LENGTH: u64 = 64;
+
+    fn current_uid() -> u32 {
+        unsafe { libc::getuid() }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn peer_uid(stream: &UnixStream) -> Result<Option<u32>> {
+        let mut credentials = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut credentials as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        match result {
+            0 => Ok(Some(credentials.uid)),
+            _ => Err(Error::last_os_error()),
+        }
+    }
+
+    // Elsewhere the socket's permissions are the only check
+    #[cfg(not(target_os = "linux"))]
+    fn peer_uid(_stream: &UnixStream) -> Result<Option<u32>> {
+        Ok(None)
+    }
+
+    // Only the owner may connect. The umask keeps the socket private from the moment it is
+    // created, rather than from when its permissions are changed afterwards.
+    pub fn bind(path: &Path) -> Result<UnixListener> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("An agent is already running on {}", path.display()),
+                ));
+            }
+            // Left behind by an agent that was killed
+            fs::remove_file(path)?;
+        }
+        let mask = unsafe { libc::umask(0o177) };
+        let listener = UnixListener::bind(path);
+        unsafe { libc::umask(mask) };
+        let listener = listener?;
+        fs::set_permissions(path, Permissions::from_mode(0o600))?;
+        Ok(listener)
+    }
+
+    // Forks an agent that carries on in its own session with its standard streams on
+    // /dev/null, so that the command returns and `eval $(otp agent)` works as with ssh-agent.
+    // Returns the child's pid in the parent and None in the child.
+    pub fn detach() -> Result<Option<u32>> {
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+        match unsafe { libc::fork() } {
+            -1 => Err(Error::last_os_error()),
+            0 => {
+                // Nobody is left to report to, and a stale socket is removed by the next bind
+                if leave_terminal().is_err() {
+                    std::process::exit(1);
+                }
+                Ok(None)
+            }
+            pid => Ok(Some(pid as u32)),
+        }
+    }
+
+    fn leave_terminal() -> Result<()> {
+        if unsafe { libc::setsid() } == -1 {
+            return Err(Error::last_os_error());
+        }
+        let null = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")?;
+        for fd in 0..3 {
+            if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+                return Err(Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    // Answers requests until the agent is locked, by request or for being idle
+    pub fn serve(agent: &mut Agent, listener: &UnixListener) -> Result<()> {
+        listener.set_nonblocking(true)?;
+        while !agent.is_locked() {
+            match listener.accept() {
+                // A client that misbehaves only loses its own answer
+                Ok((stream, _)) => {
+                    let _ = serve_client(agent, stream);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(err) => return Err(err),
+            }
+            agent.check_idle(Instant::now());
+        }
+        Ok(())
+    }
+
+    fn serve_client(agent: &mut Agent, stream: UnixStream) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        if peer_uid(&stream)?.is_some_and(|uid| uid != current_uid()) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Client belongs to another user",
+            ));
+        }
+
+        let mut request = String::new();
+        BufReader::new(&stream)
+            .take(MAX_REQUEST_LENGTH)
+            .read_line(&mut request)?;
+        let response = agent.handle(&request, Instant::now());
+        (&stream).write_all(response.expose().as_bytes())
+    }
+
+    // Anyone who could replace the socket could hand out a key of their choosing or collect
+    // ours, so it has to belong to this user and nobody else may use it
+    fn check_socket(path: &Path) -> Result<()> {
+        let metadata = fs::metadata(path)?;
+        if metadata.uid() != current_uid() || metadata.mode() & 0o077 != 0 {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} is not private to this user", path.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn request(path: &Path, request: &str) -> Result<Secret<String>> {
+        check_socket(path)?;
+        let mut stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.write_all(format!("{}\n", request).as_bytes())?;
+
+        let mut response = Secret::new(String::new());
+        BufReader::new(stream).read_line(response.expose_mut())?;
+        Ok(response)
+    }
+}
+
+#[cfg(not(unix))]
+mod unix {
+    use std::io::{Error, ErrorKind, Result};
+    use std::path::Path;
+
+    use super::Agent;
+    use crate::secret::Secret;
+
+    fn unsupported() -> Error {
+        Error::new(
+            ErrorKind::Unsupported,
+            "The agent is only supported on Unix",
+        )
+    }
+
+    pub struct UnixListener;
+
+    pub fn bind(_path: &Path) -> Result<UnixListener> {
+        Err(unsupported())
+    }
+
+    pub fn serve(_agent: &mut Agent, _listener: &UnixListener) -> Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn detach() -> Result<Option<u32>> {
+        Err(unsupported())
+    }
+
+    pub fn request(_path: &Path, _request: &str) -> Result<Secret<String>> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::storage::tests::get_test_directory;
+    use crate::tests::constants::{ACCOUNT_NAME_1, ACCOUNT_NAME_2};
+    use crate::tests::mocks::MockOtpWriter;
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn key() -> SecretBytes {
+        Secret::new(vec![7; 32])
+    }
+
+    // Starts an agent on its own thread, returning once it is listening
+    fn start_agent(directory: &Path, key: SecretBytes) -> (PathBuf, thread::JoinHandle<()>) {
+        let path = directory.join(SOCKET_NAME);
+        let listener = bind(&path).unwrap();
+        let handle = thread::spawn(move || {
+            let mut agent = Agent::new(key, TIMEOUT, Instant::now());
+            serve(&mut agent, &listener).unwrap();
+        });
+        (path, handle)
+    }
+
+    #[test]
+    fn hands_out_the_key_until_locked() {
+        let now = Instant::now();
+        let mut agent = Agent::new(key(), TIMEOUT, now);
+
+        let response = agent.handle("key\n", now);
+        assert_eq!(
+            response.expose(),
+            &format!("ok {}\n", BASE32_NOPAD.encode(&[7; 32]))
+        );
+        assert_eq!(agent.handle("lock\n", now).expose(), "ok\n");
+        assert!(agent.is_locked());
+        assert_eq!(agent.handle("key\n", now).expose(), "locked\n");
+        assert_eq!(
+            agent.handle("pin\n", now).expose(),
+            "error unknown request\n"
+        );
+    }
+
+    #[test]
+    fn locks_when_idle() {
+        let now = Instant::now();
+        let mut agent = Agent::new(key(), TIMEOUT, now);
+
+        agent.check_idle(now + TIMEOUT / 2);
+        agent.handle("key\n", now + TIMEOUT / 2);
+        agent.check_idle(now + TIMEOUT);
+        assert!(!agent.is_locked());
+
+        agent.check_idle(now + TIMEOUT * 2);
+        assert!(agent.is_locked());
+    }
+
+    #[test]
+    fn serves_the_key_over_a_private_socket() {
+        let directory = get_test_directory("agent");
+        let (path, handle) = start_agent(&directory, key());
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(request_key(&path).unwrap(), Some(key()));
+
+        let err = bind(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+        lock(&path).unwrap();
+        handle.join().unwrap();
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_socket_others_can_use() {
+        let directory = get_test_directory("agent-shared");
+        let (path, handle) = start_agent(&directory, key());
+        fs::set_permissions(&path, Permissions::from_mode(0o666)).unwrap();
+
+        let err = request_key(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+        fs::set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+        lock(&path).unwrap();
+        handle.join().unwrap();
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn unlocks_the_store_with_the_agent() {
+        let directory = get_test_directory("agent-unlock");
+        let store = get_mock_store();
+        let (path, handle) = start_agent(&directory, store.get_key().unwrap().clone());
+        let mut store = store.reopen();
+        let mut writer = MockOtpWriter::new();
+        let var = |name: &str| match name {
+            AGENT_SOCK_VAR => path.to_str().map(String::from),
+            _ => None,
+        };
+
+        assert!(unlock_with_agent(&mut store, var, &mut writer));
+        assert_eq!(store.list(), vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]);
+        assert_eq!(writer.err, Vec::new());
+
+        lock(&path).unwrap();
+        handle.join().unwrap();
+        assert!(!unlock_with_agent(&mut store.reopen(), var, &mut writer));
+        assert_eq!(writer.err, Vec::new());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn warns_when_the_key_does_not_fit() {
+        let directory = get_test_directory("agent-other-store");
+        let (path, handle) = start_agent(&directory, key());
+        let mut store = get_mock_store().reopen();
+        let mut writer = MockOtpWriter::new();
+        let var = |_: &str| path.to_str().map(String::from);
+
+        assert!(!unlock_with_agent(&mut store, var, &mut writer));
+        assert!(String::from_utf8(writer.err)
+            .unwrap()
+            .starts_with(&format!("Unable to use the agent at {}: ", path.display())));
+
+        lock(&path).unwrap();
+        handle.join().unwrap();
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use data_encoding::{BASE64, HEXLOWER};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::crypto::{decrypt_string, encrypt_string, NONCE_LENGTH};
+use crate::secret::Secret;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+        }
+    }
+}
+
+// What happened, without codes, keys or pins
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEvent {
+    pub timestamp: u64,
+    pub operation: String,
+    pub account: Option<String>,
+    pub outcome: Outcome,
+}
+
+// One line of the log, linked to the line before it by hash
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    pub previous: String,
+    pub timestamp: u64,
+    pub operation: String,
+    pub account: Option<String>,
+    pub outcome: Outcome,
+}
+
+// Entries that could be read, and where the chain broke if it did
+#[derive(Debug, Default, PartialEq)]
+pub struct AuditReport {
+    pub entries: Vec<AuditEntry>,
+    pub error: Option<String>,
+}
+
+fn hash_line(line: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(line.as_bytes()))
+}
+
+// Encrypt the event as the next line of the log. Each line is the base64 nonce and
+// ciphertext of an entry holding its position and the hash of the line before it.
+fn seal_entry(log: &str, key: &[u8], event: AuditEvent) -> Result<String, String> {
+    let lines: Vec<&str> = log.lines().collect();
+    let entry = AuditEntry {
+        sequence: lines.len() as u64,
+        previous: lines.last().map(|line| hash_line(line)).unwrap_or_default(),
+        timestamp: event.timestamp,
+        operation: event.operation,
+        account: event.account,
+        outcome: event.outcome,
+    };
+    let contents = Secret::new(toml::to_string(&entry).map_err(|err| err.to_string())?);
+
+    let (ciphertext, nonce) = encrypt_string(contents.expose(), key)?;
+    Ok(BASE64.encode(&[nonce, ciphertext].concat()))
+}
+
+// Seal events onto the end of the log, returning the lines that were added
+pub fn append_events(
+    log: &mut String,
+    key: &[u8],
+    events: Vec<AuditEvent>,
+) -> Result<String, String> {
+    let mut lines = String::new();
+    for event in events {
+        let line = format!("{}\n", seal_entry(log, key, event)?);
+        log.push_str(&line);
+        lines.push_str(&line);
+    }
+    Ok(lines)
+}
+
+// The log may have been sealed with several keys over time, so each is tried in turn
+fn open_entry(line: &str, keys: &[&[u8]]) -> Result<AuditEntry, String> {
+    let sealed = BASE64
+        .decode(line.as_bytes())
+        .map_err(|_| String::from("not valid base64"))?;
+    if sealed.len() < NONCE_LENGTH {
+        return Err(String::from("too short"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+    let contents = keys
+        .iter()
+        .find_map(|key| decrypt_string(ciphertext, key, nonce).ok())
+        .ok_or_else(|| String::from("decryption failed"))?;
+    toml::from_str(contents.expose()).map_err(|err| err.to_string())
+}
+
+// Decrypt every entry and check that each one follows the entry before it
+pub fn read_log(log: &str, keys: &[&[u8]]) -> AuditReport {
+    let mut report = AuditReport::default();
+    let mut previous = String::new();
+    for (index, line) in log.lines().enumerate() {
+        let entry = match open_entry(line, keys) {
+            Ok(entry) => entry,
+            Err(err) => {
+                report.error = Some(format!("entry {} is unreadable: {}", index + 1, err));
+                return report;
+            }
+        };
+        if entry.sequence != index as u64 {
+            report.error = Some(format!("entry {} is out of order", index + 1));
+            return report;
+        }
+        if entry.previous != previous {
+            report.error = Some(format!(
+                "entry {} does not follow the entry before it",
+                index + 1
+            ));
+            return report;
+        }
+        previous = hash_line(line);
+        report.entries.push(entry);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn event(operation: &str, account: Option<&str>) -> AuditEvent {
+        AuditEvent {
+            timestamp: 90,
+            operation: String::from(operation),
+            account: account.map(String::from),
+            outcome: Outcome::Success,
+        }
+    }
+
+    fn append(log: &mut String, event: AuditEvent) {
+        append_events(log, &KEY, vec![event]).unwrap();
+    }
+
+    fn get_log() -> String {
+        let mut log = String::new();
+        append(&mut log, event("init", None));
+        append(&mut log, event("add", Some("pets")));
+        append(&mut log, event("get", Some("pets")));
+        log
+    }
+
+    #[test]
+    fn reads_back_entries_in_order() {
+        let report = read_log(&get_log(), &[&KEY]);
+
+        assert_eq!(report.error, None);
+        assert_eq!(report.entries.len(), 3);
+        assert_eq!(report.entries[0].previous, "");
+        assert_eq!(report.entries[1].operation, "add");
+        assert_eq!(report.entries[2].sequence, 2);
+        assert_eq!(report.entries[2].account, Some(String::from("pets")));
+    }
+
+    #[test]
+    fn encrypts_entries() {
+        let log = get_log();
+
+        assert!(!log.contains("pets"));
+        let report = read_log(&log, &[&[8; 32]]);
+        assert_eq!(report.entries, Vec::new());
+        assert_eq!(
+            report.error,
+            Some(String::from("entry 1 is unreadable: decryption failed"))
+        );
+    }
+
+    #[test]
+    fn reads_entries_sealed_with_earlier_keys() {
+        let mut log = get_log();
+        append_events(&mut log, &[8; 32], vec![event("member-remove", None)]).unwrap();
+
+        let report = read_log(&log, &[&[8; 32], &KEY]);
+        assert_eq!(report.error, None);
+        assert_eq!(report.entries.len(), 4);
+        assert_eq!(
+            report.entries[3].previous,
+            hash_line(log.lines().nth(2).unwrap())
+        );
+
+        // Without the new key the chain can't be followed past the old one
+        let report = read_log(&log, &[&KEY]);
+        assert_eq!(report.entries.len(), 3);
+        assert_eq!(
+            report.error,
+            Some(String::from("entry 4 is unreadable: decryption failed"))
+        );
+    }
+
+    #[test]
+    fn detects_removed_entries() {
+        let log = get_log();
+        let lines: Vec<&str> = log.lines().collect();
+        let log = format!("{}\n{}\n", lines[0], lines[2]);
+
+        let report = read_log(&log, &[&KEY]);
+
+        assert_eq!(report.entries.len(), 1);
+        assert_eq!(report.error, Some(String::from("entry 2 is out of order")));
+    }
+
+    #[test]
+    fn detects_entries_from_another_log() {
+        let mut log = String::new();
+        append(&mut log, event("init", None));
+        let mut other = String::new();
+        append(&mut other, event("init", None));
+        append(&mut other, event("delete", Some("pets")));
+        let spliced = format!("{}{}\n", log, other.lines().nth(1).unwrap());
+
+        let report = read_log(&spliced, &[&KEY]);
+
+        assert_eq!(report.entries.len(), 1);
+        assert_eq!(
+            report.error,
+            Some(String::from("entry 2 does not follow the entry before it"))
+        );
+    }
+
+    #[test]
+    fn detects_modified_entries() {
+        let log = get_log();
+        let first = log.lines().next().unwrap();
+        let mut tampered: Vec<char> = first.chars().collect();
+        tampered[40] = if tampered[40] == 'A' { 'B' } else { 'A' };
+        let log = log.replacen(first, &tampered.into_iter().collect::<String>(), 1);
+
+        let report = read_log(&log, &[&KEY]);
+
+        assert_eq!(report.entries, Vec::new());
+        assert_eq!(
+            report.error,
+            Some(String::from("entry 1 is unreadable: decryption failed"))
+        );
+    }
+}
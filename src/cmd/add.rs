@@ -1,7 +1,12 @@
 use clap::{arg, command, ArgMatches, Command};
 
-use super::CommandType;
+use super::{
+    accepts_key, force_arg, get_tags, metadata_args, set_metadata, tag_arg, write_audit,
+    CommandType,
+};
 use crate::account::{Account, AccountStoreOperations, OtpType};
+use crate::audit::Outcome;
+use crate::totp::{get_unix_time, GetTime};
 use crate::utils::is_base32_key;
 use crate::writer::OutErr;
 
@@ -14,13 +19,17 @@ pub fn subcommand() -> Command<'static> {
                 .required(true)
                 .validator(is_base32_key),
             arg!(-c --hotp "Counter-based HOTP (Time-based TOTP is default)").required(false),
+            tag_arg("tag", "Tag the account, e.g. prod or personal"),
+            force_arg(),
         ])
+        .args(metadata_args())
 }
 
 pub fn run_add<W>(
     add_args: &ArgMatches,
     account_store: &mut impl AccountStoreOperations,
     writer: &mut W,
+    clock: &impl GetTime,
 ) where
     W: OutErr,
 {
@@ -34,21 +43,52 @@ pub fn run_add<W>(
 
     if account_store.get(account_name).is_some() {
         writer.write_err("Account already exists\n");
+        write_audit(
+            account_store,
+            writer,
+            CommandType::Add.as_str(),
+            Some(account_name),
+            Outcome::Failure,
+        );
+    } else if !accepts_key(key, add_args.is_present("force"), account_store, writer) {
+        write_audit(
+            account_store,
+            writer,
+            CommandType::Add.as_str(),
+            Some(account_name),
+            Outcome::Failure,
+        );
     } else {
         let is_hotp = add_args.is_present("hotp");
         let otp_type = match is_hotp {
             true => OtpType::HOTP(Some(0)),
             false => OtpType::TOTP,
         };
-        let account = Account::new(String::from(key), otp_type);
+        let mut account = Account::new(String::from(key), otp_type);
+        set_metadata(add_args, &mut account);
+        account.add_tags(&get_tags(add_args, "tag"));
+        account.created_at = Some(get_unix_time(clock));
         account_store.add(account_name.to_string(), account);
-        match account_store.save() {
-            Ok(_) => writer.write(&format!(
-                "Account \"{}\" successfully created\n",
-                account_name
-            )),
-            Err(err) => writer.write_err(&format!("{}", err)),
-        }
+        let outcome = match account_store.save() {
+            Ok(_) => {
+                writer.write(&format!(
+                    "Account \"{}\" successfully created\n",
+                    account_name
+                ));
+                Outcome::Success
+            }
+            Err(err) => {
+                writer.write_err(&format!("{}", err));
+                Outcome::Failure
+            }
+        };
+        write_audit(
+            account_store,
+            writer,
+            CommandType::Add.as_str(),
+            Some(account_name),
+            outcome,
+        );
     }
 }
 
@@ -69,9 +109,9 @@ mod tests {
         let arg_vec = vec!["otp", Add.as_str(), "-a", ACCOUNT_NAME_3, "-k", TOTP_KEY];
         let add_args = get_cmd_args(Add.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_add(&add_args, &mut store, &mut writer);
+        run_add(&add_args, &mut store, &mut writer, &MockClock::new());
 
-        assert_eq!(store.get(ACCOUNT_NAME_3).unwrap().key, TOTP_KEY);
+        assert_eq!(store.get(ACCOUNT_NAME_3).unwrap().key.expose(), TOTP_KEY);
         assert_eq!(store.get(ACCOUNT_NAME_3).unwrap().otp_type, OtpType::TOTP);
 
         let expected_output = format!("Account \"{}\" successfully created\n", ACCOUNT_NAME_3);
@@ -95,9 +135,9 @@ mod tests {
         ];
         let add_args = get_cmd_args(Add.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_add(&add_args, &mut store, &mut writer);
+        run_add(&add_args, &mut store, &mut writer, &MockClock::new());
 
-        assert_eq!(store.get(ACCOUNT_NAME_3).unwrap().key, HOTP_KEY);
+        assert_eq!(store.get(ACCOUNT_NAME_3).unwrap().key.expose(), HOTP_KEY);
         assert_eq!(
             store.get(ACCOUNT_NAME_3).unwrap().otp_type,
             OtpType::HOTP(Some(0))
@@ -108,6 +148,43 @@ mod tests {
         assert_eq!(writer.err, Vec::new());
     }
 
+    #[test]
+    fn adds_an_account_with_metadata() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Add.as_str(),
+            "-a",
+            ACCOUNT_NAME_3,
+            "-k",
+            TOTP_KEY,
+            "--issuer",
+            "Pets",
+            "--label",
+            "jane@pets.com",
+            "--notes",
+            "Shared with the vet",
+            "--tag",
+            "personal",
+            "--tag",
+            "pets,vet",
+        ];
+        let add_args = get_cmd_args(Add.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_add(&add_args, &mut store, &mut writer, &MockClock::new());
+
+        let account = store.get(ACCOUNT_NAME_3).unwrap();
+        assert_eq!(account.issuer, Some(String::from("Pets")));
+        assert_eq!(account.label, Some(String::from("jane@pets.com")));
+        assert_eq!(account.notes, Some(String::from("Shared with the vet")));
+        assert_eq!(account.created_at, Some(90));
+        assert_eq!(account.tags, vec!["personal", "pets", "vet"]);
+        assert_eq!(account.last_used_at, None);
+        assert_eq!(account.use_count, 0);
+    }
+
     #[test]
     fn requires_account_name() {
         let arg_vec = vec!["otp", Add.as_str(), "-k", TOTP_KEY];
@@ -148,7 +225,7 @@ mod tests {
         let arg_vec = vec!["otp", Add.as_str(), "-a", ACCOUNT_NAME_1, "-k", TOTP_KEY];
         let add_args = get_cmd_args(Add.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_add(&add_args, &mut store, &mut writer);
+        run_add(&add_args, &mut store, &mut writer, &MockClock::new());
 
         assert_eq!(
             String::from_utf8(writer.err).unwrap(),
@@ -179,17 +256,98 @@ mod tests {
         let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
-        store.set_should_save_error(true);
+        store.fail_saves();
 
         let arg_vec = vec!["otp", Add.as_str(), "-a", ACCOUNT_NAME_3, "-k", TOTP_KEY];
         let add_args = get_cmd_args(Add.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_add(&add_args, &mut store, &mut writer);
+        run_add(&add_args, &mut store, &mut writer, &MockClock::new());
 
         assert_eq!(
             String::from_utf8(writer.err).unwrap(),
-            "MockAccountStore failed to save"
+            "Storage backend failed to save"
         );
         assert_eq!(writer.out, Vec::new());
     }
+
+    #[test]
+    fn refuses_a_weak_key() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Add.as_str(), "-a", ACCOUNT_NAME_3, "-k", "AAAA"];
+        let add_args = get_cmd_args(Add.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_add(&add_args, &mut store, &mut writer, &MockClock::new());
+
+        assert!(store.get(ACCOUNT_NAME_3).is_none());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "The key is weak, it is only 16 bits long, RFC 4226 requires at least 128\n\
+             Run again with --force to add it anyway\n"
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn refuses_a_key_already_used_by_another_account() {
+        let mut store = get_mock_store();
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from(TOTP_KEY), OtpType::TOTP),
+        );
+        let mut writer = MockOtpWriter::new();
+
+        let lowercase_key = TOTP_KEY.to_lowercase();
+        let arg_vec = vec![
+            "otp",
+            Add.as_str(),
+            "-a",
+            ACCOUNT_NAME_3,
+            "-k",
+            &lowercase_key,
+        ];
+        let add_args = get_cmd_args(Add.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_add(&add_args, &mut store, &mut writer, &MockClock::new());
+
+        assert!(store.get(ACCOUNT_NAME_3).is_none());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!(
+                "The key is already used by \"{}\"\nRun again with --force to add it anyway\n",
+                ACCOUNT_NAME_1
+            )
+        );
+    }
+
+    #[test]
+    fn adds_a_weak_key_when_forced() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+        let zeros = "A".repeat(32);
+
+        let arg_vec = vec![
+            "otp",
+            Add.as_str(),
+            "-a",
+            ACCOUNT_NAME_3,
+            "-k",
+            &zeros,
+            "--force",
+        ];
+        let add_args = get_cmd_args(Add.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_add(&add_args, &mut store, &mut writer, &MockClock::new());
+
+        assert_eq!(store.get(ACCOUNT_NAME_3).unwrap().key.expose(), &zeros);
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Warning: The key is weak, it is all zeros\n"
+        );
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            format!("Account \"{}\" successfully created\n", ACCOUNT_NAME_3)
+        );
+    }
 }
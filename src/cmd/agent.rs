@@ -0,0 +1,182 @@
+use clap::{arg, command, ArgMatches, Command};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::{count_of, CommandType};
+use crate::account::AccountStoreOperations;
+use crate::agent::{self, get_socket_path, Agent, AGENT_SOCK_VAR};
+use crate::utils::is_positive_number;
+use crate::writer::OutErr;
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Agent.as_str())
+        .about("Keep the store unlocked for other commands until idle or locked")
+        .args(&[
+            arg!(--socket <PATH> "Socket to listen on, instead of OTP_AGENT_SOCK or ~/.otp/agent.sock")
+                .required(false),
+            arg!(-t --timeout <MINUTES> "Lock after this many minutes without a request")
+                .required(false)
+                .default_value("15")
+                .validator(is_positive_number),
+            arg!(-f --foreground "Keep serving in the foreground instead of detaching"),
+        ])
+}
+
+// Serves the key of the unlocked store until `otp lock` or the idle timeout, then wipes it
+pub fn run_agent<W>(
+    agent_args: &ArgMatches,
+    account_store: &impl AccountStoreOperations,
+    var: impl Fn(&str) -> Option<String>,
+    writer: &mut W,
+) where
+    W: OutErr,
+{
+    let key = match account_store.get_key() {
+        Some(key) => key.clone(),
+        None => {
+            writer.write_err("The account store is locked\n");
+            return;
+        }
+    };
+    let minutes = agent_args
+        .value_of("timeout")
+        .and_then(|minutes| minutes.parse::<u64>().ok())
+        .unwrap_or(15);
+    let path = get_socket_path(agent_args.value_of("socket"), var);
+
+    let listener = match agent::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            writer.write_err(&format!("Unable to start the agent: {}\n", err));
+            return;
+        }
+    };
+    // Detached, the agent carries on in a child process and the parent reports where it is
+    if agent_args.is_present("foreground") {
+        write_started(&path, minutes, None, writer);
+    } else {
+        match agent::detach() {
+            Ok(Some(pid)) => {
+                write_started(&path, minutes, Some(pid), writer);
+                return;
+            }
+            Ok(None) => (),
+            Err(err) => {
+                let _ = fs::remove_file(&path);
+                writer.write_err(&format!("Unable to start the agent: {}\n", err));
+                return;
+            }
+        }
+    }
+
+    let mut agent = Agent::new(key, Duration::from_secs(minutes * 60), Instant::now());
+    let result = agent::serve(&mut agent, &listener);
+    let _ = fs::remove_file(&path);
+    match result {
+        Ok(_) => writer.write_err("Agent locked\n"),
+        Err(err) => writer.write_err(&format!("Agent stopped: {}\n", err)),
+    }
+}
+
+// Shell commands on stdout, as with ssh-agent, so they can be passed to eval
+fn write_started(path: &Path, minutes: u64, pid: Option<u32>, writer: &mut impl OutErr) {
+    writer.write(&format!(
+        "{}={}; export {};\n",
+        AGENT_SOCK_VAR,
+        path.display(),
+        AGENT_SOCK_VAR
+    ));
+    let started = match pid {
+        Some(pid) => format!("Agent started with pid {}", pid),
+        None => String::from("Agent started"),
+    };
+    writer.write_err(&format!(
+        "{}, locking after {} without a request\n",
+        started,
+        count_of(minutes as usize, "minute")
+    ));
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::account::tests::{create_empty_store, get_mock_store};
+    use crate::cmd::CommandType::Agent;
+    use crate::storage::tests::get_test_directory;
+    use crate::tests::mocks::MockOtpWriter;
+    use crate::tests::utils::get_cmd_args;
+    use std::thread;
+
+    fn no_vars(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn serves_the_key_until_locked() {
+        let directory = get_test_directory("agent-command");
+        let path = directory.join("agent.sock");
+        let store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Agent.as_str(),
+            "--foreground",
+            "--socket",
+            path.to_str().unwrap(),
+        ];
+        let agent_args = get_cmd_args(Agent.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let client_path = path.clone();
+        let client = thread::spawn(move || {
+            while !client_path.exists() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            let key = agent::request_key(&client_path).unwrap();
+            agent::lock(&client_path).unwrap();
+            key
+        });
+        run_agent(&agent_args, &store, no_vars, &mut writer);
+
+        assert_eq!(client.join().unwrap().as_ref(), store.get_key());
+        assert!(!path.exists());
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            format!(
+                "OTP_AGENT_SOCK={}; export OTP_AGENT_SOCK;\n",
+                path.display()
+            )
+        );
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Agent started, locking after 15 minutes without a request\nAgent locked\n"
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn requires_an_unlocked_store() {
+        let store = create_empty_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Agent.as_str()];
+        let agent_args = get_cmd_args(Agent.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_agent(&agent_args, &store, no_vars, &mut writer);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "The account store is locked\n"
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn rejects_a_zero_timeout() {
+        let arg_vec = vec!["otp", Agent.as_str(), "--timeout", "0"];
+        let agent_args = get_cmd_args(Agent.as_str(), subcommand(), &arg_vec);
+
+        assert!(agent_args.is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use clap::{arg, command, ArgMatches, Command};
+
+use super::{count_of, write_audit, CommandType};
+use crate::account::AccountStoreOperations;
+use crate::audit::Outcome;
+use crate::utils::format_timestamp;
+use crate::writer::OutErr;
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Audit.as_str())
+        .about("Show the audit log and verify its integrity")
+        .args(&[arg!(-a --account <NAME> "Only show entries for this account").required(false)])
+}
+
+pub fn run_audit(
+    audit_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    writer: &mut impl OutErr,
+) {
+    // Recording the view also seals any failed unlocks waiting to be written
+    write_audit(
+        account_store,
+        writer,
+        CommandType::Audit.as_str(),
+        None,
+        Outcome::Success,
+    );
+
+    let report = match account_store.read_audit_log() {
+        Ok(report) => report,
+        Err(err) => {
+            writer.write_err(&format!("Unable to read the audit log: {}\n", err));
+            return;
+        }
+    };
+
+    let account_name = audit_args.value_of("account");
+    writer.write("Audit log:\n");
+    for entry in report
+        .entries
+        .iter()
+        .filter(|entry| account_name.is_none() || entry.account.as_deref() == account_name)
+    {
+        let account = entry
+            .account
+            .as_ref()
+            .map(|account| format!(" \"{}\"", account))
+            .unwrap_or_default();
+        writer.write(&format!(
+            "{} {} {}{}\n",
+            format_timestamp(entry.timestamp),
+            entry.outcome.as_str(),
+            entry.operation,
+            account
+        ));
+    }
+
+    match report.error {
+        Some(err) => writer.write_err(&format!("Audit log verification failed: {}\n", err)),
+        None => writer.write(&format!(
+            "Audit log verified: {}\n",
+            count_of(report.entries.len(), "entry")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::{create_empty_store, get_mock_store};
+    use crate::account::AccountStore;
+    use crate::audit::AuditEvent;
+    use crate::cmd::CommandType::Audit;
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+
+    fn record(store: &mut AccountStore, operation: &str, account: Option<&str>) {
+        store
+            .audit(AuditEvent {
+                timestamp: 90,
+                operation: String::from(operation),
+                account: account.map(String::from),
+                outcome: Outcome::Success,
+            })
+            .unwrap();
+    }
+
+    fn run(store: &mut AccountStore, args: &[&str]) -> (String, String) {
+        let mut writer = MockOtpWriter::new();
+        let mut arg_vec = vec!["otp", Audit.as_str()];
+        arg_vec.extend_from_slice(args);
+        let audit_args = get_cmd_args(Audit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_audit(&audit_args, store, &mut writer);
+        (
+            String::from_utf8(writer.out).unwrap(),
+            String::from_utf8(writer.err).unwrap(),
+        )
+    }
+
+    #[test]
+    fn shows_and_verifies_the_log() {
+        let mut store = get_mock_store();
+        record(&mut store, "add", Some(ACCOUNT_NAME_1));
+        record(&mut store, "get", Some(ACCOUNT_NAME_1));
+
+        let (out, err) = run(&mut store, &[]);
+
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "Audit log:");
+        assert_eq!(
+            lines[1],
+            format!("1970-01-01 00:01:30 UTC success add \"{}\"", ACCOUNT_NAME_1)
+        );
+        assert!(lines[3].ends_with(" success audit"));
+        assert_eq!(lines[4], "Audit log verified: 3 entries");
+        assert_eq!(err, "");
+    }
+
+    #[test]
+    fn filters_by_account() {
+        let mut store = get_mock_store();
+        record(&mut store, "add", Some(ACCOUNT_NAME_1));
+        record(&mut store, "add", Some(ACCOUNT_NAME_2));
+
+        let (out, _) = run(&mut store, &["-a", ACCOUNT_NAME_2]);
+
+        assert_eq!(
+            out,
+            format!(
+                "Audit log:\n1970-01-01 00:01:30 UTC success add \"{}\"\nAudit log verified: 3 entries\n",
+                ACCOUNT_NAME_2
+            )
+        );
+    }
+
+    #[test]
+    fn includes_failed_unlocks() {
+        let mut store = create_empty_store();
+        store
+            .audit(AuditEvent {
+                timestamp: 60,
+                operation: String::from("unlock"),
+                account: None,
+                outcome: Outcome::Failure,
+            })
+            .unwrap();
+        store.set_pin(PIN, test_kdf_params()).unwrap();
+
+        let (out, _) = run(&mut store, &[]);
+
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[1], "1970-01-01 00:01:00 UTC failure unlock");
+        assert_eq!(lines[3], "Audit log verified: 2 entries");
+    }
+
+    #[test]
+    fn never_records_codes() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+        let arg_vec = vec!["otp", "get", "-a", ACCOUNT_NAME_2];
+        let get_args = get_cmd_args("get", crate::cmd::get::subcommand(), &arg_vec).unwrap();
+        crate::cmd::get::run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+        let code = String::from_utf8(writer.out).unwrap();
+
+        let (out, _) = run(&mut store, &[]);
+
+        assert!(out.contains(&format!("success get \"{}\"", ACCOUNT_NAME_2)));
+        assert!(!out.contains(code.trim()));
+    }
+}
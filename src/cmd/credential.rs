@@ -0,0 +1,282 @@
+use clap::{arg, command, Arg, ArgMatches, Command};
+
+use super::{accepts_key, find_account_name, force_arg, write_audit, CommandType};
+use crate::account::{Account, AccountStoreOperations, OtpType, PRIMARY_CREDENTIAL};
+use crate::audit::Outcome;
+use crate::utils::is_base32_key;
+use crate::writer::{OutErr, ReadLine};
+
+fn account_arg() -> Arg<'static> {
+    arg!(-a --account <NAME> "Account the credential belongs to").required(true)
+}
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Credential.as_str())
+        .about("Manage the other keys an account is enrolled with, such as hardware tokens")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("add")
+                .about("Add a credential with its own key")
+                .args(&[
+                    account_arg(),
+                    arg!(<CREDENTIAL> "Name of the credential, e.g. yubikey"),
+                    arg!(-k --key <KEY> "Secret key")
+                        .required(true)
+                        .validator(is_base32_key),
+                    arg!(-c --hotp "Counter-based HOTP (Time-based TOTP is default)")
+                        .required(false),
+                    force_arg(),
+                ]),
+        )
+        .subcommand(
+            Command::new("remove")
+                .about("Remove a credential")
+                .args(&[account_arg(), arg!(<CREDENTIAL> "Credential to remove")]),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List the credentials of an account")
+                .args(&[account_arg()]),
+        )
+}
+
+pub fn run_credential(
+    credential_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+) {
+    let (subcommand, args) = match credential_args.subcommand() {
+        Some(subcommand) => subcommand,
+        None => {
+            writer.write_err("Unknown credential subcommand\n");
+            return;
+        }
+    };
+    let account_name = match args.value_of("account") {
+        Some(account_name) => account_name,
+        None => {
+            writer.write_err("Account name is required\n");
+            return;
+        }
+    };
+    let account_name = match find_account_name(account_name, account_store, reader, writer) {
+        Some(account_name) => account_name,
+        None => return,
+    };
+    if subcommand == "add"
+        && !accepts_key(
+            args.value_of("key").unwrap_or_default(),
+            args.is_present("force"),
+            account_store,
+            writer,
+        )
+    {
+        write_audit(
+            account_store,
+            writer,
+            "credential-add",
+            Some(&account_name),
+            Outcome::Failure,
+        );
+        return;
+    }
+    let account = match account_store.get_mut(&account_name) {
+        Some(account) => account,
+        None => {
+            writer.write_err(&format!("Account not found: {}\n", account_name));
+            return;
+        }
+    };
+
+    if subcommand == "list" {
+        write_credentials(account, writer);
+        return;
+    }
+
+    let credential = args.value_of("CREDENTIAL").unwrap_or_default();
+    let result = match subcommand {
+        "add" => {
+            let otp_type = match args.is_present("hotp") {
+                true => OtpType::HOTP(Some(0)),
+                false => OtpType::TOTP,
+            };
+            let key = String::from(args.value_of("key").unwrap_or_default());
+            account.add_credential(credential, key, otp_type).map(|_| {
+                format!(
+                    "Credential \"{}\" added to \"{}\"\n",
+                    credential, account_name
+                )
+            })
+        }
+        "remove" => account.remove_credential(credential).map(|_| {
+            format!(
+                "Credential \"{}\" removed from \"{}\"\n",
+                credential, account_name
+            )
+        }),
+        _ => {
+            writer.write_err("Unknown credential subcommand\n");
+            return;
+        }
+    };
+    let message = match result {
+        Ok(message) => message,
+        Err(err) => {
+            writer.write_err(&format!("{}\n", err));
+            write_audit(
+                account_store,
+                writer,
+                &format!("credential-{}", subcommand),
+                Some(&account_name),
+                Outcome::Failure,
+            );
+            return;
+        }
+    };
+
+    let outcome = match account_store.save() {
+        Ok(_) => {
+            writer.write(&message);
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        &format!("credential-{}", subcommand),
+        Some(&account_name),
+        outcome,
+    );
+}
+
+fn describe(otp_type: &OtpType) -> String {
+    match otp_type {
+        OtpType::TOTP => String::from("TOTP"),
+        OtpType::HOTP(counter) => format!("HOTP, counter {}", counter.unwrap_or(0)),
+    }
+}
+
+fn write_credentials(account: &Account, writer: &mut impl OutErr) {
+    writer.write(&format!(
+        "{} ({})\n",
+        PRIMARY_CREDENTIAL,
+        describe(&account.otp_type)
+    ));
+    for credential in &account.credentials {
+        writer.write(&format!(
+            "{} ({})\n",
+            credential.name,
+            describe(&credential.otp_type)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::cmd::CommandType::Credential;
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+
+    fn run(store: &mut impl AccountStoreOperations, arg_vec: &[&str]) -> MockOtpWriter {
+        let mut arg_vec = arg_vec.to_vec();
+        arg_vec.insert(0, "otp");
+        arg_vec.insert(1, Credential.as_str());
+        let credential_args =
+            get_cmd_args(Credential.as_str(), subcommand(), &arg_vec.to_vec()).unwrap();
+        let mut writer = MockOtpWriter::new();
+        run_credential(
+            &credential_args,
+            store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+        );
+        writer
+    }
+
+    #[test]
+    fn adds_lists_and_removes_credentials() {
+        let mut store = get_mock_store();
+
+        let writer = run(
+            &mut store,
+            &["add", "-a", ACCOUNT_NAME_2, "yubikey", "-k", HOTP_KEY, "-c"],
+        );
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "Credential \"yubikey\" added to \"test_account_2\"\n"
+        );
+        let account = store.get(ACCOUNT_NAME_2).unwrap();
+        assert_eq!(account.credentials[0].key.expose(), HOTP_KEY);
+
+        let writer = run(&mut store, &["list", "-a", ACCOUNT_NAME_2]);
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "primary (TOTP)\nyubikey (HOTP, counter 0)\n"
+        );
+
+        let writer = run(&mut store, &["remove", "-a", ACCOUNT_NAME_2, "yubikey"]);
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "Credential \"yubikey\" removed from \"test_account_2\"\n"
+        );
+        assert!(store.get(ACCOUNT_NAME_2).unwrap().credentials.is_empty());
+    }
+
+    #[test]
+    fn refuses_a_duplicate_name() {
+        let mut store = get_mock_store();
+
+        let writer = run(
+            &mut store,
+            &["add", "-a", ACCOUNT_NAME_2, "primary", "-k", HOTP_KEY],
+        );
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Credential already exists: primary\n"
+        );
+        assert!(store.get(ACCOUNT_NAME_2).unwrap().credentials.is_empty());
+    }
+
+    #[test]
+    fn refuses_a_key_used_by_another_credential() {
+        let mut store = get_mock_store();
+        run(
+            &mut store,
+            &["add", "-a", ACCOUNT_NAME_2, "yubikey", "-k", HOTP_KEY],
+        );
+
+        let writer = run(
+            &mut store,
+            &["add", "-a", ACCOUNT_NAME_1, "spare", "-k", HOTP_KEY],
+        );
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "The key is already used by credential \"yubikey\" of \"test_account_2\"\n\
+             Run again with --force to add it anyway\n"
+        );
+        assert!(store.get(ACCOUNT_NAME_1).unwrap().credentials.is_empty());
+    }
+
+    #[test]
+    fn errors_when_removing_a_missing_credential() {
+        let mut store = get_mock_store();
+
+        let writer = run(&mut store, &["remove", "-a", ACCOUNT_NAME_2, "yubikey"]);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Credential not found: yubikey\n"
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+}
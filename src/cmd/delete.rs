@@ -1,19 +1,26 @@
 use clap::{arg, command, ArgMatches, Command};
 
-use super::CommandType;
+use super::{confirm, find_account_name, write_audit, yes_arg, CommandType};
 use crate::account::AccountStoreOperations;
-use crate::writer::OutErr;
+use crate::audit::Outcome;
+use crate::totp::{get_unix_time, GetTime};
+use crate::writer::{OutErr, ReadLine};
 
 pub fn subcommand() -> Command<'static> {
     command!(CommandType::Delete.as_str())
-        .about("Delete an account")
-        .args(&[arg!(-a --account <NAME> "Account name to delete").required(true)])
+        .about("Move an account to the trash")
+        .args(&[
+            arg!(-a --account <NAME> "Account name to delete").required(true),
+            yes_arg(),
+        ])
 }
 
 pub fn run_delete<W>(
     delete_args: &ArgMatches,
     account_store: &mut impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
     writer: &mut W,
+    clock: &impl GetTime,
 ) where
     W: OutErr,
 {
@@ -25,15 +32,44 @@ pub fn run_delete<W>(
         }
     };
 
-    let result = account_store.delete(account_name);
+    let account_name = match find_account_name(account_name, account_store, reader, writer) {
+        Some(account_name) => account_name,
+        None => return,
+    };
 
-    match result {
-        Some(_) => match account_store.save() {
-            Ok(_) => writer.write("Account successfully deleted\n"),
-            Err(err) => writer.write_err(&format!("{}", err)),
-        },
-        None => writer.write_err(&format!("Account not found: {}\n", account_name)),
+    let question = format!("Move account \"{}\" to the trash?", account_name);
+    if !confirm(delete_args, &question, reader, writer) {
+        return;
     }
+
+    let result = account_store.delete(&account_name, get_unix_time(clock));
+
+    let outcome = match result {
+        true => match account_store.save() {
+            Ok(_) => {
+                writer.write(&format!(
+                    "Account \"{}\" moved to the trash\n",
+                    account_name
+                ));
+                Outcome::Success
+            }
+            Err(err) => {
+                writer.write_err(&format!("{}", err));
+                Outcome::Failure
+            }
+        },
+        false => {
+            writer.write_err(&format!("Account not found: {}\n", account_name));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        CommandType::Delete.as_str(),
+        Some(&account_name),
+        outcome,
+    );
 }
 
 #[cfg(test)]
@@ -50,35 +86,160 @@ mod tests {
         let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
-        let arg_vec = vec!["otp", Delete.as_str(), "-a", ACCOUNT_NAME_1];
+        let arg_vec = vec!["otp", Delete.as_str(), "-a", ACCOUNT_NAME_1, "--yes"];
         let delete_args =
             get_cmd_args(CommandType::Delete.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_delete(&delete_args, &mut store, &mut writer);
+        run_delete(
+            &delete_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         assert_eq!(store.get(ACCOUNT_NAME_1), None);
+        assert_eq!(store.list_trash()[0].name, ACCOUNT_NAME_1);
+        assert_eq!(store.list_trash()[0].deleted_at, 90);
 
-        let expected_output = format!("Account successfully deleted\n");
+        let expected_output = format!("Account \"{}\" moved to the trash\n", ACCOUNT_NAME_1);
         assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
         assert_eq!(writer.err, Vec::new());
     }
 
+    #[test]
+    fn deletes_after_confirmation() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Delete.as_str(), "-a", ACCOUNT_NAME_1];
+        let delete_args = get_cmd_args(Delete.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_delete(
+            &delete_args,
+            &mut store,
+            &mut MockOtpReader::interactive("y\n"),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        assert_eq!(store.get(ACCOUNT_NAME_1), None);
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!("Move account \"{}\" to the trash? [y/N] ", ACCOUNT_NAME_1)
+        );
+    }
+
+    #[test]
+    fn does_not_delete_when_not_confirmed() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Delete.as_str(), "-a", ACCOUNT_NAME_1];
+        let delete_args = get_cmd_args(Delete.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_delete(
+            &delete_args,
+            &mut store,
+            &mut MockOtpReader::interactive("\n"),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        assert!(store.get(ACCOUNT_NAME_1).is_some());
+        assert!(store.list_trash().is_empty());
+        assert!(String::from_utf8(writer.err)
+            .unwrap()
+            .ends_with("Cancelled\n"));
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn requires_yes_without_a_terminal() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Delete.as_str(), "-a", ACCOUNT_NAME_1];
+        let delete_args = get_cmd_args(Delete.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_delete(
+            &delete_args,
+            &mut store,
+            &mut MockOtpReader::new("y"),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        assert!(store.get(ACCOUNT_NAME_1).is_some());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Confirmation required, run again with --yes\n"
+        );
+    }
+
     #[test]
     fn does_not_delete_an_account_that_does_not_exist() {
         let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
-        let arg_vec = vec!["otp", Delete.as_str(), "-a", "not_an_account"];
+        let arg_vec = vec!["otp", Delete.as_str(), "-a", "not_an_account", "--yes"];
         let delete_args =
             get_cmd_args(CommandType::Delete.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_delete(&delete_args, &mut store, &mut writer);
+        run_delete(
+            &delete_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let expected_output = format!("Account not found: {}\n", "not_an_account");
         assert_eq!(writer.out, Vec::new());
         assert_eq!(String::from_utf8(writer.err).unwrap(), expected_output);
     }
 
+    #[test]
+    fn deletes_an_account_ignoring_case() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Delete.as_str(), "-a", "TEST_ACCOUNT_1", "--yes"];
+        let delete_args = get_cmd_args(Delete.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_delete(
+            &delete_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        assert_eq!(store.get(ACCOUNT_NAME_1), None);
+        assert!(store.get(ACCOUNT_NAME_2).is_some());
+    }
+
+    #[test]
+    fn does_not_delete_when_several_accounts_match() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Delete.as_str(), "-a", "test", "--yes"];
+        let delete_args = get_cmd_args(Delete.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_delete(
+            &delete_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        assert!(store.get(ACCOUNT_NAME_1).is_some());
+        assert!(store.get(ACCOUNT_NAME_2).is_some());
+        assert_eq!(writer.out, Vec::new());
+    }
+
     #[test]
     fn requires_account_name() {
         let arg_vec = vec!["otp", Delete.as_str()];
@@ -100,16 +261,22 @@ mod tests {
         let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
-        store.set_should_save_error(true);
+        store.fail_saves();
 
-        let arg_vec = vec!["otp", Delete.as_str(), "-a", ACCOUNT_NAME_1];
+        let arg_vec = vec!["otp", Delete.as_str(), "-a", ACCOUNT_NAME_1, "--yes"];
         let delete_args = get_cmd_args(Delete.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_delete(&delete_args, &mut store, &mut writer);
+        run_delete(
+            &delete_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         assert_eq!(
             String::from_utf8(writer.err).unwrap(),
-            "MockAccountStore failed to save"
+            "Storage backend failed to save"
         );
         assert_eq!(writer.out, Vec::new());
     }
@@ -0,0 +1,416 @@
+use clap::{arg, command, ArgMatches, Command};
+
+use super::{get_tags, metadata_args, set_metadata, tag_arg, write_audit, CommandType};
+use crate::account::{AccountStoreOperations, Algorithm, OtpType};
+use crate::audit::Outcome;
+use crate::utils::{is_counter, is_positive_number};
+use crate::writer::OutErr;
+
+const EDIT_ARGS: [&str; 11] = [
+    "hotp",
+    "totp",
+    "counter",
+    "digits",
+    "algorithm",
+    "period",
+    "issuer",
+    "label",
+    "notes",
+    "tag",
+    "untag",
+];
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Edit.as_str())
+        .about("Change the settings and details of an account")
+        .args(&[
+            arg!(-a --account <NAME> "Account name to edit").required(true),
+            arg!(--hotp "Switch to counter-based HOTP")
+                .required(false)
+                .conflicts_with("totp"),
+            arg!(--totp "Switch to time-based TOTP").required(false),
+            arg!(--counter <COUNTER> "HOTP counter")
+                .required(false)
+                .validator(is_counter),
+            arg!(--digits <DIGITS> "Number of digits in a code")
+                .required(false)
+                .possible_values(["6", "7", "8"]),
+            arg!(--algorithm <ALGORITHM> "HMAC algorithm")
+                .required(false)
+                .possible_values(["sha1", "sha256", "sha512"]),
+            arg!(--period <SECONDS> "TOTP time step in seconds")
+                .required(false)
+                .validator(is_positive_number),
+            tag_arg("tag", "Add a tag"),
+            tag_arg("untag", "Remove a tag"),
+        ])
+        .args(metadata_args())
+}
+
+pub fn run_edit(
+    edit_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    writer: &mut impl OutErr,
+) {
+    let account_name = match edit_args.value_of("account") {
+        Some(account_name) => account_name,
+        _ => {
+            writer.write_err("Account name is required\n");
+            return;
+        }
+    };
+
+    if !EDIT_ARGS.iter().any(|name| edit_args.is_present(name)) {
+        writer.write_err("Nothing to change\n");
+        return;
+    }
+
+    let account = match account_store.get(account_name) {
+        Some(account) => account,
+        None => {
+            writer.write_err(&format!("Account not found: {}\n", account_name));
+            return;
+        }
+    };
+
+    // Work out the new type before touching the account, so errors leave it unchanged
+    let counter = edit_args
+        .value_of("counter")
+        .and_then(|value| value.parse::<i32>().ok());
+    let is_hotp = match (edit_args.is_present("hotp"), edit_args.is_present("totp")) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => matches!(account.otp_type, OtpType::HOTP(_)),
+    };
+    let otp_type = match (is_hotp, &account.otp_type) {
+        (true, OtpType::HOTP(current)) => OtpType::HOTP(counter.or(*current)),
+        (true, OtpType::TOTP) => OtpType::HOTP(Some(counter.unwrap_or(0))),
+        (false, _) if counter.is_some() => {
+            writer.write_err("A counter can only be set on HOTP accounts\n");
+            return;
+        }
+        (false, _) => OtpType::TOTP,
+    };
+    if is_hotp && edit_args.is_present("period") {
+        writer.write_err("A period can only be set on TOTP accounts\n");
+        return;
+    }
+
+    let account = match account_store.get_mut(account_name) {
+        Some(account) => account,
+        None => return,
+    };
+    account.otp_type = otp_type;
+    if let Some(digits) = edit_args
+        .value_of("digits")
+        .and_then(|value| value.parse::<u32>().ok())
+    {
+        account.digits = Some(digits);
+    }
+    if let Some(algorithm) = edit_args
+        .value_of("algorithm")
+        .and_then(Algorithm::from_str)
+    {
+        account.algorithm = Some(algorithm);
+    }
+    if let Some(period) = edit_args
+        .value_of("period")
+        .and_then(|value| value.parse::<u64>().ok())
+    {
+        account.period = Some(period);
+    }
+    set_metadata(edit_args, account);
+    account.add_tags(&get_tags(edit_args, "tag"));
+    account.remove_tags(&get_tags(edit_args, "untag"));
+
+    let outcome = match account_store.save() {
+        Ok(_) => {
+            writer.write(&format!(
+                "Account \"{}\" successfully updated\n",
+                account_name
+            ));
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        CommandType::Edit.as_str(),
+        Some(account_name),
+        outcome,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::cmd::CommandType::Edit;
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+
+    #[test]
+    fn switches_totp_to_hotp_with_counter() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Edit.as_str(),
+            "-a",
+            ACCOUNT_NAME_2,
+            "--hotp",
+            "--counter",
+            "7",
+        ];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        let account = store.get(ACCOUNT_NAME_2).unwrap();
+        assert_eq!(account.otp_type, OtpType::HOTP(Some(7)));
+        assert_eq!(account.get_algorithm(), Algorithm::SHA1);
+        assert_eq!(account.key.expose(), "key-2");
+
+        let expected_output = format!("Account \"{}\" successfully updated\n", ACCOUNT_NAME_2);
+        assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn switches_hotp_to_totp() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Edit.as_str(), "-a", ACCOUNT_NAME_1, "--totp"];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        assert_eq!(store.get(ACCOUNT_NAME_1).unwrap().otp_type, OtpType::TOTP);
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn sets_the_hotp_counter() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Edit.as_str(),
+            "-a",
+            ACCOUNT_NAME_1,
+            "--counter",
+            "42",
+        ];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        assert_eq!(
+            store.get(ACCOUNT_NAME_1).unwrap().otp_type,
+            OtpType::HOTP(Some(42))
+        );
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn sets_code_settings_and_metadata() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Edit.as_str(),
+            "-a",
+            ACCOUNT_NAME_2,
+            "--digits",
+            "8",
+            "--algorithm",
+            "sha512",
+            "--period",
+            "60",
+            "--issuer",
+            "Pets",
+            "--notes",
+            "Shared with the vet",
+        ];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        let account = store.get(ACCOUNT_NAME_2).unwrap();
+        assert_eq!(account.otp_type, OtpType::TOTP);
+        assert_eq!(account.digits, Some(8));
+        assert_eq!(account.algorithm, Some(Algorithm::SHA512));
+        assert_eq!(account.period, Some(60));
+        assert_eq!(account.issuer, Some(String::from("Pets")));
+        assert_eq!(account.notes, Some(String::from("Shared with the vet")));
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn adds_and_removes_tags() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+        store
+            .get_mut(ACCOUNT_NAME_2)
+            .unwrap()
+            .add_tags(&["personal", "prod"]);
+
+        let arg_vec = vec![
+            "otp",
+            Edit.as_str(),
+            "-a",
+            ACCOUNT_NAME_2,
+            "--tag",
+            "billing",
+            "--untag",
+            "personal",
+        ];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        assert_eq!(
+            store.get(ACCOUNT_NAME_2).unwrap().tags,
+            vec!["billing", "prod"]
+        );
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn clears_metadata_with_an_empty_value() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+        store.get_mut(ACCOUNT_NAME_2).unwrap().issuer = Some(String::from("Pets"));
+
+        let arg_vec = vec!["otp", Edit.as_str(), "-a", ACCOUNT_NAME_2, "--issuer", ""];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        assert_eq!(store.get(ACCOUNT_NAME_2).unwrap().issuer, None);
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn does_not_set_a_counter_on_totp_accounts() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Edit.as_str(),
+            "-a",
+            ACCOUNT_NAME_2,
+            "--counter",
+            "3",
+            "--digits",
+            "8",
+        ];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        let account = store.get(ACCOUNT_NAME_2).unwrap();
+        assert_eq!(account.otp_type, OtpType::TOTP);
+        assert_eq!(account.digits, None);
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "A counter can only be set on HOTP accounts\n"
+        );
+    }
+
+    #[test]
+    fn does_not_set_a_period_on_hotp_accounts() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Edit.as_str(), "-a", ACCOUNT_NAME_1, "--period", "60"];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        assert_eq!(store.get(ACCOUNT_NAME_1).unwrap().period, None);
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "A period can only be set on TOTP accounts\n"
+        );
+    }
+
+    #[test]
+    fn requires_a_change() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Edit.as_str(), "-a", ACCOUNT_NAME_1];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Nothing to change\n"
+        );
+    }
+
+    #[test]
+    fn rejects_conflicting_types() {
+        let arg_vec = vec![
+            "otp",
+            Edit.as_str(),
+            "-a",
+            ACCOUNT_NAME_1,
+            "--hotp",
+            "--totp",
+        ];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec);
+
+        assert!(edit_args.is_err());
+    }
+
+    #[test]
+    fn errors_when_account_does_not_exist() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Edit.as_str(), "-a", "not_an_account", "--totp"];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Account not found: not_an_account\n"
+        );
+    }
+
+    #[test]
+    fn records_errors_on_save_failure() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        store.fail_saves();
+
+        let arg_vec = vec!["otp", Edit.as_str(), "-a", ACCOUNT_NAME_1, "--totp"];
+        let edit_args = get_cmd_args(Edit.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_edit(&edit_args, &mut store, &mut writer);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Storage backend failed to save"
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+}
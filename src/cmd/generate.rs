@@ -34,6 +34,7 @@ mod tests {
     use crate::tests::utils::get_cmd_args;
 
     #[test]
+    #[allow(clippy::needless_as_bytes)]
     fn generates_a_20_byte_secret_for_hotp() {
         let mut writer = MockOtpWriter::new();
 
@@ -48,6 +49,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_as_bytes)]
     fn generates_a_32_byte_secret_for_totp() {
         let mut writer = MockOtpWriter::new();
 
@@ -1,22 +1,26 @@
 use clap::{arg, command, ArgMatches, Command};
 
-use super::CommandType;
-use crate::account::{AccountStoreOperations, OtpType};
+use super::{find_account_name, write_audit, CommandType};
+use crate::account::{AccountStoreOperations, OtpType, PRIMARY_CREDENTIAL};
+use crate::audit::Outcome;
 use crate::hotp::get_hotp;
-use crate::totp::{get_totp, get_totp_moving_factor, GetTime};
-use crate::writer::OutErr;
+use crate::totp::{get_moving_factor, get_totp, get_unix_time, GetTime};
+use crate::writer::{OutErr, ReadLine};
 
 pub fn subcommand() -> Command<'static> {
     command!(CommandType::Get.as_str())
         .about("Get a one-time password")
         .args(&[
             arg!(-a --account <NAME> "Account name to get one-time password for").required(true),
+            arg!(--credential <CREDENTIAL> "Credential to get the password for, primary by default")
+                .required(false),
         ])
 }
 
 pub fn run_get<W>(
     get_args: &ArgMatches,
     account_store: &mut impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
     writer: &mut W,
     clock: &impl GetTime,
 ) where
@@ -30,27 +34,73 @@ pub fn run_get<W>(
         }
     };
 
-    let account = account_store.get(account_name);
+    let account_name = match find_account_name(account_name, account_store, reader, writer) {
+        Some(account_name) => account_name,
+        None => return,
+    };
+
+    let credential = get_args
+        .value_of("credential")
+        .filter(|credential| *credential != PRIMARY_CREDENTIAL);
+    let credential_account;
+    let account = match (account_store.get(&account_name), credential) {
+        (Some(account), Some(credential)) => match account.get_credential(credential) {
+            Some(account) => {
+                credential_account = account;
+                Some(&credential_account)
+            }
+            None => {
+                writer.write_err(&format!("Credential not found: {}\n", credential));
+                return;
+            }
+        },
+        (account, _) => account,
+    };
 
     match account {
         None => writer.write_err(&format!("Account not found: {}\n", account_name)),
         Some(account) => {
+            let digits = account.get_digits() as usize;
             let (otp, new_counter) = match account.otp_type {
-                OtpType::TOTP => (get_totp(&account.key, get_totp_moving_factor(clock)), None),
+                OtpType::TOTP => (
+                    get_totp(account, get_moving_factor(clock, account.get_period())),
+                    None,
+                ),
                 OtpType::HOTP(maybe_counter) => {
                     let counter = maybe_counter.unwrap_or(0);
-                    (get_hotp(&account.key, counter), Some(counter + 1))
+                    (get_hotp(account, counter), Some(counter + 1))
                 }
             };
 
-            if new_counter.is_some() {
-                account_store.set_counter(account_name, new_counter.unwrap_or(1));
+            match (new_counter, credential) {
+                (Some(counter), None) => account_store.set_counter(&account_name, counter),
+                (Some(counter), Some(credential)) => {
+                    if let Some(account) = account_store.get_mut(&account_name) {
+                        account.set_credential_counter(credential, counter);
+                    }
+                }
+                (None, _) => (),
             }
+            account_store.record_use(&account_name, get_unix_time(clock));
 
-            match account_store.save() {
-                Ok(_) => writer.write(&format!("{:0>6}\n", otp)),
-                Err(err) => writer.write_err(&format!("Unable to save account: {}", err)),
-            }
+            // The code itself never goes in the audit log
+            let outcome = match account_store.save() {
+                Ok(_) => {
+                    writer.write(&format!("{:0>width$}\n", otp, width = digits));
+                    Outcome::Success
+                }
+                Err(err) => {
+                    writer.write_err(&format!("Unable to save account: {}", err));
+                    Outcome::Failure
+                }
+            };
+            write_audit(
+                account_store,
+                writer,
+                CommandType::Get.as_str(),
+                Some(&account_name),
+                outcome,
+            );
         }
     }
 }
@@ -65,6 +115,7 @@ mod tests {
     use crate::tests::utils::get_cmd_args;
 
     #[test]
+    #[allow(clippy::useless_format)]
     fn gets_totp_for_account() {
         let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
@@ -72,7 +123,13 @@ mod tests {
         let arg_vec = vec!["otp", Get.as_str(), "-a", ACCOUNT_NAME_2];
         let get_args = get_cmd_args(CommandType::Get.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_get(&get_args, &mut store, &mut writer, &MockClock::new());
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let expected_output = format!("249961\n");
         assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
@@ -80,6 +137,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_format)]
     fn gets_hotp_for_account() {
         let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
@@ -87,13 +145,41 @@ mod tests {
         let arg_vec = vec!["otp", Get.as_str(), "-a", ACCOUNT_NAME_1];
         let get_args = get_cmd_args(CommandType::Get.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_get(&get_args, &mut store, &mut writer, &MockClock::new());
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let expected_output = format!("543440\n");
         assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
         assert_eq!(writer.err, Vec::new());
     }
 
+    #[test]
+    fn gets_otp_with_account_digits() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+        store.get_mut(ACCOUNT_NAME_1).unwrap().digits = Some(8);
+
+        let arg_vec = vec!["otp", Get.as_str(), "-a", ACCOUNT_NAME_1];
+        let get_args = get_cmd_args(Get.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        let output = String::from_utf8(writer.out).unwrap();
+        assert_eq!(output.len(), 9);
+        assert!(output.ends_with("543440\n"), "{}", output);
+    }
+
     #[test]
     fn requires_account_name() {
         let arg_vec = vec!["otp", Get.as_str()];
@@ -118,7 +204,13 @@ mod tests {
         let arg_vec = vec!["otp", Get.as_str(), "-a", "not_an_account"];
         let get_args = get_cmd_args(CommandType::Get.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_get(&get_args, &mut store, &mut writer, &MockClock::new());
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let expected_output = format!("Account not found: {}\n", "not_an_account");
         assert_eq!(writer.out, Vec::new());
@@ -126,6 +218,121 @@ mod tests {
     }
 
     #[test]
+    fn gets_account_ignoring_case() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Get.as_str(), "-a", "TEST_ACCOUNT_2"];
+        let get_args = get_cmd_args(Get.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        assert_eq!(String::from_utf8(writer.out).unwrap(), "249961\n");
+        assert_eq!(store.get(ACCOUNT_NAME_2).unwrap().use_count, 1);
+    }
+
+    #[test]
+    fn suggests_close_account_names() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Get.as_str(), "-a", "test_acount_2"];
+        let get_args = get_cmd_args(Get.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        let expected_output = format!(
+            "Account not found: test_acount_2\nDid you mean {}, {}?\n",
+            ACCOUNT_NAME_2, ACCOUNT_NAME_1
+        );
+        assert_eq!(String::from_utf8(writer.err).unwrap(), expected_output);
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn does_not_guess_between_several_matches() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Get.as_str(), "-a", "test_acc"];
+        let get_args = get_cmd_args(Get.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new("2"),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        let expected_output = format!(
+            "Several accounts match test_acc: {}, {}\n",
+            ACCOUNT_NAME_1, ACCOUNT_NAME_2
+        );
+        assert_eq!(String::from_utf8(writer.err).unwrap(), expected_output);
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn picks_between_several_matches_at_a_terminal() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Get.as_str(), "-a", "test_acc"];
+        let get_args = get_cmd_args(Get.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::interactive("2\n"),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        let expected_prompt = format!(
+            "Several accounts match test_acc:\n1) {}\n2) {}\nChoose an account: ",
+            ACCOUNT_NAME_1, ACCOUNT_NAME_2
+        );
+        assert_eq!(String::from_utf8(writer.err).unwrap(), expected_prompt);
+        assert_eq!(String::from_utf8(writer.out).unwrap(), "249961\n");
+    }
+
+    #[test]
+    fn rejects_invalid_picks() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Get.as_str(), "-a", "test_acc"];
+        let get_args = get_cmd_args(Get.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::interactive("3\n"),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        assert!(String::from_utf8(writer.err)
+            .unwrap()
+            .ends_with("No account chosen\n"));
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    #[allow(clippy::useless_format)]
     fn increments_hotp_counter() {
         let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
@@ -133,7 +340,13 @@ mod tests {
         let arg_vec = vec!["otp", Get.as_str(), "-a", ACCOUNT_NAME_1];
         let get_args = get_cmd_args(CommandType::Get.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_get(&get_args, &mut store, &mut writer, &MockClock::new());
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let account = store.get(ACCOUNT_NAME_1).unwrap();
         let otp_type = &account.otp_type;
@@ -143,7 +356,13 @@ mod tests {
         assert_eq!(writer.err, Vec::new());
 
         let mut writer2 = MockOtpWriter::new();
-        run_get(&get_args, &mut store, &mut writer2, &MockClock::new());
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer2,
+            &MockClock::new(),
+        );
 
         let account = store.get(ACCOUNT_NAME_1).unwrap();
         let otp_type = &account.otp_type;
@@ -154,7 +373,13 @@ mod tests {
         assert_eq!(writer2.err, Vec::new());
 
         let mut writer3 = MockOtpWriter::new();
-        run_get(&get_args, &mut store, &mut writer3, &MockClock::new());
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer3,
+            &MockClock::new(),
+        );
 
         let account = store.get(ACCOUNT_NAME_1).unwrap();
         let otp_type = &account.otp_type;
@@ -165,21 +390,120 @@ mod tests {
         assert_eq!(writer3.err, Vec::new());
     }
 
+    #[test]
+    fn gets_a_credential_and_increments_its_counter() {
+        let mut store = get_mock_store();
+        store
+            .get_mut(ACCOUNT_NAME_2)
+            .unwrap()
+            .add_credential("yubikey", String::from(HOTP_KEY), OtpType::HOTP(Some(0)))
+            .unwrap();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Get.as_str(),
+            "-a",
+            ACCOUNT_NAME_2,
+            "--credential",
+            "yubikey",
+        ];
+        let get_args = get_cmd_args(Get.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        let account = store.get(ACCOUNT_NAME_2).unwrap();
+        assert_eq!(account.otp_type, OtpType::TOTP);
+        assert_eq!(account.credentials[0].otp_type, OtpType::HOTP(Some(1)));
+        assert_eq!(String::from_utf8(writer.out).unwrap(), "052123\n");
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn does_not_get_when_credential_does_not_exist() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Get.as_str(),
+            "-a",
+            ACCOUNT_NAME_2,
+            "--credential",
+            "yubikey",
+        ];
+        let get_args = get_cmd_args(Get.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Credential not found: yubikey\n"
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn records_account_use() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Get.as_str(), "-a", ACCOUNT_NAME_2];
+        let get_args = get_cmd_args(Get.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        let account = store.get(ACCOUNT_NAME_2).unwrap();
+        assert_eq!(account.last_used_at, Some(90));
+        assert_eq!(account.use_count, 2);
+    }
+
     #[test]
     fn records_errors_on_save_failure() {
         let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
-        store.set_should_save_error(true);
+        store.fail_saves();
 
         let arg_vec = vec!["otp", Get.as_str(), "-a", ACCOUNT_NAME_1];
         let get_args = get_cmd_args(Get.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_get(&get_args, &mut store, &mut writer, &MockClock::new());
+        run_get(
+            &get_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         assert_eq!(
             String::from_utf8(writer.err).unwrap(),
-            "Unable to save account: MockAccountStore failed to save"
+            "Unable to save account: Storage backend failed to save"
         );
         assert_eq!(writer.out, Vec::new());
     }
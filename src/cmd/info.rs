@@ -0,0 +1,171 @@
+use clap::{arg, command, ArgMatches, Command};
+
+use super::CommandType;
+use crate::account::{AccountStoreOperations, OtpType};
+use crate::utils::format_timestamp;
+use crate::writer::OutErr;
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Info.as_str())
+        .about("Show account details without the secret key")
+        .args(&[arg!(-a --account <NAME> "Account name to show").required(true)])
+}
+
+pub fn run_info(
+    info_args: &ArgMatches,
+    account_store: &impl AccountStoreOperations,
+    writer: &mut impl OutErr,
+) {
+    let account_name = match info_args.value_of("account") {
+        Some(account_name) => account_name,
+        _ => {
+            writer.write_err("Account name is required\n");
+            return;
+        }
+    };
+
+    let account = match account_store.get(account_name) {
+        Some(account) => account,
+        None => {
+            writer.write_err(&format!("Account not found: {}\n", account_name));
+            return;
+        }
+    };
+
+    writer.write(&format!("Account: {}\n", account_name));
+    match account.otp_type {
+        OtpType::TOTP => writer.write("Type: TOTP\n"),
+        OtpType::HOTP(counter) => {
+            writer.write(&format!("Type: HOTP\nCounter: {}\n", counter.unwrap_or(0)))
+        }
+    }
+    writer.write(&format!(
+        "Algorithm: {}\nDigits: {}\n",
+        account.get_algorithm().as_str(),
+        account.get_digits()
+    ));
+    if account.otp_type == OtpType::TOTP {
+        writer.write(&format!("Period: {} seconds\n", account.get_period()));
+    }
+    // Fields that were never set are left out
+    for (field, value) in [
+        ("Issuer", &account.issuer),
+        ("Label", &account.label),
+        ("Notes", &account.notes),
+    ] {
+        if let Some(value) = value {
+            writer.write(&format!("{}: {}\n", field, value));
+        }
+    }
+    if !account.tags.is_empty() {
+        writer.write(&format!("Tags: {}\n", account.tags.join(", ")));
+    }
+    if !account.recovery_codes.is_empty() {
+        writer.write(&format!(
+            "Recovery codes: {} of {} left\n",
+            account.remaining_recovery_codes(),
+            account.recovery_codes.len()
+        ));
+    }
+    if !account.credentials.is_empty() {
+        let names: Vec<&str> = account.keys().into_iter().map(|(name, _)| name).collect();
+        writer.write(&format!("Credentials: {}\n", names.join(", ")));
+    }
+    if let Some(previous_key) = &account.previous_key {
+        writer.write(&format!(
+            "Previous key accepted until: {}\n",
+            format_timestamp(previous_key.expires_at)
+        ));
+    }
+    let created = account
+        .created_at
+        .map(format_timestamp)
+        .unwrap_or_else(|| String::from("unknown"));
+    writer.write(&format!("Created: {}\n", created));
+    let last_used = account
+        .last_used_at
+        .map(format_timestamp)
+        .unwrap_or_else(|| String::from("never"));
+    writer.write(&format!("Last used: {}\n", last_used));
+    writer.write(&format!("Uses: {}\n", account.use_count));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::account::Account;
+    use crate::cmd::CommandType::Info;
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+
+    #[test]
+    fn shows_account_metadata() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let mut account = Account::new(String::from(TOTP_KEY), OtpType::TOTP);
+        account.issuer = Some(String::from("Pets"));
+        account.label = Some(String::from("jane@pets.com"));
+        account.notes = Some(String::from("Shared with the vet"));
+        account.created_at = Some(1646143509);
+        account.add_tags(&["personal", "pets"]);
+        account.add_recovery_codes(&["aaaa-1111", "bbbb-2222"]);
+        account.use_recovery_code(None, 1646143569).unwrap();
+        store.add(String::from(ACCOUNT_NAME_3), account);
+        store.record_use(ACCOUNT_NAME_3, 1646143569);
+
+        let arg_vec = vec!["otp", Info.as_str(), "-a", ACCOUNT_NAME_3];
+        let info_args = get_cmd_args(Info.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_info(&info_args, &store, &mut writer);
+
+        let expected_output = format!(
+            "Account: {}\nType: TOTP\nAlgorithm: sha256\nDigits: 6\nPeriod: 30 seconds\nIssuer: Pets\nLabel: jane@pets.com\n\
+             Notes: Shared with the vet\nTags: personal, pets\nRecovery codes: 1 of 2 left\nCreated: 2022-03-01 14:05:09 UTC\n\
+             Last used: 2022-03-01 14:06:09 UTC\nUses: 1\n",
+            ACCOUNT_NAME_3
+        );
+        let output = String::from_utf8(writer.out).unwrap();
+        assert_eq!(output, expected_output);
+        assert!(!output.contains(TOTP_KEY));
+        assert!(!output.contains("bbbb-2222"));
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn shows_hotp_counter_and_missing_metadata() {
+        let store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Info.as_str(), "-a", ACCOUNT_NAME_1];
+        let info_args = get_cmd_args(Info.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_info(&info_args, &store, &mut writer);
+
+        let expected_output = format!(
+            "Account: {}\nType: HOTP\nCounter: 0\nAlgorithm: sha1\nDigits: 6\nCreated: unknown\nLast used: never\nUses: 0\n",
+            ACCOUNT_NAME_1
+        );
+        assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn errors_when_account_does_not_exist() {
+        let store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Info.as_str(), "-a", "not_an_account"];
+        let info_args = get_cmd_args(Info.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_info(&info_args, &store, &mut writer);
+
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Account not found: not_an_account\n"
+        );
+    }
+}
@@ -1,45 +1,121 @@
 use clap::{arg, command, ArgMatches, Command};
 
-use super::CommandType;
+use super::{
+    count_of, get_kdf_params, get_listing, get_pin_policy, kdf_args, listing_arg, pin_arg,
+    pin_policy_args, read_new_pin, write_audit, write_listing, write_pin_strength, CommandType,
+};
 use crate::account::AccountStoreOperations;
-use crate::crypto::encrypt_pw;
-use crate::writer::OutErr;
+use crate::audit::Outcome;
+use crate::crypto::KdfParams;
+use crate::policy::{PinPolicy, FREE_UNLOCK_ATTEMPTS};
+use crate::writer::{OutErr, ReadLine};
 
 pub fn subcommand() -> Command<'static> {
     command!(CommandType::Init.as_str())
         .about("Initialize a new account store")
-        .args(&[arg!(-p --pin <PIN> "4-6 character secret pin").required(true)])
+        .args(&[
+            pin_arg("Secret pin or passphrase, deprecated in favour of the prompt"),
+            arg!(--backend <BACKEND> "Where to keep the accounts")
+                .required(false)
+                .possible_values(["file", "sqlite"]),
+            listing_arg(),
+            arg!(--"wipe-after" <FAILURES> "Destroy the store after this many failed unlocks in a row")
+                .required(false)
+                .validator(is_wipe_limit),
+        ])
+        .args(pin_policy_args())
+        .args(kdf_args())
 }
 
-pub fn run_init<W>(
+// Wiping on the first typo would be too easy
+fn is_wipe_limit(value: &str) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(failures) if failures >= FREE_UNLOCK_ATTEMPTS => Ok(()),
+        _ => Err(format!(
+            "the value must be at least {}",
+            FREE_UNLOCK_ATTEMPTS
+        )),
+    }
+}
+
+pub fn run_init<R, W>(
     init_args: &ArgMatches,
     account_store: &mut impl AccountStoreOperations,
+    reader: &mut R,
     writer: &mut W,
 ) where
+    R: ReadLine,
     W: OutErr,
 {
-    let pin = match init_args.value_of("pin") {
-        Some(pin) => pin,
-        _ => {
-            writer.write_err("Pin is required\n");
-            return;
-        }
-    };
+    // The backend is opened before a new store is initialized and can't change afterwards
+    if init_args.is_present("backend") && account_store.is_initialized() {
+        writer.write_err("The storage backend can only be chosen for a new account store\n");
+        return;
+    }
 
-    let encrypted_pin = match encrypt_pw(pin) {
-        Ok(encrypted_pin) => encrypted_pin,
-        Err(e) => {
-            writer.write_err(&format!("Error encrypting password {}\n", e));
+    let policy = match get_pin_policy(init_args, PinPolicy::default()) {
+        Ok(policy) => policy,
+        Err(err) => {
+            writer.write_err(&format!("{}\n", err));
             return;
         }
     };
+    let pin = match read_new_pin(init_args, &policy.mode, reader, writer) {
+        Some(pin) => pin,
+        None => return,
+    };
+    let pin = pin.expose().as_str();
+    if let Err(err) = policy.validate(pin) {
+        writer.write_err(&format!("{}\n", err));
+        return;
+    }
 
-    account_store.set_secrets(&encrypted_pin);
-
-    match account_store.save() {
-        Ok(_) => writer.write("Client successfully initialized\n"),
-        Err(err) => writer.write_err(&format!("{}\n", err)),
+    account_store.set_pin_policy(policy);
+    let listing = get_listing(init_args);
+    if let Some(listing) = listing {
+        account_store.set_listing(listing);
+    }
+    account_store.set_wipe_after(
+        init_args
+            .value_of("wipe-after")
+            .and_then(|failures| failures.parse().ok()),
+    );
+    let kdf = get_kdf_params(init_args, KdfParams::default());
+    if let Err(err) = account_store.set_pin(pin, kdf) {
+        writer.write_err(&format!("{}\n", err));
+        return;
     }
+
+    let outcome = match account_store.save() {
+        Ok(_) => {
+            write_pin_strength(pin, writer);
+            writer.write("Client successfully initialized\n");
+            if let Some(listing) = listing {
+                write_listing(listing, writer);
+            }
+            if let Some(failures) = account_store.get_wipe_after() {
+                writer.write(&format!(
+                    "The account store will be destroyed after {} in a row\n",
+                    count_of(failures as usize, "failed unlock")
+                ));
+            }
+            if account_store.has_keyfile() {
+                writer.write("The keyfile will be required to unlock the account store\n");
+            }
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}\n", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        CommandType::Init.as_str(),
+        None,
+        outcome,
+    );
 }
 
 #[cfg(test)]
@@ -47,41 +123,247 @@ mod tests {
     use super::*;
     use crate::account::tests::{create_empty_store, get_mock_store};
     use crate::cmd::CommandType::Init;
+    use crate::crypto::{hash_keyfile, KdfVariant};
+    use crate::policy::{Listing, PinMode};
     use crate::tests::constants::*;
     use crate::tests::mocks::*;
     use crate::tests::utils::get_cmd_args;
 
+    #[test]
+    fn rejects_a_kdf_memory_cost_that_overflows() {
+        let arg_vec = vec!["otp", Init.as_str(), "--kdf-memory", "4194304"];
+
+        assert!(get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).is_err());
+    }
+
     #[test]
     fn initializes_a_new_account_store() {
         let mut store = create_empty_store();
         let mut writer = MockOtpWriter::new();
 
-        let arg_vec = vec!["otp", Init.as_str(), "-p", PIN];
+        let arg_vec = vec!["otp", Init.as_str(), "--kdf-memory", "1"];
         let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
 
         assert!(!store.is_initialized());
 
-        run_init(&init_args, &mut store, &mut writer);
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
 
-        let expected_output = format!("Client successfully initialized\n");
+        let expected_output =
+            "Estimated strength: very weak (20 bits)\nClient successfully initialized\n"
+                .to_string();
         assert!(store.is_initialized());
         assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
-        assert_eq!(writer.err, Vec::new());
+        assert_eq!(String::from_utf8(writer.err).unwrap(), NEW_PIN_PROMPTS);
     }
 
     #[test]
-    fn requires_pin() {
-        let arg_vec = vec!["otp", Init.as_str()];
+    fn records_kdf_params() {
+        let mut store = create_empty_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Init.as_str(),
+            "--kdf-memory",
+            "2",
+            "--kdf-time",
+            "1",
+            "--kdf-lanes",
+            "2",
+        ];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert_eq!(
+            store.get_kdf_params(),
+            KdfParams {
+                variant: KdfVariant::Argon2id,
+                mem_cost: 2048,
+                time_cost: 1,
+                lanes: 2,
+            }
+        );
+        assert_eq!(String::from_utf8(writer.err).unwrap(), NEW_PIN_PROMPTS);
+    }
+
+    #[test]
+    fn initializes_with_a_passphrase() {
+        let mut store = create_empty_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Init.as_str(),
+            "--mode",
+            "passphrase",
+            "--kdf-memory",
+            "1",
+        ];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[PASSPHRASE, PASSPHRASE]);
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert!(store.validate_pin(PASSPHRASE));
+        assert_eq!(store.get_pin_policy(), PinPolicy::new(PinMode::Passphrase));
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "Estimated strength: very strong (133 bits)\nClient successfully initialized\n"
+        );
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            NEW_PASSPHRASE_PROMPTS
+        );
+    }
+
+    #[test]
+    fn enforces_the_pin_policy() {
+        let mut store = create_empty_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Init.as_str(),
+            "--allow",
+            "digits",
+            "--min-length",
+            "4",
+        ];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&["12ab", "12ab"]);
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert!(!store.is_initialized());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!("{}PIN may only contain digits\n", NEW_PIN_PROMPTS)
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn rejects_a_pin_outside_length_limits() {
+        let mut store = create_empty_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Init.as_str(), "--max-length", "6"];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&["12345678", "12345678"]);
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert!(!store.is_initialized());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!(
+                "{}PIN must be between 4 and 6 characters\n",
+                NEW_PIN_PROMPTS
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_min_length_above_max_length() {
+        let mut store = create_empty_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Init.as_str(), "--min-length", "8"];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Minimum length must not be greater than maximum length\n"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_kdf_params() {
+        let arg_vec = vec!["otp", Init.as_str(), "--kdf-time", "0"];
         let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec);
 
         assert!(init_args.is_err());
-
-        let err = init_args.unwrap_err();
-        assert!(err
+        assert!(init_args
+            .unwrap_err()
             .to_string()
-            .contains("The following required arguments were not provided:"));
+            .contains("the value must be a positive number"));
+    }
+
+    #[test]
+    fn reads_the_pin_without_echo() {
+        let mut store = create_empty_store();
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Init.as_str(), "--kdf-memory", "1"];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert_eq!(reader.secret_reads, 2);
+        assert!(store.validate_pin(PIN));
+    }
+
+    #[test]
+    fn rejects_mismatched_pins() {
+        let mut store = create_empty_store();
+        let mut reader = MockOtpReader::script(&[PIN, NEW_PIN]);
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Init.as_str()];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
 
-        assert!(err.to_string().contains("--pin <PIN>"));
+        assert!(!store.is_initialized());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!("{}The pins do not match\n", NEW_PIN_PROMPTS)
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn stops_when_no_pin_is_entered() {
+        let mut store = create_empty_store();
+        let mut reader = MockOtpReader::script(&[]);
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Init.as_str()];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert!(!store.is_initialized());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Enter a new pin:\nNo pin entered\n"
+        );
+    }
+
+    #[test]
+    fn warns_about_a_pin_on_the_command_line() {
+        let mut store = create_empty_store();
+        let mut reader = MockOtpReader::script(&[]);
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Init.as_str(), "-p", PIN, "--kdf-memory", "1"];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert!(store.validate_pin(PIN));
+        assert_eq!(reader.secret_reads, 0);
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Warning: -p is deprecated as it leaves the pin in shell history, leave it out to be prompted\n"
+        );
     }
 
     #[test]
@@ -89,15 +371,115 @@ mod tests {
         let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
-        store.set_should_save_error(true);
+        store.fail_saves();
 
-        let arg_vec = vec!["otp", Init.as_str(), "-p", PIN];
+        let arg_vec = vec!["otp", Init.as_str(), "--kdf-memory", "1"];
         let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_init(&init_args, &mut store, &mut writer);
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
 
-        let expected_output = format!("MockAccountStore failed to save\n");
+        let expected_output = format!("{}Storage backend failed to save\n", NEW_PIN_PROMPTS);
         assert_eq!(writer.out, Vec::new());
         assert_eq!(String::from_utf8(writer.err).unwrap(), expected_output);
     }
+
+    #[test]
+    fn does_not_change_the_backend_of_an_existing_store() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Init.as_str(), "--backend", "sqlite"];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "The storage backend can only be chosen for a new account store\n"
+        );
+    }
+
+    #[test]
+    fn keeps_an_index_of_names_when_asked() {
+        let mut store = create_empty_store();
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Init.as_str(),
+            "--listing",
+            "index",
+            "--kdf-memory",
+            "1",
+        ];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        let store = store.reopen();
+        assert_eq!(store.get_listing(), Listing::Index);
+        assert_eq!(store.list_names().unwrap(), Vec::<String>::new());
+        assert!(String::from_utf8(writer.out).unwrap().ends_with(
+            "Client successfully initialized\nAccount names can be listed without the pin\n"
+        ));
+    }
+
+    #[test]
+    fn opts_in_to_wiping_after_failed_unlocks() {
+        let mut store = create_empty_store();
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Init.as_str(),
+            "--wipe-after",
+            "10",
+            "--kdf-memory",
+            "1",
+        ];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert_eq!(store.reopen().get_wipe_after(), Some(10));
+        assert!(String::from_utf8(writer.out)
+            .unwrap()
+            .ends_with("The account store will be destroyed after 10 failed unlocks in a row\n"));
+    }
+
+    #[test]
+    fn rejects_wiping_after_too_few_failures() {
+        let arg_vec = vec!["otp", Init.as_str(), "--wipe-after", "2"];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec);
+
+        assert!(init_args
+            .unwrap_err()
+            .to_string()
+            .contains("the value must be at least 3"));
+    }
+
+    #[test]
+    fn requires_the_keyfile_it_was_initialized_with() {
+        let mut store = create_empty_store();
+        let mut writer = MockOtpWriter::new();
+        store.set_keyfile(Some(hash_keyfile(b"keyfile")));
+
+        let arg_vec = vec!["otp", Init.as_str(), "--kdf-memory", "1"];
+        let init_args = get_cmd_args(CommandType::Init.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        run_init(&init_args, &mut store, &mut reader, &mut writer);
+
+        assert!(store.has_keyfile());
+        assert!(String::from_utf8(writer.out)
+            .unwrap()
+            .ends_with("The keyfile will be required to unlock the account store\n"));
+        store.set_keyfile(None);
+        assert!(store.unlock(PIN).is_err());
+    }
 }
@@ -0,0 +1,219 @@
+use clap::{arg, command, ArgMatches, Command};
+use std::time::{Duration, Instant};
+
+use super::CommandType;
+use crate::account::{AccountStore, AccountStoreOperations};
+use crate::crypto::{KdfParams, MAX_MEMORY_MIB};
+use crate::storage::MemoryBackend;
+use crate::utils::is_positive_number;
+use crate::writer::OutErr;
+
+const MIN_MEMORY_MIB: u32 = 8;
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::KdfBenchmark.as_str())
+        .about("Find Argon2 parameters for a target unlock time")
+        .args(&[
+            arg!(-t --target <MS> "Target unlock time in milliseconds")
+                .required(false)
+                .default_value("500")
+                .validator(is_positive_number),
+            arg!(--"kdf-time" <ITERATIONS> "Argon2 iterations")
+                .required(false)
+                .validator(is_positive_number),
+            arg!(--"kdf-lanes" <LANES> "Argon2 parallel lanes")
+                .required(false)
+                .validator(is_positive_number),
+        ])
+}
+
+// Double the memory cost until unlocking would take longer than the target
+pub fn find_kdf_params<F>(
+    target: Duration,
+    params: KdfParams,
+    mut measure: F,
+) -> (KdfParams, Duration)
+where
+    F: FnMut(&KdfParams) -> Duration,
+{
+    let mut memory_mib = MIN_MEMORY_MIB;
+    let mut best = KdfParams {
+        mem_cost: memory_mib * 1024,
+        ..params
+    };
+    let mut best_time = measure(&best);
+
+    while best_time <= target && memory_mib < MAX_MEMORY_MIB {
+        memory_mib *= 2;
+        let candidate = KdfParams {
+            mem_cost: memory_mib * 1024,
+            ..best.clone()
+        };
+        let elapsed = measure(&candidate);
+        if elapsed > target {
+            break;
+        }
+        best = candidate;
+        best_time = elapsed;
+    }
+
+    (best, best_time)
+}
+
+// Time a real unlock of a throwaway store kept in memory
+fn measure_unlock(params: &KdfParams) -> Duration {
+    let mut store = match AccountStore::open(Box::new(MemoryBackend::new())) {
+        Ok(store) => store,
+        Err(_) => return Duration::MAX,
+    };
+    if store.set_pin("benchmark", params.clone()).is_err() || store.save().is_err() {
+        return Duration::MAX;
+    }
+
+    let start = Instant::now();
+    let _ = store.validate_pin("benchmark") && store.unlock("benchmark").is_ok();
+    start.elapsed()
+}
+
+pub fn run_kdf_benchmark<W>(benchmark_args: &ArgMatches, writer: &mut W)
+where
+    W: OutErr,
+{
+    let target = benchmark_args
+        .value_of("target")
+        .and_then(|target| target.parse::<u64>().ok())
+        .map(Duration::from_millis)
+        .unwrap_or_else(|| Duration::from_millis(500));
+    let params = get_benchmark_params(benchmark_args);
+
+    let (params, elapsed) = find_kdf_params(target, params, measure_unlock);
+    write_params(&params, elapsed, target, writer);
+}
+
+fn get_benchmark_params(benchmark_args: &ArgMatches) -> KdfParams {
+    let parse = |name| {
+        benchmark_args
+            .value_of(name)
+            .and_then(|value| value.parse::<u32>().ok())
+    };
+    let defaults = KdfParams::default();
+    KdfParams {
+        time_cost: parse("kdf-time").unwrap_or(defaults.time_cost),
+        lanes: parse("kdf-lanes").unwrap_or(defaults.lanes),
+        ..defaults
+    }
+}
+
+fn write_params<W>(params: &KdfParams, elapsed: Duration, target: Duration, writer: &mut W)
+where
+    W: OutErr,
+{
+    if elapsed > target {
+        writer.write_err("The minimum memory cost exceeds the target unlock time\n");
+    }
+
+    let memory_mib = params.mem_cost / 1024;
+    writer.write(&format!(
+        "{} with {} MiB, {} iterations and {} lanes unlocks in {} ms\n",
+        params.variant.as_str(),
+        memory_mib,
+        params.time_cost,
+        params.lanes,
+        elapsed.as_millis()
+    ));
+    writer.write(&format!(
+        "otp init --kdf-memory {} --kdf-time {} --kdf-lanes {}\n",
+        memory_mib, params.time_cost, params.lanes
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::CommandType::KdfBenchmark;
+    use crate::crypto::KdfVariant;
+    use crate::tests::mocks::MockOtpWriter;
+    use crate::tests::utils::get_cmd_args;
+
+    // Pretend every MiB of memory costs one millisecond
+    fn measure_by_memory(params: &KdfParams) -> Duration {
+        Duration::from_millis((params.mem_cost / 1024) as u64)
+    }
+
+    #[test]
+    fn finds_the_largest_memory_within_the_target() {
+        let (params, elapsed) = find_kdf_params(
+            Duration::from_millis(100),
+            KdfParams::default(),
+            measure_by_memory,
+        );
+
+        assert_eq!(params.mem_cost, 64 * 1024);
+        assert_eq!(params.variant, KdfVariant::Argon2id);
+        assert_eq!(elapsed, Duration::from_millis(64));
+    }
+
+    #[test]
+    fn stops_at_the_maximum_memory() {
+        let (params, _) = find_kdf_params(
+            Duration::from_secs(60),
+            KdfParams::default(),
+            measure_by_memory,
+        );
+
+        assert_eq!(params.mem_cost, MAX_MEMORY_MIB * 1024);
+    }
+
+    #[test]
+    fn falls_back_to_the_minimum_memory() {
+        let (params, elapsed) = find_kdf_params(
+            Duration::from_millis(1),
+            KdfParams::default(),
+            measure_by_memory,
+        );
+
+        assert_eq!(params.mem_cost, MIN_MEMORY_MIB * 1024);
+        assert_eq!(elapsed, Duration::from_millis(8));
+    }
+
+    #[test]
+    fn keeps_requested_time_and_lanes() {
+        let arg_vec = vec![
+            "otp",
+            KdfBenchmark.as_str(),
+            "--kdf-time",
+            "4",
+            "--kdf-lanes",
+            "2",
+        ];
+        let benchmark_args = get_cmd_args(KdfBenchmark.as_str(), subcommand(), &arg_vec).unwrap();
+        let params = get_benchmark_params(&benchmark_args);
+
+        let (params, _) = find_kdf_params(Duration::from_millis(100), params, measure_by_memory);
+
+        assert_eq!(params.time_cost, 4);
+        assert_eq!(params.lanes, 2);
+    }
+
+    #[test]
+    fn writes_suggested_init_arguments() {
+        let mut writer = MockOtpWriter::new();
+        let params = KdfParams {
+            mem_cost: 256 * 1024,
+            ..KdfParams::default()
+        };
+
+        write_params(
+            &params,
+            Duration::from_millis(420),
+            Duration::from_millis(500),
+            &mut writer,
+        );
+
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "argon2id with 256 MiB, 3 iterations and 1 lanes unlocks in 420 ms\notp init --kdf-memory 256 --kdf-time 3 --kdf-lanes 1\n"
+        );
+        assert_eq!(writer.err, Vec::new());
+    }
+}
@@ -1,17 +1,108 @@
-use clap::{command, Command};
+use clap::{arg, command, ArgMatches, Command};
+use std::cmp::Reverse;
 
-use super::CommandType;
-use crate::account::AccountStoreOperations;
+use super::{get_tags, tag_arg, CommandType};
+use crate::account::{Account, AccountStoreOperations};
+use crate::policy::Listing;
 use crate::writer::OutErr;
 
 pub fn subcommand() -> Command<'static> {
-    command!(CommandType::List.as_str()).about("List all accounts")
+    command!(CommandType::List.as_str())
+        .about("List all accounts")
+        .args(&[
+            tag_arg("tag", "Only list accounts with this tag"),
+            arg!(--sort <ORDER> "Sort by name, most recently used or most used")
+                .required(false)
+                .possible_values(["name", "last-used", "uses"])
+                .default_value("name"),
+            arg!(--"by-issuer" "Group accounts by issuer").required(false),
+        ])
 }
 
-pub fn run_list(account_store: &impl AccountStoreOperations, writer: &mut impl OutErr) {
+// A store with an index of names lists them without the pin, when nothing more is needed
+pub fn lists_without_unlock(
+    list_args: &ArgMatches,
+    account_store: &impl AccountStoreOperations,
+) -> bool {
+    account_store.is_initialized()
+        && account_store.get_listing() == Listing::Index
+        && !list_args.is_present("tag")
+        && !list_args.is_present("by-issuer")
+        && list_args.value_of("sort") == Some("name")
+}
+
+pub fn run_list(
+    list_args: &ArgMatches,
+    account_store: &impl AccountStoreOperations,
+    writer: &mut impl OutErr,
+) {
+    if account_store.get_key().is_none() {
+        match account_store.list_names() {
+            Ok(names) => {
+                writer.write("Accounts:\n");
+                for name in names {
+                    writer.write(&format!("{}\n", name));
+                }
+            }
+            Err(err) => writer.write_err(&format!("{}\n", err)),
+        }
+        return;
+    }
+
+    let tags = get_tags(list_args, "tag");
+    let names = account_store.list();
+    let mut accounts: Vec<(&String, &Account)> = names
+        .iter()
+        .filter_map(|name| account_store.get(name).map(|account| (name, account)))
+        .filter(|(_, account)| tags.iter().all(|tag| account.tags.iter().any(|t| t == tag)))
+        .collect();
+
+    // Accounts come sorted by name, and the sorts are stable so ties stay that way
+    match list_args.value_of("sort") {
+        Some("last-used") => accounts.sort_by_key(|(_, account)| Reverse(account.last_used_at)),
+        Some("uses") => accounts.sort_by_key(|(_, account)| Reverse(account.use_count)),
+        _ => {}
+    }
+
     writer.write("Accounts:\n");
-    for name in account_store.list() {
-        writer.write(&format!("{}\n", name));
+    if !list_args.is_present("by-issuer") {
+        for (name, account) in accounts {
+            write_account(name, account, "", writer);
+        }
+        return;
+    }
+
+    let mut issuers: Vec<Option<&String>> = Vec::new();
+    for (_, account) in &accounts {
+        if !issuers.contains(&account.issuer.as_ref()) {
+            issuers.push(account.issuer.as_ref());
+        }
+    }
+    // Accounts without an issuer go last
+    issuers.sort_by_key(|issuer| (issuer.is_none(), issuer.cloned()));
+    for issuer in issuers {
+        match issuer {
+            Some(issuer) => writer.write(&format!("{}:\n", issuer)),
+            None => writer.write("No issuer:\n"),
+        }
+        for (name, account) in accounts
+            .iter()
+            .filter(|(_, account)| account.issuer.as_ref() == issuer)
+        {
+            write_account(name, account, "  ", writer);
+        }
+    }
+}
+
+fn write_account(name: &str, account: &Account, indent: &str, writer: &mut impl OutErr) {
+    match account.tags.is_empty() {
+        true => writer.write(&format!("{}{}\n", indent, name)),
+        false => writer.write(&format!(
+            "{}{} [{}]\n",
+            indent,
+            name,
+            account.tags.join(", ")
+        )),
     }
 }
 
@@ -19,18 +110,150 @@ pub fn run_list(account_store: &impl AccountStoreOperations, writer: &mut impl O
 mod tests {
     use super::*;
     use crate::account::tests::get_mock_store;
+    use crate::account::{AccountStore, OtpType};
+    use crate::cmd::CommandType::List;
     use crate::tests::constants::*;
     use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
 
-    #[test]
-    fn lists_account_names() {
+    fn get_tagged_store() -> AccountStore {
         let mut store = get_mock_store();
+        store.add(
+            String::from(ACCOUNT_NAME_3),
+            Account::new(String::from("key-3"), OtpType::TOTP),
+        );
+
+        let account = store.get_mut(ACCOUNT_NAME_1).unwrap();
+        account.issuer = Some(String::from("Pets"));
+        account.add_tags(&["personal"]);
+        let account = store.get_mut(ACCOUNT_NAME_2).unwrap();
+        account.issuer = Some(String::from("Bank"));
+        account.add_tags(&["personal", "prod"]);
+        let account = store.get_mut(ACCOUNT_NAME_3).unwrap();
+        account.add_tags(&["prod"]);
+
+        store.record_use(ACCOUNT_NAME_3, 60);
+        store.record_use(ACCOUNT_NAME_3, 90);
+        store.record_use(ACCOUNT_NAME_1, 120);
+        store
+    }
+
+    fn run(store: &AccountStore, args: &[&str]) -> String {
         let mut writer = MockOtpWriter::new();
+        let mut arg_vec = vec!["otp", List.as_str()];
+        arg_vec.extend_from_slice(args);
+        let list_args = get_cmd_args(List.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_list(&mut store, &mut writer);
+        run_list(&list_args, store, &mut writer);
 
-        let expected_output = format!("Accounts:\n{}\n{}\n", ACCOUNT_NAME_1, ACCOUNT_NAME_2);
-        assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
         assert_eq!(writer.err, Vec::new());
+        String::from_utf8(writer.out).unwrap()
+    }
+
+    #[test]
+    fn lists_account_names() {
+        let store = get_mock_store();
+
+        let expected_output = format!("Accounts:\n{}\n{}\n", ACCOUNT_NAME_1, ACCOUNT_NAME_2);
+        assert_eq!(run(&store, &[]), expected_output);
+    }
+
+    #[test]
+    fn lists_names_from_the_index_while_locked() {
+        let mut store = get_tagged_store();
+        store.set_listing(Listing::Index);
+        store.save().unwrap();
+        let store = store.reopen();
+
+        let arg_vec = vec!["otp", List.as_str()];
+        let list_args = get_cmd_args(List.as_str(), subcommand(), &arg_vec).unwrap();
+        assert!(lists_without_unlock(&list_args, &store));
+
+        let expected_output = format!(
+            "Accounts:\n{}\n{}\n{}\n",
+            ACCOUNT_NAME_1, ACCOUNT_NAME_2, ACCOUNT_NAME_3
+        );
+        assert_eq!(run(&store, &[]), expected_output);
+    }
+
+    #[test]
+    fn needs_the_pin_for_details_or_without_an_index() {
+        let mut store = get_mock_store();
+        store.set_listing(Listing::Index);
+
+        let arg_vec = vec!["otp", List.as_str(), "--tag", "prod"];
+        let list_args = get_cmd_args(List.as_str(), subcommand(), &arg_vec).unwrap();
+        assert!(!lists_without_unlock(&list_args, &store));
+
+        let store = get_mock_store();
+        let arg_vec = vec!["otp", List.as_str()];
+        let list_args = get_cmd_args(List.as_str(), subcommand(), &arg_vec).unwrap();
+        assert!(!lists_without_unlock(&list_args, &store));
+    }
+
+    #[test]
+    fn lists_tags_with_account_names() {
+        let store = get_tagged_store();
+
+        let expected_output = format!(
+            "Accounts:\n{} [personal]\n{} [personal, prod]\n{} [prod]\n",
+            ACCOUNT_NAME_1, ACCOUNT_NAME_2, ACCOUNT_NAME_3
+        );
+        assert_eq!(run(&store, &[]), expected_output);
+    }
+
+    #[test]
+    fn filters_by_tag() {
+        let store = get_tagged_store();
+
+        let expected_output = format!(
+            "Accounts:\n{} [personal, prod]\n{} [prod]\n",
+            ACCOUNT_NAME_2, ACCOUNT_NAME_3
+        );
+        assert_eq!(run(&store, &["--tag", "prod"]), expected_output);
+
+        let expected_output = format!("Accounts:\n{} [personal, prod]\n", ACCOUNT_NAME_2);
+        assert_eq!(run(&store, &["--tag", "prod,personal"]), expected_output);
+
+        assert_eq!(run(&store, &["--tag", "work"]), "Accounts:\n");
+    }
+
+    #[test]
+    fn sorts_by_last_used() {
+        let store = get_tagged_store();
+
+        let expected_output = format!(
+            "Accounts:\n{} [personal]\n{} [prod]\n{} [personal, prod]\n",
+            ACCOUNT_NAME_1, ACCOUNT_NAME_3, ACCOUNT_NAME_2
+        );
+        assert_eq!(run(&store, &["--sort", "last-used"]), expected_output);
+    }
+
+    #[test]
+    fn sorts_by_use_count() {
+        let store = get_tagged_store();
+
+        let expected_output = format!(
+            "Accounts:\n{} [prod]\n{} [personal]\n{} [personal, prod]\n",
+            ACCOUNT_NAME_3, ACCOUNT_NAME_1, ACCOUNT_NAME_2
+        );
+        assert_eq!(run(&store, &["--sort", "uses"]), expected_output);
+    }
+
+    #[test]
+    fn groups_by_issuer() {
+        let store = get_tagged_store();
+
+        let expected_output = format!(
+            "Accounts:\nBank:\n  {} [personal, prod]\nPets:\n  {} [personal]\nNo issuer:\n  {} [prod]\n",
+            ACCOUNT_NAME_2, ACCOUNT_NAME_1, ACCOUNT_NAME_3
+        );
+        assert_eq!(run(&store, &["--by-issuer"]), expected_output);
+    }
+
+    #[test]
+    fn rejects_unknown_sort_orders() {
+        let arg_vec = vec!["otp", List.as_str(), "--sort", "age"];
+        assert!(get_cmd_args(List.as_str(), subcommand(), &arg_vec).is_err());
     }
 }
@@ -0,0 +1,82 @@
+use clap::{arg, command, ArgMatches, Command};
+
+use super::CommandType;
+use crate::agent::{self, get_socket_path, is_not_running};
+use crate::writer::OutErr;
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Lock.as_str())
+        .about("Wipe the key held by the agent and stop it")
+        .args(&[
+            arg!(--socket <PATH> "Socket of the agent, instead of OTP_AGENT_SOCK or ~/.otp/agent.sock")
+                .required(false),
+        ])
+}
+
+pub fn run_lock<W>(lock_args: &ArgMatches, var: impl Fn(&str) -> Option<String>, writer: &mut W)
+where
+    W: OutErr,
+{
+    let path = get_socket_path(lock_args.value_of("socket"), var);
+    match agent::lock(&path) {
+        Ok(_) => writer.write("Agent locked\n"),
+        Err(err) if is_not_running(&err) => {
+            writer.write_err(&format!("No agent is running on {}\n", path.display()))
+        }
+        Err(err) => writer.write_err(&format!("Unable to lock the agent: {}\n", err)),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::agent::{bind, serve, Agent, AGENT_SOCK_VAR};
+    use crate::cmd::CommandType::Lock;
+    use crate::secret::Secret;
+    use crate::storage::tests::get_test_directory;
+    use crate::tests::mocks::MockOtpWriter;
+    use crate::tests::utils::get_cmd_args;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn locks_the_agent_from_the_environment() {
+        let directory = get_test_directory("lock");
+        let path = directory.join("agent.sock");
+        let listener = bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let key = Secret::new(vec![1; 32]);
+            let mut agent = Agent::new(key, Duration::from_secs(60), Instant::now());
+            serve(&mut agent, &listener).unwrap();
+        });
+        let mut writer = MockOtpWriter::new();
+        let var = |name: &str| match name {
+            AGENT_SOCK_VAR => path.to_str().map(String::from),
+            _ => None,
+        };
+
+        let arg_vec = vec!["otp", Lock.as_str()];
+        let lock_args = get_cmd_args(Lock.as_str(), subcommand(), &arg_vec).unwrap();
+        run_lock(&lock_args, var, &mut writer);
+
+        server.join().unwrap();
+        assert_eq!(String::from_utf8(writer.out).unwrap(), "Agent locked\n");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn reports_when_no_agent_is_running() {
+        let directory = get_test_directory("lock-missing");
+        let path = directory.join("agent.sock");
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Lock.as_str(), "--socket", path.to_str().unwrap()];
+        let lock_args = get_cmd_args(Lock.as_str(), subcommand(), &arg_vec).unwrap();
+        run_lock(&lock_args, |_| None, &mut writer);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!("No agent is running on {}\n", path.display())
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,322 @@
+use clap::{arg, command, ArgMatches, Command};
+use data_encoding::BASE32_NOPAD;
+use std::io::Write;
+
+use super::{write_audit, CommandType};
+use crate::account::{encode_public_key, AccountStoreOperations};
+use crate::audit::Outcome;
+use crate::crypto::generate_identity;
+use crate::secret::Secret;
+use crate::storage::private_options;
+use crate::writer::OutErr;
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Member.as_str())
+        .about("Share the account store with team members, who unlock it with their keys")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("keygen")
+                .about("Generate an identity to unlock shared account stores with")
+                .args(&[
+                    arg!(-o --output <FILE> "Write the identity to a new file instead of printing it")
+                        .required(false),
+                ]),
+        )
+        .subcommand(
+            Command::new("add")
+                .about("Let a member unlock the account store with their identity")
+                .args(&[
+                    arg!(<NAME> "Name of the member"),
+                    arg!(<PUBLIC_KEY> "Their public key, from 'otp member keygen'"),
+                ]),
+        )
+        .subcommand(
+            Command::new("remove")
+                .about("Remove a member and re-encrypt the account store with a new key")
+                .args(&[arg!(<NAME> "Member to remove")]),
+        )
+        .subcommand(Command::new("list").about("List the members"))
+}
+
+// Generating an identity needs no account store, so main runs it before unlocking
+pub fn is_keygen(member_args: &ArgMatches) -> bool {
+    matches!(member_args.subcommand(), Some(("keygen", _)))
+}
+
+pub fn run_keygen(member_args: &ArgMatches, writer: &mut impl OutErr) {
+    let (identity, public_key) = generate_identity();
+    let public_key = encode_public_key(&public_key);
+    let contents = Secret::new(format!(
+        "# public key: {}\n{}\n",
+        public_key,
+        BASE32_NOPAD.encode(identity.expose())
+    ));
+    let output = member_args
+        .subcommand_matches("keygen")
+        .and_then(|args| args.value_of("output"));
+    let path = match output {
+        Some(path) => path,
+        None => {
+            writer.write(contents.expose());
+            return;
+        }
+    };
+    // Never overwrite an identity, which may be the only way into a shared store
+    let written = private_options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.expose().as_bytes()));
+    match written {
+        Ok(_) => writer.write(&format!("Public key: {}\n", public_key)),
+        Err(err) => writer.write_err(&format!("Unable to write identity {}: {}\n", path, err)),
+    }
+}
+
+pub fn run_member(
+    member_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    writer: &mut impl OutErr,
+) {
+    let (subcommand, args) = match member_args.subcommand() {
+        Some(subcommand) => subcommand,
+        None => {
+            writer.write_err("Unknown member subcommand\n");
+            return;
+        }
+    };
+
+    if subcommand == "list" {
+        write_members(account_store, writer);
+        return;
+    }
+
+    let name = args.value_of("NAME").unwrap_or_default();
+    let result = match subcommand {
+        "add" => {
+            let public_key = args.value_of("PUBLIC_KEY").unwrap_or_default();
+            account_store
+                .add_member(name, public_key)
+                .map(|_| format!("Member \"{}\" added\n", name))
+        }
+        "remove" => account_store.remove_member(name).map(|_| {
+            format!(
+                "Member \"{}\" removed, the account store was re-encrypted with a new key\n",
+                name
+            )
+        }),
+        _ => {
+            writer.write_err("Unknown member subcommand\n");
+            return;
+        }
+    };
+    let message = match result {
+        Ok(message) => message,
+        Err(err) => {
+            writer.write_err(&format!("{}\n", err));
+            write_audit(
+                account_store,
+                writer,
+                &format!("member-{}", subcommand),
+                None,
+                Outcome::Failure,
+            );
+            return;
+        }
+    };
+
+    let outcome = match account_store.save() {
+        Ok(_) => {
+            writer.write(&message);
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}\n", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        &format!("member-{}", subcommand),
+        None,
+        outcome,
+    );
+}
+
+fn write_members(account_store: &impl AccountStoreOperations, writer: &mut impl OutErr) {
+    let members = account_store.list_members();
+    if members.is_empty() {
+        writer.write("The account store is not shared with anyone\n");
+        return;
+    }
+    for member in members {
+        writer.write(&format!("{} {}\n", member.name, member.public_key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::account::AccountStore;
+    use crate::cmd::CommandType::Member;
+    use crate::crypto::get_public_key;
+    use crate::secret::SecretBytes;
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+    use crate::utils::read_identity;
+
+    fn run(store: &mut impl AccountStoreOperations, arg_vec: &[&str]) -> MockOtpWriter {
+        let mut arg_vec = arg_vec.to_vec();
+        arg_vec.insert(0, "otp");
+        arg_vec.insert(1, Member.as_str());
+        let member_args = get_cmd_args(Member.as_str(), subcommand(), &arg_vec.to_vec()).unwrap();
+        let mut writer = MockOtpWriter::new();
+        run_member(&member_args, store, &mut writer);
+        writer
+    }
+
+    fn new_identity() -> (SecretBytes, String) {
+        let (identity, public_key) = generate_identity();
+        (identity, encode_public_key(&public_key))
+    }
+
+    #[test]
+    fn adds_lists_and_removes_members() {
+        let mut store = get_mock_store();
+        let (identity, public_key) = new_identity();
+
+        let writer = run(&mut store, &["add", "alice", &public_key]);
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "Member \"alice\" added\n"
+        );
+
+        let writer = run(&mut store, &["list"]);
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            format!("alice {}\n", public_key)
+        );
+
+        let mut shared = store.reopen();
+        shared.unlock_with_identity(identity.expose()).unwrap();
+        assert!(shared.get(ACCOUNT_NAME_1).is_some());
+
+        // Members can be removed by whoever has the pin
+        let mut store = shared.reopen();
+        store.unlock(PIN).unwrap();
+        let writer = run(&mut store, &["remove", "alice"]);
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "Member \"alice\" removed, the account store was re-encrypted with a new key\n"
+        );
+
+        let mut store = store.reopen();
+        assert_eq!(
+            store
+                .unlock_with_identity(identity.expose())
+                .unwrap_err()
+                .to_string(),
+            "This identity is not a member of the account store"
+        );
+        store.unlock(PIN).unwrap();
+        let writer = run(&mut store, &["list"]);
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "The account store is not shared with anyone\n"
+        );
+    }
+
+    #[test]
+    fn refuses_an_invalid_public_key() {
+        let mut store = get_mock_store();
+
+        let writer = run(&mut store, &["add", "alice", "not-a-key"]);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Invalid public key: not-a-key\n"
+        );
+        assert!(store.list_members().is_empty());
+    }
+
+    #[test]
+    fn refuses_a_member_twice() {
+        let mut store = get_mock_store();
+        let (_, public_key) = new_identity();
+        run(&mut store, &["add", "alice", &public_key]);
+
+        let writer = run(&mut store, &["add", "bob", &public_key]);
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "The key is already used by member \"alice\"\n"
+        );
+
+        let (_, public_key) = new_identity();
+        let writer = run(&mut store, &["add", "alice", &public_key]);
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Member already exists: alice\n"
+        );
+        assert_eq!(store.list_members().len(), 1);
+    }
+
+    #[test]
+    fn needs_the_pin_to_remove_a_member() {
+        let mut store = get_mock_store();
+        let (identity, public_key) = new_identity();
+        run(&mut store, &["add", "alice", &public_key]);
+        let mut store: AccountStore = store.reopen();
+        store.unlock_with_identity(identity.expose()).unwrap();
+
+        let writer = run(&mut store, &["remove", "alice"]);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Changing who can unlock the account store needs the pin, rather than an identity or the agent\n"
+        );
+        assert_eq!(store.list_members().len(), 1);
+    }
+
+    #[test]
+    fn errors_when_removing_a_missing_member() {
+        let mut store = get_mock_store();
+
+        let writer = run(&mut store, &["remove", "alice"]);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Member not found: alice\n"
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn writes_a_new_identity_file() {
+        let directory = crate::storage::tests::get_test_directory("keygen");
+        let path = directory.join("identity.txt");
+        let path = path.to_str().unwrap();
+        let arg_vec = vec!["otp", Member.as_str(), "keygen", "-o", path];
+        let member_args = get_cmd_args(Member.as_str(), subcommand(), &arg_vec).unwrap();
+        assert!(is_keygen(&member_args));
+
+        let mut writer = MockOtpWriter::new();
+        run_keygen(&member_args, &mut writer);
+        let identity = read_identity(path).unwrap();
+        let public_key = encode_public_key(&get_public_key(identity.expose()).unwrap());
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            format!("Public key: {}\n", public_key)
+        );
+
+        let mut writer = MockOtpWriter::new();
+        run_keygen(&member_args, &mut writer);
+        assert!(String::from_utf8(writer.err)
+            .unwrap()
+            .starts_with(&format!("Unable to write identity {}: ", path)));
+        assert_eq!(read_identity(path).unwrap(), identity);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,10 +1,36 @@
+use clap::{arg, Arg, ArgMatches};
+
+use crate::account::{Account, AccountStoreOperations, PRIMARY_CREDENTIAL};
+use crate::audit::{AuditEvent, Outcome};
+use crate::crypto::{KdfParams, KdfVariant};
+use crate::lookup::{find_account, Lookup};
+use crate::policy::{estimate_strength, secret_weakness, CharClass, Listing, PinMode, PinPolicy};
+use crate::secret::Secret;
+use crate::totp::{get_unix_time, Clock};
+use crate::utils::{decode_key, is_kdf_memory, is_positive_number, is_tag};
+use crate::writer::{OutErr, ReadLine};
+
 pub mod add;
+pub mod agent;
+pub mod audit;
+pub mod credential;
 pub mod delete;
+pub mod edit;
 pub mod generate;
 pub mod get;
+pub mod info;
 pub mod init;
+pub mod kdf_benchmark;
 pub mod list;
+pub mod lock;
+pub mod member;
+pub mod passwd;
+pub mod recovery;
+pub mod rename;
+pub mod rotate;
+pub mod trash;
 pub mod validate;
+pub mod verify;
 
 pub enum CommandType {
     Init,
@@ -13,7 +39,21 @@ pub enum CommandType {
     Delete,
     List,
     Get,
+    Info,
     Validate,
+    Rename,
+    Edit,
+    Trash,
+    Recovery,
+    Audit,
+    Passwd,
+    KdfBenchmark,
+    Agent,
+    Lock,
+    Verify,
+    Rotate,
+    Credential,
+    Member,
 }
 
 impl CommandType {
@@ -25,7 +65,385 @@ impl CommandType {
             CommandType::Delete => "delete",
             CommandType::List => "list",
             CommandType::Get => "get",
+            CommandType::Info => "info",
             CommandType::Validate => "validate",
+            CommandType::Rename => "rename",
+            CommandType::Edit => "edit",
+            CommandType::Trash => "trash",
+            CommandType::Recovery => "recovery",
+            CommandType::Audit => "audit",
+            CommandType::Passwd => "passwd",
+            CommandType::KdfBenchmark => "kdf-benchmark",
+            CommandType::Agent => "agent",
+            CommandType::Lock => "lock",
+            CommandType::Verify => "verify",
+            CommandType::Rotate => "rotate",
+            CommandType::Credential => "credential",
+            CommandType::Member => "member",
+        }
+    }
+}
+
+// Resolve a partial or differently cased account name, letting the user pick when
+// several accounts match and someone is at the terminal
+pub fn find_account_name(
+    query: &str,
+    account_store: &impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+) -> Option<String> {
+    match find_account(query, &account_store.list()) {
+        Lookup::Found(name) => Some(name),
+        Lookup::NotFound(suggestions) => {
+            let mut message = format!("Account not found: {}\n", query);
+            if !suggestions.is_empty() {
+                message.push_str(&format!("Did you mean {}?\n", suggestions.join(", ")));
+            }
+            writer.write_err(&message);
+            None
+        }
+        Lookup::Ambiguous(names) if reader.is_interactive() => {
+            pick_account(query, names, reader, writer)
+        }
+        Lookup::Ambiguous(names) => {
+            writer.write_err(&format!(
+                "Several accounts match {}: {}\n",
+                query,
+                names.join(", ")
+            ));
+            None
+        }
+    }
+}
+
+fn pick_account(
+    query: &str,
+    names: Vec<String>,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+) -> Option<String> {
+    let mut prompt = format!("Several accounts match {}:\n", query);
+    for (index, name) in names.iter().enumerate() {
+        prompt.push_str(&format!("{}) {}\n", index + 1, name));
+    }
+    prompt.push_str("Choose an account: ");
+    writer.write_err(&prompt);
+
+    let mut choice = String::new();
+    reader.read_line(&mut choice);
+    match choice.trim().parse::<usize>() {
+        Ok(number) if number >= 1 && number <= names.len() => names.into_iter().nth(number - 1),
+        _ => {
+            writer.write_err("No account chosen\n");
+            None
         }
     }
 }
+
+// "1 account", "2 accounts", and "1 entry", "2 entries"
+pub fn count_of(count: usize, noun: &str) -> String {
+    match (count, noun.strip_suffix('y')) {
+        (1, _) => format!("1 {}", noun),
+        (count, Some(stem)) if !stem.ends_with(['a', 'e', 'i', 'o', 'u']) => {
+            format!("{} {}ies", count, stem)
+        }
+        (count, _) => format!("{} {}s", count, noun),
+    }
+}
+
+// Record what a command did. Failing to write the log is reported but doesn't undo the command.
+pub fn write_audit(
+    account_store: &mut impl AccountStoreOperations,
+    writer: &mut impl OutErr,
+    operation: &str,
+    account: Option<&str>,
+    outcome: Outcome,
+) {
+    let event = AuditEvent {
+        timestamp: get_unix_time(&Clock::new()),
+        operation: String::from(operation),
+        account: account.map(String::from),
+        outcome,
+    };
+    if let Err(err) = account_store.audit(event) {
+        writer.write_err(&format!("Unable to write the audit log: {}\n", err));
+    }
+}
+
+pub fn force_arg() -> Arg<'static> {
+    arg!(--force "Add the key even if it is weak or already used").required(false)
+}
+
+// Reasons not to trust a key, judged on the bytes it encodes
+fn check_key(key: &str, account_store: &impl AccountStoreOperations) -> Vec<String> {
+    let decoded = match decode_key(key) {
+        Some(decoded) => decoded,
+        None => return vec![String::from("The key is not a valid base32 encoding")],
+    };
+    let mut problems = Vec::new();
+    if let Some(weakness) = secret_weakness(&decoded) {
+        problems.push(format!("The key is weak, {}", weakness));
+    }
+    for name in account_store.list() {
+        let account = match account_store.get(&name) {
+            Some(account) => account,
+            None => continue,
+        };
+        for (credential, other) in account.keys() {
+            if decode_key(other.expose()).is_some_and(|other| other == decoded) {
+                problems.push(match credential {
+                    PRIMARY_CREDENTIAL => format!("The key is already used by \"{}\"", name),
+                    credential => format!(
+                        "The key is already used by credential \"{}\" of \"{}\"",
+                        credential, name
+                    ),
+                });
+            }
+        }
+    }
+    problems
+}
+
+// Weak or reused keys are refused, unless forced for a service that issued one
+pub fn accepts_key(
+    key: &str,
+    force: bool,
+    account_store: &impl AccountStoreOperations,
+    writer: &mut impl OutErr,
+) -> bool {
+    let problems = check_key(key, account_store);
+    for problem in &problems {
+        match force {
+            true => writer.write_err(&format!("Warning: {}\n", problem)),
+            false => writer.write_err(&format!("{}\n", problem)),
+        }
+    }
+    if !problems.is_empty() && !force {
+        writer.write_err("Run again with --force to add it anyway\n");
+        return false;
+    }
+    true
+}
+
+pub fn yes_arg() -> Arg<'static> {
+    arg!(-y --yes "Don't ask for confirmation").required(false)
+}
+
+// Ask before destructive changes. Without a terminal to ask at, --yes is required.
+pub fn confirm(
+    args: &ArgMatches,
+    question: &str,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+) -> bool {
+    if args.is_present("yes") {
+        return true;
+    }
+    if !reader.is_interactive() {
+        writer.write_err("Confirmation required, run again with --yes\n");
+        return false;
+    }
+
+    writer.write_err(&format!("{} [y/N] ", question));
+    let mut answer = String::new();
+    reader.read_line(&mut answer);
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => true,
+        _ => {
+            writer.write_err("Cancelled\n");
+            false
+        }
+    }
+}
+
+// Arguments for the account details shown by info
+pub fn metadata_args() -> Vec<Arg<'static>> {
+    vec![
+        arg!(--issuer <ISSUER> "Service that issued the key").required(false),
+        arg!(--label <LABEL> "Label of the account at the issuer, e.g. an email address")
+            .required(false),
+        arg!(--notes <NOTES> "Free-form notes").required(false),
+    ]
+}
+
+// Tags given as repeated flags or a comma separated list
+pub fn tag_arg(name: &'static str, help: &'static str) -> Arg<'static> {
+    Arg::new(name)
+        .long(name)
+        .value_name("TAG")
+        .help(help)
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .use_value_delimiter(true)
+        .validator(is_tag)
+}
+
+pub fn get_tags<'a>(args: &'a ArgMatches, name: &str) -> Vec<&'a str> {
+    args.values_of(name)
+        .map(|tags| tags.collect())
+        .unwrap_or_default()
+}
+
+// Apply any metadata arguments that were supplied, an empty value clears the field
+pub fn set_metadata(args: &ArgMatches, account: &mut Account) {
+    for (name, field) in [
+        ("issuer", &mut account.issuer),
+        ("label", &mut account.label),
+        ("notes", &mut account.notes),
+    ] {
+        if let Some(value) = args.value_of(name) {
+            *field = Some(String::from(value)).filter(|value| !value.is_empty());
+        }
+    }
+}
+
+// Arguments for tuning the Argon2 key derivation
+pub fn kdf_args() -> Vec<Arg<'static>> {
+    vec![
+        arg!(--"kdf-memory" <MIB> "Argon2 memory cost in MiB")
+            .required(false)
+            .validator(is_kdf_memory),
+        arg!(--"kdf-time" <ITERATIONS> "Argon2 iterations")
+            .required(false)
+            .validator(is_positive_number),
+        arg!(--"kdf-lanes" <LANES> "Argon2 parallel lanes")
+            .required(false)
+            .validator(is_positive_number),
+        arg!(--"kdf-variant" <VARIANT> "Argon2 variant")
+            .required(false)
+            .possible_values(["argon2id", "argon2i", "argon2d"]),
+    ]
+}
+
+// Apply any Argon2 arguments that were supplied on top of existing params
+pub fn get_kdf_params(args: &ArgMatches, params: KdfParams) -> KdfParams {
+    let parse = |name| {
+        args.value_of(name)
+            .and_then(|value| value.parse::<u32>().ok())
+    };
+    KdfParams {
+        variant: args
+            .value_of("kdf-variant")
+            .and_then(KdfVariant::from_str)
+            .unwrap_or(params.variant),
+        mem_cost: parse("kdf-memory")
+            .and_then(|mib| mib.checked_mul(1024))
+            .unwrap_or(params.mem_cost),
+        time_cost: parse("kdf-time").unwrap_or(params.time_cost),
+        lanes: parse("kdf-lanes").unwrap_or(params.lanes),
+    }
+}
+
+// Arguments for the rules a pin or passphrase must follow
+pub fn pin_policy_args() -> Vec<Arg<'static>> {
+    vec![
+        arg!(--mode <MODE> "Unlock with a short pin or a longer passphrase")
+            .required(false)
+            .possible_values(["pin", "passphrase"]),
+        arg!(--"min-length" <LENGTH> "Minimum pin or passphrase length")
+            .required(false)
+            .validator(is_positive_number),
+        arg!(--"max-length" <LENGTH> "Maximum pin or passphrase length")
+            .required(false)
+            .validator(is_positive_number),
+        arg!(--allow <CLASSES> "Allowed character classes, comma separated")
+            .required(false)
+            .use_value_delimiter(true)
+            .possible_values(["digits", "lowercase", "uppercase", "symbols", "space"]),
+    ]
+}
+
+pub fn listing_arg() -> Arg<'static> {
+    arg!(--listing <POLICY> "Whether listing accounts needs the pin, or reads an index of names")
+        .required(false)
+        .possible_values(["unlock", "index"])
+}
+
+pub fn get_listing(args: &ArgMatches) -> Option<Listing> {
+    args.value_of("listing").and_then(Listing::from_str)
+}
+
+// Tells whether names can now be listed without the pin
+pub fn write_listing(listing: Listing, writer: &mut impl OutErr) {
+    match listing {
+        Listing::Index => writer.write("Account names can be listed without the pin\n"),
+        Listing::Unlock => writer.write("Listing accounts requires the pin\n"),
+    }
+}
+
+// Apply any policy arguments that were supplied on top of an existing policy
+pub fn get_pin_policy(args: &ArgMatches, policy: PinPolicy) -> Result<PinPolicy, String> {
+    let mut policy = match args.value_of("mode") {
+        Some("passphrase") if policy.mode != PinMode::Passphrase => {
+            PinPolicy::new(PinMode::Passphrase)
+        }
+        Some("pin") if policy.mode != PinMode::Pin => PinPolicy::new(PinMode::Pin),
+        _ => policy,
+    };
+
+    let parse = |name| {
+        args.value_of(name)
+            .and_then(|value| value.parse::<usize>().ok())
+    };
+    policy.min_length = parse("min-length").unwrap_or(policy.min_length);
+    policy.max_length = parse("max-length").unwrap_or(policy.max_length);
+    if let Some(classes) = args.values_of("allow") {
+        policy.allowed = classes.filter_map(CharClass::from_str).collect();
+    }
+
+    if policy.min_length > policy.max_length {
+        return Err(String::from(
+            "Minimum length must not be greater than maximum length",
+        ));
+    }
+    Ok(policy)
+}
+
+// Deprecated, since a pin on the command line ends up in shell history and process listings
+pub fn pin_arg(help: &'static str) -> Arg<'static> {
+    arg!(-p --pin <PIN>).help(help).required(false)
+}
+
+// The new pin, typed twice without echo. A pin given with -p is still accepted, with a warning.
+pub fn read_new_pin(
+    args: &ArgMatches,
+    mode: &PinMode,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+) -> Option<Secret<String>> {
+    if let Some(pin) = args.value_of("pin") {
+        writer.write_err(
+            "Warning: -p is deprecated as it leaves the pin in shell history, leave it out to be prompted\n",
+        );
+        return Some(Secret::new(String::from(pin)));
+    }
+
+    let mut entries = Vec::new();
+    for prompt in ["Enter a new", "Confirm the new"] {
+        writer.write_err(&format!("{} {}:\n", prompt, mode.as_str()));
+        let mut buffer = Secret::new(String::new());
+        reader.read_secret(buffer.expose_mut());
+        if buffer.expose().is_empty() {
+            writer.write_err(&format!("No {} entered\n", mode.as_str()));
+            return None;
+        }
+        entries.push(Secret::new(String::from(buffer.expose().trim())));
+    }
+
+    let confirmation = entries.pop();
+    let pin = entries.pop();
+    if pin != confirmation {
+        writer.write_err(&format!("The {}s do not match\n", mode.as_str()));
+        return None;
+    }
+    pin
+}
+
+pub fn write_pin_strength(pin: &str, writer: &mut impl OutErr) {
+    let strength = estimate_strength(pin);
+    writer.write(&format!(
+        "Estimated strength: {} ({:.0} bits)\n",
+        strength.label(),
+        strength.bits
+    ));
+}
@@ -0,0 +1,376 @@
+use clap::{arg, command, ArgMatches, Command};
+
+use super::{
+    get_kdf_params, get_listing, get_pin_policy, kdf_args, listing_arg, pin_arg, pin_policy_args,
+    read_new_pin, write_audit, write_listing, write_pin_strength, CommandType,
+};
+use crate::account::AccountStoreOperations;
+use crate::audit::Outcome;
+use crate::utils::read_keyfile;
+use crate::writer::{OutErr, ReadLine};
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Passwd.as_str())
+        .about("Change the pin and re-encrypt the account store")
+        .args(&[
+            pin_arg("New secret pin or passphrase, deprecated in favour of the prompt"),
+            arg!(--"add-keyfile" <PATH> "Also require this keyfile to unlock")
+                .required(false)
+                .conflicts_with("remove-keyfile"),
+            arg!(--"remove-keyfile" "Stop requiring a keyfile to unlock").required(false),
+            listing_arg(),
+        ])
+        .args(pin_policy_args())
+        .args(kdf_args())
+}
+
+pub fn run_passwd<R, W>(
+    passwd_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    reader: &mut R,
+    writer: &mut W,
+) where
+    R: ReadLine,
+    W: OutErr,
+{
+    let policy = match get_pin_policy(passwd_args, account_store.get_pin_policy()) {
+        Ok(policy) => policy,
+        Err(err) => {
+            writer.write_err(&format!("{}\n", err));
+            return;
+        }
+    };
+    let new_pin = match read_new_pin(passwd_args, &policy.mode, reader, writer) {
+        Some(pin) => pin,
+        None => return,
+    };
+    let new_pin = new_pin.expose().as_str();
+    if let Err(err) = policy.validate(new_pin) {
+        writer.write_err(&format!("{}\n", err));
+        return;
+    }
+
+    // The keyfile in use for unlocking carries over unless it is replaced or removed
+    let keyfile_message = match (
+        passwd_args.value_of("add-keyfile"),
+        passwd_args.is_present("remove-keyfile"),
+    ) {
+        (Some(path), _) => match read_keyfile(path) {
+            Ok(keyfile) => {
+                account_store.set_keyfile(Some(keyfile));
+                Some("Keyfile added\n")
+            }
+            Err(err) => {
+                writer.write_err(&format!("{}\n", err));
+                return;
+            }
+        },
+        (None, true) if !account_store.has_keyfile() => {
+            writer.write_err("The account store does not use a keyfile\n");
+            return;
+        }
+        (None, true) => {
+            account_store.set_keyfile(None);
+            Some("Keyfile removed\n")
+        }
+        (None, false) => None,
+    };
+
+    let listing =
+        get_listing(passwd_args).filter(|listing| *listing != account_store.get_listing());
+    if keyfile_message.is_none() && listing.is_none() && account_store.validate_pin(new_pin) {
+        writer.write_err("New pin must be different from the current pin\n");
+        return;
+    }
+
+    account_store.set_pin_policy(policy);
+    if let Some(listing) = listing {
+        account_store.set_listing(listing);
+    }
+
+    // Keep the store's current Argon2 costs unless new ones are given
+    let kdf = get_kdf_params(passwd_args, account_store.get_kdf_params());
+
+    // A new salt and pin change the derived key, so saving re-encrypts every account
+    if let Err(err) = account_store.set_pin(new_pin, kdf) {
+        writer.write_err(&format!("{}\n", err));
+        return;
+    }
+
+    let outcome = match account_store.save() {
+        Ok(_) => {
+            write_pin_strength(new_pin, writer);
+            writer.write("Pin successfully changed\n");
+            if let Some(message) = keyfile_message {
+                writer.write(message);
+            }
+            if let Some(listing) = listing {
+                write_listing(listing, writer);
+            }
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}\n", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        CommandType::Passwd.as_str(),
+        None,
+        outcome,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::cmd::CommandType::Passwd;
+    use crate::policy::{CharClass, PinMode, PinPolicy};
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+
+    #[test]
+    fn changes_the_pin() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Passwd.as_str()];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[NEW_PIN, NEW_PIN]);
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert!(store.validate_pin(NEW_PIN));
+        assert!(!store.validate_pin(PIN));
+        assert!(store.get(ACCOUNT_NAME_1).is_some());
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "Estimated strength: very weak (3 bits)\nPin successfully changed\n"
+        );
+        assert_eq!(String::from_utf8(writer.err).unwrap(), NEW_PIN_PROMPTS);
+    }
+
+    #[test]
+    fn switches_to_a_passphrase() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Passwd.as_str(), "--mode", "passphrase"];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[PASSPHRASE, PASSPHRASE]);
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert!(store.validate_pin(PASSPHRASE));
+        assert_eq!(store.get_pin_policy().mode, PinMode::Passphrase);
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            NEW_PASSPHRASE_PROMPTS
+        );
+    }
+
+    #[test]
+    fn enforces_the_stored_policy() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+        store.set_pin_policy(PinPolicy {
+            allowed: vec![CharClass::Digits],
+            ..PinPolicy::default()
+        });
+
+        let arg_vec = vec!["otp", Passwd.as_str()];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&["abcd", "abcd"]);
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert!(store.validate_pin(PIN));
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!("{}PIN may only contain digits\n", NEW_PIN_PROMPTS)
+        );
+    }
+
+    #[test]
+    fn raises_kdf_params() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Passwd.as_str(), "--kdf-time", "2"];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[NEW_PIN, NEW_PIN]);
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        let kdf = store.get_kdf_params();
+        assert_eq!(kdf.time_cost, 2);
+        assert_eq!(kdf.mem_cost, test_kdf_params().mem_cost);
+        assert_eq!(String::from_utf8(writer.err).unwrap(), NEW_PIN_PROMPTS);
+    }
+
+    #[test]
+    fn requires_the_new_pin_twice() {
+        let mut store = get_mock_store();
+        let mut reader = MockOtpReader::script(&[NEW_PIN, "5556"]);
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Passwd.as_str()];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert!(store.validate_pin(PIN));
+        assert_eq!(reader.secret_reads, 2);
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!("{}The pins do not match\n", NEW_PIN_PROMPTS)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_new_pin() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Passwd.as_str()];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&["123", "123"]);
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert!(store.validate_pin(PIN));
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!(
+                "{}PIN must be between 4 and 6 characters\n",
+                NEW_PIN_PROMPTS
+            )
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn rejects_unchanged_pin() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Passwd.as_str()];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!(
+                "{}New pin must be different from the current pin\n",
+                NEW_PIN_PROMPTS
+            )
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn returns_error_on_save_failure() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        store.fail_saves();
+
+        let arg_vec = vec!["otp", Passwd.as_str()];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[NEW_PIN, NEW_PIN]);
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!("{}Storage backend failed to save\n", NEW_PIN_PROMPTS)
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn adds_and_removes_a_keyfile() {
+        let mut store = get_mock_store();
+        let path = std::env::temp_dir().join(format!("otp-passwd-keyfile-{}", std::process::id()));
+        std::fs::write(&path, "keyfile contents").unwrap();
+
+        let mut writer = MockOtpWriter::new();
+        let arg_vec = vec![
+            "otp",
+            Passwd.as_str(),
+            "--add-keyfile",
+            path.to_str().unwrap(),
+        ];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert!(store.has_keyfile());
+        assert!(String::from_utf8(writer.out)
+            .unwrap()
+            .ends_with("Pin successfully changed\nKeyfile added\n"));
+        assert_eq!(String::from_utf8(writer.err).unwrap(), NEW_PIN_PROMPTS);
+
+        let mut writer = MockOtpWriter::new();
+        let arg_vec = vec!["otp", Passwd.as_str(), "--remove-keyfile"];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert!(!store.has_keyfile());
+        assert!(String::from_utf8(writer.out)
+            .unwrap()
+            .ends_with("Keyfile removed\n"));
+        store.unlock(PIN).unwrap();
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn switches_the_listing_policy_with_the_same_pin() {
+        let mut store = get_mock_store();
+        let mut reader = MockOtpReader::script(&[PIN, PIN]);
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Passwd.as_str(), "--listing", "index"];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert_eq!(
+            store.reopen().list_names().unwrap(),
+            vec![ACCOUNT_NAME_1, ACCOUNT_NAME_2]
+        );
+        assert!(String::from_utf8(writer.out)
+            .unwrap()
+            .ends_with("Pin successfully changed\nAccount names can be listed without the pin\n"));
+        assert_eq!(String::from_utf8(writer.err).unwrap(), NEW_PIN_PROMPTS);
+    }
+
+    #[test]
+    fn rejects_removing_a_missing_keyfile() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Passwd.as_str(), "--remove-keyfile"];
+        let passwd_args = get_cmd_args(Passwd.as_str(), subcommand(), &arg_vec).unwrap();
+
+        let mut reader = MockOtpReader::script(&[NEW_PIN, NEW_PIN]);
+        run_passwd(&passwd_args, &mut store, &mut reader, &mut writer);
+
+        assert!(store.validate_pin(PIN));
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!(
+                "{}The account store does not use a keyfile\n",
+                NEW_PIN_PROMPTS
+            )
+        );
+    }
+}
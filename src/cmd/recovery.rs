@@ -0,0 +1,371 @@
+use clap::{arg, command, Arg, ArgMatches, Command};
+
+use super::{count_of, find_account_name, write_audit, CommandType};
+use crate::account::{Account, AccountStoreOperations};
+use crate::audit::Outcome;
+use crate::totp::{get_unix_time, GetTime};
+use crate::utils::format_timestamp;
+use crate::writer::{OutErr, ReadLine};
+
+// Warn once this many unused codes or fewer are left
+const LOW_RECOVERY_CODES: usize = 3;
+
+fn account_arg() -> Arg<'static> {
+    arg!(-a --account <NAME> "Account the recovery codes belong to").required(true)
+}
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Recovery.as_str())
+        .about("Store and use one-time recovery codes")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("add")
+                .about("Store recovery codes issued by the service")
+                .args(&[account_arg(), arg!(<CODES>... "Recovery codes to store")]),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List recovery codes and when they were used")
+                .args(&[account_arg()]),
+        )
+        .subcommand(
+            Command::new("use")
+                .about("Mark a recovery code as used, or print and use the next one")
+                .args(&[account_arg(), arg!([CODE] "Recovery code that was used")]),
+        )
+}
+
+pub fn run_recovery(
+    recovery_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+    clock: &impl GetTime,
+) {
+    let (subcommand, args) = match recovery_args.subcommand() {
+        Some(subcommand) => subcommand,
+        None => {
+            writer.write_err("Unknown recovery subcommand\n");
+            return;
+        }
+    };
+    let account_name = match args.value_of("account") {
+        Some(account_name) => account_name,
+        None => {
+            writer.write_err("Account name is required\n");
+            return;
+        }
+    };
+    let account_name = match find_account_name(account_name, account_store, reader, writer) {
+        Some(account_name) => account_name,
+        None => return,
+    };
+    let account = match account_store.get_mut(&account_name) {
+        Some(account) => account,
+        None => {
+            writer.write_err(&format!("Account not found: {}\n", account_name));
+            return;
+        }
+    };
+
+    let message = match subcommand {
+        "add" => {
+            let codes: Vec<&str> = args
+                .values_of("CODES")
+                .map(|codes| codes.map(str::trim).collect())
+                .unwrap_or_default();
+            let added = account.add_recovery_codes(&codes);
+            let mut message = format!(
+                "Added {} to \"{}\"\n",
+                count_of(added, "recovery code"),
+                account_name
+            );
+            if added < codes.len() {
+                message.push_str(&format!(
+                    "Skipped {} already stored\n",
+                    count_of(codes.len() - added, "recovery code")
+                ));
+            }
+            message
+        }
+        "list" => {
+            write_codes(&account_name, account, writer);
+            return;
+        }
+        "use" => {
+            let code = args.value_of("CODE").map(str::trim);
+            match account.use_recovery_code(code, get_unix_time(clock)) {
+                Ok(used) if code.is_none() => format!("{}\n", used.expose()),
+                Ok(_) => String::from("Recovery code marked as used\n"),
+                Err(err) => {
+                    writer.write_err(&format!("{}\n", err));
+                    write_audit(
+                        account_store,
+                        writer,
+                        "recovery-use",
+                        Some(&account_name),
+                        Outcome::Failure,
+                    );
+                    return;
+                }
+            }
+        }
+        _ => {
+            writer.write_err("Unknown recovery subcommand\n");
+            return;
+        }
+    };
+
+    // Only hand out a code once it is recorded as used
+    let outcome = match account_store.save() {
+        Ok(_) => {
+            writer.write(&message);
+            if let Some(account) = account_store.get(&account_name) {
+                write_low_warning(&account_name, account, writer);
+            }
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        &format!("recovery-{}", subcommand),
+        Some(&account_name),
+        outcome,
+    );
+}
+
+fn write_codes(account_name: &str, account: &Account, writer: &mut impl OutErr) {
+    writer.write(&format!("Recovery codes for \"{}\":\n", account_name));
+    for recovery_code in &account.recovery_codes {
+        match recovery_code.used_at {
+            Some(used_at) => writer.write(&format!(
+                "{} (used {})\n",
+                recovery_code.code.expose(),
+                format_timestamp(used_at)
+            )),
+            None => writer.write(&format!("{}\n", recovery_code.code.expose())),
+        }
+    }
+    writer.write(&format!(
+        "{} of {} left\n",
+        account.remaining_recovery_codes(),
+        account.recovery_codes.len()
+    ));
+    write_low_warning(account_name, account, writer);
+}
+
+fn write_low_warning(account_name: &str, account: &Account, writer: &mut impl OutErr) {
+    let remaining = account.remaining_recovery_codes();
+    if account.recovery_codes.is_empty() || remaining > LOW_RECOVERY_CODES {
+        return;
+    }
+    match remaining {
+        0 => writer.write_err(&format!(
+            "Warning: no recovery codes left for \"{}\"\n",
+            account_name
+        )),
+        remaining => writer.write_err(&format!(
+            "Warning: only {} left for \"{}\"\n",
+            count_of(remaining, "recovery code"),
+            account_name
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::account::AccountStore;
+    use crate::cmd::CommandType::Recovery;
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+
+    const CODES: [&str; 5] = [
+        "aaaa-1111",
+        "bbbb-2222",
+        "cccc-3333",
+        "dddd-4444",
+        "eeee-5555",
+    ];
+
+    fn get_recovery_store() -> AccountStore {
+        let mut store = get_mock_store();
+        store
+            .get_mut(ACCOUNT_NAME_1)
+            .unwrap()
+            .add_recovery_codes(&CODES);
+        store
+    }
+
+    fn run(store: &mut AccountStore, args: &[&str]) -> MockOtpWriter {
+        let mut writer = MockOtpWriter::new();
+        let mut arg_vec = vec!["otp", Recovery.as_str()];
+        arg_vec.extend_from_slice(args);
+        let recovery_args = get_cmd_args(Recovery.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_recovery(
+            &recovery_args,
+            store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+        writer
+    }
+
+    #[test]
+    fn adds_recovery_codes() {
+        let mut store = get_mock_store();
+
+        let writer = run(
+            &mut store,
+            &["add", "-a", ACCOUNT_NAME_1, "aaaa-1111", "bbbb-2222"],
+        );
+
+        let account = store.get(ACCOUNT_NAME_1).unwrap();
+        assert_eq!(account.recovery_codes.len(), 2);
+        assert_eq!(account.recovery_codes[1].code.expose(), "bbbb-2222");
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            format!("Added 2 recovery codes to \"{}\"\n", ACCOUNT_NAME_1)
+        );
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!(
+                "Warning: only 2 recovery codes left for \"{}\"\n",
+                ACCOUNT_NAME_1
+            )
+        );
+    }
+
+    #[test]
+    fn skips_codes_already_stored() {
+        let mut store = get_recovery_store();
+
+        let writer = run(
+            &mut store,
+            &["add", "-a", ACCOUNT_NAME_1, "aaaa-1111", "ffff-6666"],
+        );
+
+        assert_eq!(store.get(ACCOUNT_NAME_1).unwrap().recovery_codes.len(), 6);
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            format!(
+                "Added 1 recovery code to \"{}\"\nSkipped 1 recovery code already stored\n",
+                ACCOUNT_NAME_1
+            )
+        );
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn lists_recovery_codes() {
+        let mut store = get_recovery_store();
+        store
+            .get_mut(ACCOUNT_NAME_1)
+            .unwrap()
+            .use_recovery_code(Some("bbbb-2222"), 60)
+            .unwrap();
+
+        let writer = run(&mut store, &["list", "-a", ACCOUNT_NAME_1]);
+
+        let expected_output = format!(
+            "Recovery codes for \"{}\":\naaaa-1111\nbbbb-2222 (used 1970-01-01 00:01:00 UTC)\ncccc-3333\ndddd-4444\neeee-5555\n4 of 5 left\n",
+            ACCOUNT_NAME_1
+        );
+        assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn uses_the_next_recovery_code() {
+        let mut store = get_recovery_store();
+
+        let writer = run(&mut store, &["use", "-a", ACCOUNT_NAME_1]);
+
+        let account = store.get(ACCOUNT_NAME_1).unwrap();
+        assert_eq!(account.recovery_codes[0].used_at, Some(90));
+        assert_eq!(String::from_utf8(writer.out).unwrap(), "aaaa-1111\n");
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn marks_a_given_recovery_code_as_used() {
+        let mut store = get_recovery_store();
+
+        let writer = run(&mut store, &["use", "-a", ACCOUNT_NAME_1, "cccc-3333"]);
+
+        let account = store.get(ACCOUNT_NAME_1).unwrap();
+        assert_eq!(account.recovery_codes[0].used_at, None);
+        assert_eq!(account.recovery_codes[2].used_at, Some(90));
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "Recovery code marked as used\n"
+        );
+    }
+
+    #[test]
+    fn warns_when_few_recovery_codes_remain() {
+        let mut store = get_recovery_store();
+        run(&mut store, &["use", "-a", ACCOUNT_NAME_1]);
+
+        let writer = run(&mut store, &["use", "-a", ACCOUNT_NAME_1]);
+
+        assert_eq!(String::from_utf8(writer.out).unwrap(), "bbbb-2222\n");
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!(
+                "Warning: only 3 recovery codes left for \"{}\"\n",
+                ACCOUNT_NAME_1
+            )
+        );
+    }
+
+    #[test]
+    fn reports_codes_that_were_already_used() {
+        let mut store = get_recovery_store();
+        run(&mut store, &["use", "-a", ACCOUNT_NAME_1, "aaaa-1111"]);
+
+        let writer = run(&mut store, &["use", "-a", ACCOUNT_NAME_1, "aaaa-1111"]);
+
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Recovery code was already used on 1970-01-01 00:01:30 UTC\n"
+        );
+    }
+
+    #[test]
+    fn reports_when_no_codes_are_left() {
+        let mut store = get_mock_store();
+
+        let writer = run(&mut store, &["use", "-a", ACCOUNT_NAME_2]);
+
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "No recovery codes left\n"
+        );
+    }
+
+    #[test]
+    fn records_errors_on_save_failure() {
+        let mut store = get_recovery_store();
+        store.fail_saves();
+
+        let writer = run(&mut store, &["use", "-a", ACCOUNT_NAME_1]);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Storage backend failed to save"
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+}
@@ -0,0 +1,158 @@
+use clap::{arg, command, ArgMatches, Command};
+
+use super::{write_audit, CommandType};
+use crate::account::AccountStoreOperations;
+use crate::audit::Outcome;
+use crate::writer::OutErr;
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Rename.as_str())
+        .about("Rename an account")
+        .args(&[
+            arg!(<OLD> "Current account name"),
+            arg!(<NEW> "New account name"),
+        ])
+}
+
+pub fn run_rename(
+    rename_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    writer: &mut impl OutErr,
+) {
+    let (account_name, new_name) = match (rename_args.value_of("OLD"), rename_args.value_of("NEW"))
+    {
+        (Some(account_name), Some(new_name)) => (account_name, new_name),
+        _ => {
+            writer.write_err("Current and new account names are required\n");
+            return;
+        }
+    };
+
+    if let Err(err) = account_store.rename(account_name, new_name) {
+        writer.write_err(&format!("{}\n", err));
+        write_audit(
+            account_store,
+            writer,
+            CommandType::Rename.as_str(),
+            Some(account_name),
+            Outcome::Failure,
+        );
+        return;
+    }
+
+    let outcome = match account_store.save() {
+        Ok(_) => {
+            writer.write(&format!(
+                "Account \"{}\" renamed to \"{}\"\n",
+                account_name, new_name
+            ));
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        CommandType::Rename.as_str(),
+        Some(new_name),
+        outcome,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::cmd::CommandType::Rename;
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+
+    #[test]
+    fn renames_an_account() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Rename.as_str(), ACCOUNT_NAME_1, ACCOUNT_NAME_3];
+        let rename_args = get_cmd_args(Rename.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_rename(&rename_args, &mut store, &mut writer);
+
+        assert_eq!(store.get(ACCOUNT_NAME_1), None);
+        assert_eq!(store.get(ACCOUNT_NAME_3).unwrap().key.expose(), "key-1");
+
+        let expected_output = format!(
+            "Account \"{}\" renamed to \"{}\"\n",
+            ACCOUNT_NAME_1, ACCOUNT_NAME_3
+        );
+        assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn refuses_to_overwrite_an_existing_account() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Rename.as_str(), ACCOUNT_NAME_1, ACCOUNT_NAME_2];
+        let rename_args = get_cmd_args(Rename.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_rename(&rename_args, &mut store, &mut writer);
+
+        assert_eq!(store.get(ACCOUNT_NAME_1).unwrap().key.expose(), "key-1");
+        assert_eq!(store.get(ACCOUNT_NAME_2).unwrap().key.expose(), "key-2");
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!("Account already exists: {}\n", ACCOUNT_NAME_2)
+        );
+    }
+
+    #[test]
+    fn errors_when_account_does_not_exist() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Rename.as_str(), "not_an_account", ACCOUNT_NAME_3];
+        let rename_args = get_cmd_args(Rename.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_rename(&rename_args, &mut store, &mut writer);
+
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Account not found: not_an_account\n"
+        );
+    }
+
+    #[test]
+    fn requires_both_names() {
+        let arg_vec = vec!["otp", Rename.as_str(), ACCOUNT_NAME_1];
+        let rename_args = get_cmd_args(Rename.as_str(), subcommand(), &arg_vec);
+
+        assert!(rename_args.is_err());
+        assert!(rename_args.unwrap_err().to_string().contains("<NEW>"));
+    }
+
+    #[test]
+    fn records_errors_on_save_failure() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        store.fail_saves();
+
+        let arg_vec = vec!["otp", Rename.as_str(), ACCOUNT_NAME_1, ACCOUNT_NAME_3];
+        let rename_args = get_cmd_args(Rename.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_rename(&rename_args, &mut store, &mut writer);
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Storage backend failed to save"
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+}
@@ -0,0 +1,162 @@
+use clap::{arg, command, ArgMatches, Command};
+
+use super::{count_of, find_account_name, write_audit, CommandType};
+use crate::account::AccountStoreOperations;
+use crate::audit::Outcome;
+use crate::totp::{get_unix_time, GetTime};
+use crate::utils::{format_timestamp, generate_secret_32, is_positive_number};
+use crate::writer::{OutErr, ReadLine};
+
+const SECONDS_PER_HOUR: u64 = 60 * 60;
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Rotate.as_str())
+        .about("Replace the key of an account, accepting the old one for a grace period")
+        .args(&[
+            arg!(-a --account <NAME> "Account name to rotate the key of").required(true),
+            arg!(-g --grace <HOURS> "Hours that codes from the old key are still accepted")
+                .required(false)
+                .default_value("24")
+                .validator(is_positive_number),
+        ])
+}
+
+pub fn run_rotate<W>(
+    rotate_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
+    writer: &mut W,
+    clock: &impl GetTime,
+) where
+    W: OutErr,
+{
+    let account_name = match rotate_args.value_of("account") {
+        Some(account_name) => account_name,
+        None => {
+            writer.write_err("Account name is required\n");
+            return;
+        }
+    };
+    let account_name = match find_account_name(account_name, account_store, reader, writer) {
+        Some(account_name) => account_name,
+        None => return,
+    };
+    let grace_hours = rotate_args
+        .value_of("grace")
+        .and_then(|hours| hours.parse::<u64>().ok())
+        .unwrap_or(24);
+    let expires_at = get_unix_time(clock) + grace_hours * SECONDS_PER_HOUR;
+
+    let uri = match account_store.get_mut(&account_name) {
+        Some(account) => {
+            account.rotate_key(generate_secret_32(), expires_at);
+            account.get_uri(&account_name)
+        }
+        None => {
+            writer.write_err(&format!("Account not found: {}\n", account_name));
+            return;
+        }
+    };
+
+    let outcome = match account_store.save() {
+        Ok(_) => {
+            // Only the URI goes to stdout, so it can be piped into a QR code generator
+            writer.write_err(&format!(
+                "Rotated the key of \"{}\", the old key is accepted for {} until {}\n",
+                account_name,
+                count_of(grace_hours as usize, "hour"),
+                format_timestamp(expires_at)
+            ));
+            writer.write(&format!("{}\n", uri));
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}\n", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        CommandType::Rotate.as_str(),
+        Some(&account_name),
+        outcome,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::account::{Account, Algorithm, OtpType};
+    use crate::cmd::CommandType::Rotate;
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+    use data_encoding::BASE32_NOPAD;
+
+    #[test]
+    fn rotates_the_key_and_keeps_the_old_one() {
+        let mut store = get_mock_store();
+        let mut account = Account::new(String::from(TOTP_KEY), OtpType::TOTP);
+        account.issuer = Some(String::from("Pets & Co"));
+        account.label = Some(String::from("jane@pets.com"));
+        store.add(String::from(ACCOUNT_NAME_3), account);
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Rotate.as_str(), "-a", ACCOUNT_NAME_3, "-g", "2"];
+        let rotate_args = get_cmd_args(Rotate.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_rotate(
+            &rotate_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        let account = store.get(ACCOUNT_NAME_3).unwrap();
+        let previous_key = account.previous_key.as_ref().unwrap();
+        assert_eq!(previous_key.key.expose(), TOTP_KEY);
+        assert_eq!(previous_key.expires_at, 90 + 2 * SECONDS_PER_HOUR);
+        assert_eq!(account.key.expose().len(), TOTP_KEY.len());
+        assert_ne!(account.key.expose(), TOTP_KEY);
+        assert_eq!(account.algorithm, Some(Algorithm::SHA256));
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            format!(
+                "otpauth://totp/Pets%20%26%20Co:jane%40pets.com?issuer=Pets%20%26%20Co&\
+                 secret={}&algorithm=SHA256&digits=6&period=30\n",
+                BASE32_NOPAD.encode(account.key.expose().as_bytes())
+            )
+        );
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Rotated the key of \"test_account_3\", the old key is accepted for 2 hours \
+             until 1970-01-01 02:01:30 UTC\n"
+        );
+    }
+
+    #[test]
+    fn errors_when_account_does_not_exist() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec!["otp", Rotate.as_str(), "-a", "missing"];
+        let rotate_args = get_cmd_args(Rotate.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_rotate(
+            &rotate_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Account not found: missing\n"
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+}
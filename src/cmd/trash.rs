@@ -0,0 +1,329 @@
+use clap::{arg, command, ArgMatches, Command};
+
+use super::{confirm, count_of, write_audit, yes_arg, CommandType};
+use crate::account::AccountStoreOperations;
+use crate::audit::Outcome;
+use crate::totp::{get_unix_time, GetTime};
+use crate::utils::{format_timestamp, is_positive_number};
+use crate::writer::{OutErr, ReadLine};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Trash.as_str())
+        .about("List, restore or purge deleted accounts")
+        .subcommand_required(true)
+        .subcommand(Command::new("list").about("List deleted accounts"))
+        .subcommand(
+            Command::new("restore")
+                .about("Restore a deleted account")
+                .args(&[
+                    arg!(<NAME> "Name of the deleted account"),
+                    arg!(--"as" <NEW_NAME> "Restore under a different name").required(false),
+                ]),
+        )
+        .subcommand(
+            Command::new("purge")
+                .about("Permanently delete accounts in the trash")
+                .args(&[
+                    arg!(--"older-than" <DAYS> "Only purge accounts deleted at least this many days ago")
+                        .required(false)
+                        .validator(is_positive_number),
+                    yes_arg(),
+                ]),
+        )
+}
+
+pub fn run_trash(
+    trash_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+    clock: &impl GetTime,
+) {
+    match trash_args.subcommand() {
+        Some(("list", _)) => run_list(account_store, writer),
+        Some(("restore", restore_args)) => run_restore(restore_args, account_store, writer),
+        Some(("purge", purge_args)) => run_purge(purge_args, account_store, reader, writer, clock),
+        _ => writer.write_err("Unknown trash subcommand\n"),
+    }
+}
+
+fn run_list(account_store: &impl AccountStoreOperations, writer: &mut impl OutErr) {
+    writer.write("Trash:\n");
+    for trashed in account_store.list_trash() {
+        writer.write(&format!(
+            "{} (deleted {})\n",
+            trashed.name,
+            format_timestamp(trashed.deleted_at)
+        ));
+    }
+}
+
+fn run_restore(
+    restore_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    writer: &mut impl OutErr,
+) {
+    let account_name = match restore_args.value_of("NAME") {
+        Some(account_name) => account_name,
+        None => {
+            writer.write_err("Account name is required\n");
+            return;
+        }
+    };
+    let new_name = restore_args.value_of("as").unwrap_or(account_name);
+
+    if let Err(err) = account_store.restore(account_name, new_name) {
+        writer.write_err(&format!("{}\n", err));
+        write_audit(
+            account_store,
+            writer,
+            "trash-restore",
+            Some(account_name),
+            Outcome::Failure,
+        );
+        return;
+    }
+
+    let outcome = match account_store.save() {
+        Ok(_) if new_name == account_name => {
+            writer.write(&format!("Account \"{}\" restored\n", account_name));
+            Outcome::Success
+        }
+        Ok(_) => {
+            writer.write(&format!(
+                "Account \"{}\" restored as \"{}\"\n",
+                account_name, new_name
+            ));
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        "trash-restore",
+        Some(new_name),
+        outcome,
+    );
+}
+
+fn run_purge(
+    purge_args: &ArgMatches,
+    account_store: &mut impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+    clock: &impl GetTime,
+) {
+    let deleted_before = purge_args
+        .value_of("older-than")
+        .and_then(|days| days.parse::<u64>().ok())
+        .map(|days| get_unix_time(clock).saturating_sub(days * SECONDS_PER_DAY));
+    let count = account_store
+        .list_trash()
+        .iter()
+        .filter(|trashed| deleted_before.is_none_or(|before| trashed.deleted_at < before))
+        .count();
+
+    if count == 0 {
+        writer.write("Nothing to purge\n");
+        return;
+    }
+    let question = format!(
+        "Permanently delete {} from the trash?",
+        count_of(count, "account")
+    );
+    if !confirm(purge_args, &question, reader, writer) {
+        return;
+    }
+
+    let purged = account_store.purge(deleted_before);
+    let outcome = match account_store.save() {
+        Ok(_) => {
+            writer.write(&format!(
+                "Purged {} from the trash\n",
+                count_of(purged, "account")
+            ));
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(account_store, writer, "trash-purge", None, outcome);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::account::{Account, AccountStore, OtpType};
+    use crate::cmd::CommandType::Trash;
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+
+    // Ten days after the epoch, so purge cutoffs land between the test deletions
+    struct DayClock {}
+
+    impl GetTime for DayClock {
+        fn get_now(&self) -> std::time::SystemTime {
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(10 * SECONDS_PER_DAY)
+        }
+    }
+
+    fn get_trash_store() -> AccountStore {
+        let mut store = get_mock_store();
+        store.delete(ACCOUNT_NAME_1, SECONDS_PER_DAY);
+        store.delete(ACCOUNT_NAME_2, 9 * SECONDS_PER_DAY);
+        store
+    }
+
+    fn run(store: &mut AccountStore, reader: &mut MockOtpReader, args: &[&str]) -> MockOtpWriter {
+        let mut writer = MockOtpWriter::new();
+        let mut arg_vec = vec!["otp", Trash.as_str()];
+        arg_vec.extend_from_slice(args);
+        let trash_args = get_cmd_args(Trash.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_trash(&trash_args, store, reader, &mut writer, &DayClock {});
+        writer
+    }
+
+    #[test]
+    fn lists_deleted_accounts() {
+        let mut store = get_trash_store();
+
+        let writer = run(&mut store, &mut MockOtpReader::new(""), &["list"]);
+
+        let expected_output = format!(
+            "Trash:\n{} (deleted 1970-01-02 00:00:00 UTC)\n{} (deleted 1970-01-10 00:00:00 UTC)\n",
+            ACCOUNT_NAME_1, ACCOUNT_NAME_2
+        );
+        assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn restores_a_deleted_account() {
+        let mut store = get_trash_store();
+
+        let writer = run(
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &["restore", ACCOUNT_NAME_1],
+        );
+
+        assert_eq!(store.get(ACCOUNT_NAME_1).unwrap().key.expose(), "key-1");
+        assert_eq!(store.list_trash().len(), 1);
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            format!("Account \"{}\" restored\n", ACCOUNT_NAME_1)
+        );
+    }
+
+    #[test]
+    fn restores_under_a_new_name() {
+        let mut store = get_trash_store();
+
+        let writer = run(
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &["restore", ACCOUNT_NAME_1, "--as", ACCOUNT_NAME_3],
+        );
+
+        assert_eq!(store.get(ACCOUNT_NAME_3).unwrap().key.expose(), "key-1");
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            format!(
+                "Account \"{}\" restored as \"{}\"\n",
+                ACCOUNT_NAME_1, ACCOUNT_NAME_3
+            )
+        );
+    }
+
+    #[test]
+    fn does_not_restore_over_an_existing_account() {
+        let mut store = get_trash_store();
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from("key-3"), OtpType::TOTP),
+        );
+
+        let writer = run(
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &["restore", ACCOUNT_NAME_1],
+        );
+
+        assert_eq!(store.get(ACCOUNT_NAME_1).unwrap().key.expose(), "key-3");
+        assert_eq!(store.list_trash().len(), 2);
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            format!("Account already exists: {}\n", ACCOUNT_NAME_1)
+        );
+    }
+
+    #[test]
+    fn purges_old_accounts() {
+        let mut store = get_trash_store();
+
+        let writer = run(
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &["purge", "--older-than", "7", "--yes"],
+        );
+
+        assert_eq!(store.list_trash().len(), 1);
+        assert_eq!(store.list_trash()[0].name, ACCOUNT_NAME_2);
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "Purged 1 account from the trash\n"
+        );
+    }
+
+    #[test]
+    fn purges_everything_after_confirmation() {
+        let mut store = get_trash_store();
+
+        let writer = run(
+            &mut store,
+            &mut MockOtpReader::interactive("yes\n"),
+            &["purge"],
+        );
+
+        assert!(store.list_trash().is_empty());
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Permanently delete 2 accounts from the trash? [y/N] "
+        );
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "Purged 2 accounts from the trash\n"
+        );
+    }
+
+    #[test]
+    fn does_not_purge_without_confirmation() {
+        let mut store = get_trash_store();
+
+        let writer = run(&mut store, &mut MockOtpReader::new(""), &["purge"]);
+
+        assert_eq!(store.list_trash().len(), 2);
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn reports_an_empty_trash() {
+        let mut store = get_mock_store();
+
+        let writer = run(&mut store, &mut MockOtpReader::new(""), &["purge", "--yes"]);
+
+        assert_eq!(String::from_utf8(writer.out).unwrap(), "Nothing to purge\n");
+    }
+}
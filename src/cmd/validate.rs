@@ -1,10 +1,11 @@
 use clap::{arg, command, ArgMatches, Command};
 
-use super::CommandType;
-use crate::account::{AccountStoreOperations, OtpType};
+use super::{find_account_name, write_audit, CommandType};
+use crate::account::{Account, AccountStoreOperations, OtpType, PRIMARY_CREDENTIAL};
+use crate::audit::Outcome;
 use crate::hotp::validate_hotp;
-use crate::totp::{validate_totp, GetTime};
-use crate::writer::OutErr;
+use crate::totp::{get_unix_time, validate_totp, GetTime};
+use crate::writer::{OutErr, ReadLine};
 
 pub fn subcommand() -> Command<'static> {
     command!(CommandType::Validate.as_str())
@@ -16,9 +17,20 @@ pub fn subcommand() -> Command<'static> {
         ])
 }
 
+// The server implementing the HOTP check should update its counter to prevent replay attacks
+fn validate_code(account: &Account, code: u32, clock: &impl GetTime) -> std::io::Result<u32> {
+    match account.otp_type {
+        OtpType::TOTP => validate_totp(account, code, clock),
+        OtpType::HOTP(_) => {
+            validate_hotp(account, code).map(|(_new_counter, valid_code)| valid_code)
+        }
+    }
+}
+
 pub fn run_validate<W>(
     validate_args: &ArgMatches,
-    account_store: &impl AccountStoreOperations,
+    account_store: &mut impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
     writer: &mut W,
     clock: &impl GetTime,
 ) where
@@ -35,51 +47,74 @@ pub fn run_validate<W>(
         }
     };
 
-    let account = account_store.get(account_name);
-
-    match account {
-        None => writer.write_err(&format!("Account not found: {}\n", account_name)),
-        Some(account) => {
-            let parsed_token = match token.parse::<u32>() {
-                Ok(parsed_token) => parsed_token,
-                Err(err) => {
-                    writer.write_err(&format!("Unable to parse token: {}\n", err));
-                    return;
-                }
-            };
-
-            let is_totp = match account.otp_type {
-                OtpType::TOTP => true,
-                _ => false,
-            };
-
-            if is_totp {
-                let result = validate_totp(&account, parsed_token, clock);
-                match result {
-                    Ok(valid_code) => writer.write(&format!("{} valid\n", valid_code)),
-                    Err(err) => writer.write_err(&format!("{}\n", err)),
-                }
-            } else {
-                let result = validate_hotp(&account, parsed_token);
-                match result {
-                    Ok((_new_counter, valid_code)) => {
-                        writer.write(&format!("{} valid\n", valid_code));
-
-                        // The server implementing this check should update its counter to prevent replay attacks
-                        /*
-                        account_store.set_counter(account_name, new_counter);
-
-                        match account_store.save() {
-                            Ok(_) => println!("Success!"),
-                            Err(err) => eprintln!("Unable to save account: {}", err),
-                        }
-                        */
-                    }
-                    Err(err) => writer.write_err(&format!("{}\n", err)),
-                }
-            }
+    let account_name = match find_account_name(account_name, account_store, reader, writer) {
+        Some(account_name) => account_name,
+        None => return,
+    };
+
+    let account = match account_store.get(&account_name) {
+        Some(account) => account,
+        None => {
+            writer.write_err(&format!("Account not found: {}\n", account_name));
+            return;
         }
-    }
+    };
+    let parsed_token = match token.parse::<u32>() {
+        Ok(parsed_token) => parsed_token,
+        Err(err) => {
+            writer.write_err(&format!("Unable to parse token: {}\n", err));
+            return;
+        }
+    };
+
+    // Codes from a rotated key still count until its grace period is over
+    let result = validate_code(account, parsed_token, clock)
+        .map(|valid_code| match account.credentials.is_empty() {
+            true => format!("{} valid\n", valid_code),
+            false => format!(
+                "{} valid with credential \"{}\"\n",
+                valid_code, PRIMARY_CREDENTIAL
+            ),
+        })
+        .or_else(|err| match account.get_previous(get_unix_time(clock)) {
+            Some(previous) => validate_code(&previous, parsed_token, clock)
+                .map(|valid_code| format!("{} valid with the previous key\n", valid_code)),
+            None => Err(err),
+        })
+        .or_else(|err| {
+            account
+                .credentials
+                .iter()
+                .find_map(|credential| {
+                    let credential_account = account.get_credential(&credential.name)?;
+                    validate_code(&credential_account, parsed_token, clock)
+                        .ok()
+                        .map(|valid_code| {
+                            format!(
+                                "{} valid with credential \"{}\"\n",
+                                valid_code, credential.name
+                            )
+                        })
+                })
+                .ok_or(err)
+        });
+    let outcome = match result {
+        Ok(message) => {
+            writer.write(&message);
+            Outcome::Success
+        }
+        Err(err) => {
+            writer.write_err(&format!("{}\n", err));
+            Outcome::Failure
+        }
+    };
+    write_audit(
+        account_store,
+        writer,
+        CommandType::Validate.as_str(),
+        Some(&account_name),
+        outcome,
+    );
 }
 
 #[cfg(test)]
@@ -92,8 +127,9 @@ mod tests {
     use crate::tests::utils::get_cmd_args;
 
     #[test]
+    #[allow(clippy::useless_format)]
     fn validates_totp() {
-        let store = get_mock_store();
+        let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
         let arg_vec = vec![
@@ -106,7 +142,13 @@ mod tests {
         ];
         let validate_args = get_cmd_args(Validate.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_validate(&validate_args, &store, &mut writer, &MockClock::new());
+        run_validate(
+            &validate_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let expected_output = format!("249961 valid\n");
         assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
@@ -114,8 +156,9 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_format)]
     fn validates_hotp() {
-        let store = get_mock_store();
+        let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
         let arg_vec = vec![
@@ -128,13 +171,46 @@ mod tests {
         ];
         let validate_args = get_cmd_args(Validate.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_validate(&validate_args, &store, &mut writer, &MockClock::new());
+        run_validate(
+            &validate_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let expected_output = format!("543440 valid\n");
         assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
         assert_eq!(writer.err, Vec::new());
     }
 
+    #[test]
+    fn validates_ignoring_case() {
+        let mut store = get_mock_store();
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Validate.as_str(),
+            "-a",
+            "Test_Account_2",
+            "-t",
+            "249961",
+        ];
+        let validate_args = get_cmd_args(Validate.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_validate(
+            &validate_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+
+        assert_eq!(String::from_utf8(writer.out).unwrap(), "249961 valid\n");
+        assert_eq!(writer.err, Vec::new());
+    }
+
     #[test]
     fn requires_account_name() {
         let arg_vec = vec!["otp", Validate.as_str(), "-t", "249961"];
@@ -167,7 +243,7 @@ mod tests {
 
     #[test]
     fn errors_when_account_not_found() {
-        let store = get_mock_store();
+        let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
         let arg_vec = vec![
@@ -180,7 +256,13 @@ mod tests {
         ];
         let validate_args = get_cmd_args(Validate.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_validate(&validate_args, &store, &mut writer, &MockClock::new());
+        run_validate(
+            &validate_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let expected_output = format!("Account not found: {}\n", "not_an_account");
         assert_eq!(String::from_utf8(writer.err).unwrap(), expected_output);
@@ -189,7 +271,7 @@ mod tests {
 
     #[test]
     fn errors_when_token_cannot_be_parsed() {
-        let store = get_mock_store();
+        let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
         let arg_vec = vec![
@@ -202,7 +284,13 @@ mod tests {
         ];
         let validate_args = get_cmd_args(Validate.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_validate(&validate_args, &store, &mut writer, &MockClock::new());
+        run_validate(
+            &validate_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let expected_output = format!(
             "Unable to parse token: {}\n",
@@ -213,8 +301,9 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_format)]
     fn does_not_accept_invalid_totp() {
-        let store = get_mock_store();
+        let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
         let arg_vec = vec![
@@ -227,7 +316,13 @@ mod tests {
         ];
         let validate_args = get_cmd_args(Validate.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_validate(&validate_args, &store, &mut writer, &MockClock::new());
+        run_validate(
+            &validate_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let expected_output = format!("Invalid code\n");
         assert_eq!(String::from_utf8(writer.err).unwrap(), expected_output);
@@ -235,8 +330,9 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_format)]
     fn does_not_accept_invalid_hotp() {
-        let store = get_mock_store();
+        let mut store = get_mock_store();
         let mut writer = MockOtpWriter::new();
 
         let arg_vec = vec![
@@ -249,10 +345,93 @@ mod tests {
         ];
         let validate_args = get_cmd_args(Validate.as_str(), subcommand(), &arg_vec).unwrap();
 
-        run_validate(&validate_args, &store, &mut writer, &MockClock::new());
+        run_validate(
+            &validate_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
 
         let expected_output = format!("Invalid code\n");
         assert_eq!(String::from_utf8(writer.err).unwrap(), expected_output);
         assert_eq!(writer.out, Vec::new());
     }
+
+    fn validate_after_rotating(expires_at: u64) -> MockOtpWriter {
+        let mut store = get_mock_store();
+        store
+            .get_mut(ACCOUNT_NAME_2)
+            .unwrap()
+            .rotate_key(String::from(TOTP_KEY), expires_at);
+        let mut writer = MockOtpWriter::new();
+
+        let arg_vec = vec![
+            "otp",
+            Validate.as_str(),
+            "-a",
+            ACCOUNT_NAME_2,
+            "-t",
+            "249961",
+        ];
+        let validate_args = get_cmd_args(Validate.as_str(), subcommand(), &arg_vec).unwrap();
+
+        run_validate(
+            &validate_args,
+            &mut store,
+            &mut MockOtpReader::new(""),
+            &mut writer,
+            &MockClock::new(),
+        );
+        writer
+    }
+
+    #[test]
+    fn accepts_the_previous_key_until_it_expires() {
+        let writer = validate_after_rotating(91);
+
+        assert_eq!(
+            String::from_utf8(writer.out).unwrap(),
+            "249961 valid with the previous key\n"
+        );
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn rejects_the_previous_key_once_expired() {
+        let writer = validate_after_rotating(90);
+
+        assert_eq!(String::from_utf8(writer.err).unwrap(), "Invalid code\n");
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn accepts_codes_from_other_credentials() {
+        let mut store = get_mock_store();
+        store
+            .get_mut(ACCOUNT_NAME_2)
+            .unwrap()
+            .add_credential("yubikey", String::from(HOTP_KEY), OtpType::HOTP(Some(0)))
+            .unwrap();
+
+        for (token, expected_output) in [
+            ("249961", "249961 valid with credential \"primary\"\n"),
+            ("052123", "52123 valid with credential \"yubikey\"\n"),
+        ] {
+            let mut writer = MockOtpWriter::new();
+            let arg_vec = vec!["otp", Validate.as_str(), "-a", ACCOUNT_NAME_2, "-t", token];
+            let validate_args = get_cmd_args(Validate.as_str(), subcommand(), &arg_vec).unwrap();
+
+            run_validate(
+                &validate_args,
+                &mut store,
+                &mut MockOtpReader::new(""),
+                &mut writer,
+                &MockClock::new(),
+            );
+
+            assert_eq!(String::from_utf8(writer.out).unwrap(), expected_output);
+            assert_eq!(writer.err, Vec::new());
+        }
+    }
 }
@@ -0,0 +1,248 @@
+use clap::{command, ArgMatches, Command};
+use std::io::Result;
+
+use super::{count_of, CommandType};
+use crate::account::{AccountStore, AccountStoreOperations};
+use crate::storage::StorageBackend;
+use crate::verify::{check_files, check_secrets, Finding, Severity};
+use crate::writer::OutErr;
+
+pub fn subcommand() -> Command<'static> {
+    command!(CommandType::Verify.as_str())
+        .about("Check the account store for damage and weak secrets, without changing it")
+        .alias("doctor")
+}
+
+fn write_findings(findings: &[Finding], writer: &mut impl OutErr) {
+    for finding in findings {
+        writer.write(&format!(
+            "{}: {}\n",
+            finding.severity.as_str(),
+            finding.message
+        ));
+        if let Some(fix) = &finding.fix {
+            writer.write(&format!("  Fix: {}\n", fix));
+        }
+    }
+}
+
+// Returns whether the account store passed, so the exit status can tell scripts
+pub fn run_verify<W: OutErr>(
+    _verify_args: &ArgMatches,
+    backend: Result<Box<dyn StorageBackend>>,
+    unlock: impl FnOnce(&mut AccountStore, &mut W) -> std::result::Result<(), String>,
+    writer: &mut W,
+) -> bool {
+    let findings = verify(backend, unlock, writer);
+    write_findings(&findings, writer);
+
+    let count = |severity| {
+        findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    };
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    match (errors, warnings) {
+        (0, 0) => writer.write("No problems found\n"),
+        _ => writer.write(&format!(
+            "Found {} and {}\n",
+            count_of(errors, "error"),
+            count_of(warnings, "warning")
+        )),
+    }
+    errors == 0
+}
+
+fn verify<W: OutErr>(
+    backend: Result<Box<dyn StorageBackend>>,
+    unlock: impl FnOnce(&mut AccountStore, &mut W) -> std::result::Result<(), String>,
+    writer: &mut W,
+) -> Vec<Finding> {
+    let restore =
+        "If the pin is right, restore the accounts and secrets together from the same backup";
+    let backend = match backend {
+        Ok(backend) => backend,
+        Err(err) => {
+            return vec![Finding::error(
+                &format!("Unable to open the storage backend: {}", err),
+                "Restore the account store directory from a backup",
+            )]
+        }
+    };
+    // Nothing to check before init, and missing files are expected then
+    if backend
+        .load_secrets()
+        .is_ok_and(|secrets| secrets.trim().is_empty())
+    {
+        return vec![Finding::error(
+            "No account store found",
+            "Run 'otp init' to create one, or restore it from a backup",
+        )];
+    }
+    let mut paths = backend.paths();
+    if let Some(directory) = paths.first().and_then(|path| path.parent()) {
+        paths.insert(0, directory.to_path_buf());
+    }
+    let mut findings = check_files(&paths);
+    findings.extend(AccountStore::inspect(backend.as_ref()));
+    // Decrypting a store that is already known to be damaged can only fail
+    if findings
+        .iter()
+        .any(|finding| finding.severity == Severity::Error)
+    {
+        return findings;
+    }
+
+    let mut account_store = match AccountStore::open(backend) {
+        Ok(account_store) => account_store,
+        Err(err) => {
+            findings.push(Finding::error(&err.to_string(), restore));
+            return findings;
+        }
+    };
+    if let Err(err) = unlock(&mut account_store, writer) {
+        findings.push(Finding::error(&err, restore));
+        return findings;
+    }
+    findings.push(Finding::ok(&format!(
+        "Decrypted and authenticated {}",
+        count_of(account_store.list().len(), "account")
+    )));
+    findings.extend(check_secrets(&account_store));
+
+    match account_store.read_audit_log() {
+        Ok(report) => match report.error {
+            Some(err) => findings.push(Finding::warning(
+                &format!("The audit log failed verification: {}", err),
+                "Keep the log as evidence and compare it with a backup",
+            )),
+            None => findings.push(Finding::ok(&format!(
+                "The audit log is intact with {}",
+                count_of(report.entries.len(), "entry")
+            ))),
+        },
+        Err(err) => findings.push(Finding::warning(
+            &format!("Unable to read the audit log: {}", err),
+            "Restore the audit log from a backup",
+        )),
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{Account, OtpType};
+    use crate::cmd::CommandType::Verify;
+    use crate::storage::MemoryBackend;
+    use crate::tests::constants::*;
+    use crate::tests::mocks::*;
+    use crate::tests::utils::get_cmd_args;
+
+    fn get_backend() -> Box<dyn StorageBackend> {
+        let mut account_store = AccountStore::open(Box::new(MemoryBackend::new())).unwrap();
+        account_store.set_pin(PIN, test_kdf_params()).unwrap();
+        account_store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from(HOTP_KEY), OtpType::HOTP(Some(0))),
+        );
+        account_store.save().unwrap();
+        account_store.into_backend()
+    }
+
+    fn run(backend: Box<dyn StorageBackend>, pin: &str) -> (bool, String) {
+        let arg_vec = vec!["otp", Verify.as_str()];
+        let verify_args = get_cmd_args(Verify.as_str(), subcommand(), &arg_vec).unwrap();
+        let mut writer = MockOtpWriter::new();
+        let passed = run_verify(
+            &verify_args,
+            Ok(backend),
+            |account_store, _| account_store.unlock(pin).map_err(|err| err.to_string()),
+            &mut writer,
+        );
+        (passed, String::from_utf8(writer.out).unwrap())
+    }
+
+    #[test]
+    fn passes_a_healthy_store() {
+        let (passed, out) = run(get_backend(), PIN);
+
+        assert!(passed);
+        assert_eq!(
+            out,
+            "ok: The secrets are valid TOML\n\
+             ok: The nonce matches the encrypted accounts\n\
+             ok: Decrypted and authenticated 1 account\n\
+             ok: No weak or duplicate secrets\n\
+             ok: The audit log is intact with 0 entries\n\
+             No problems found\n"
+        );
+    }
+
+    #[test]
+    fn suggests_init_without_a_store() {
+        let (passed, out) = run(Box::new(MemoryBackend::new()), PIN);
+
+        assert!(!passed);
+        assert_eq!(
+            out,
+            "error: No account store found\n  \
+             Fix: Run 'otp init' to create one, or restore it from a backup\n\
+             Found 1 error and 0 warnings\n"
+        );
+    }
+
+    #[test]
+    fn suggests_a_backup_when_the_accounts_fail_to_authenticate() {
+        let mut backend = get_backend();
+        let secrets = backend.load_secrets().unwrap();
+        let mut accounts = backend.load_accounts().unwrap();
+        accounts[0] ^= 1;
+        backend.commit(&secrets, &accounts).unwrap();
+
+        let (passed, out) = run(backend, PIN);
+
+        assert!(!passed);
+        assert!(out.contains(
+            "error: Decryption failed\n  \
+             Fix: If the pin is right, restore the accounts and secrets together from the same backup\n"
+        ));
+        assert!(out.ends_with("Found 1 error and 0 warnings\n"));
+    }
+
+    #[test]
+    fn reports_secrets_that_are_not_toml() {
+        let mut backend = get_backend();
+        let accounts = backend.load_accounts().unwrap();
+        backend.commit("hash = ", &accounts).unwrap();
+
+        let (passed, out) = run(backend, PIN);
+
+        assert!(!passed);
+        assert!(out.starts_with("error: The secrets are not valid: "));
+        assert!(!out.contains("Decrypted"));
+    }
+
+    #[test]
+    fn reports_a_nonce_of_the_wrong_length() {
+        let mut backend = get_backend();
+        let secrets = backend
+            .load_secrets()
+            .unwrap()
+            .lines()
+            .map(|line| match line.starts_with("nonce = ") {
+                true => String::from("nonce = [1, 2, 3]"),
+                false => String::from(line),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let accounts = backend.load_accounts().unwrap();
+        backend.commit(&secrets, &accounts).unwrap();
+
+        let (passed, out) = run(backend, PIN);
+
+        assert!(!passed);
+        assert!(out.contains("error: The nonce is 3 bytes long instead of 24\n"));
+    }
+}
@@ -1,34 +1,149 @@
-use argon2::{self, Config, Error};
+use argon2::{self, Config, Error, Variant};
 use chacha20poly1305::aead::{Aead, NewAead};
 use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use data_encoding::HEXLOWER;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use rand::rngs::OsRng;
 use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
 
-pub fn encrypt_pw(pw: &str) -> Result<String, Error> {
-    let mut salt = [0u8; 32];
-    OsRng.fill_bytes(&mut salt);
-    let config = Config::default();
-    let hash = argon2::hash_encoded(pw.as_bytes(), &salt, &config);
-    hash
+use crate::secret::{Secret, SecretBytes};
+
+const KEY_LENGTH: u32 = 32;
+const SALT_LENGTH: usize = 32;
+pub const NONCE_LENGTH: usize = 24;
+// Poly1305 appends a tag of this many bytes to every ciphertext
+pub const TAG_LENGTH: usize = 16;
+// More memory than an unlock should ever need, so a typo can't make every unlock abort
+pub const MAX_MEMORY_MIB: u32 = 4 * 1024;
+const MEMBER_KEY_INFO: &[u8] = b"otp member key";
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KdfVariant {
+    Argon2i,
+    Argon2d,
+    Argon2id,
 }
 
-pub fn decrypt_pw(hash: &str, pw: &str) -> bool {
-    let verification = argon2::verify_encoded(&hash, &pw.as_bytes());
-    match verification {
-        Ok(result) => result,
-        _ => false,
+impl KdfVariant {
+    pub fn as_str(&self) -> &str {
+        match self {
+            KdfVariant::Argon2i => "argon2i",
+            KdfVariant::Argon2d => "argon2d",
+            KdfVariant::Argon2id => "argon2id",
+        }
+    }
+
+    pub fn from_str(variant: &str) -> Option<Self> {
+        match variant {
+            "argon2i" => Some(KdfVariant::Argon2i),
+            "argon2d" => Some(KdfVariant::Argon2d),
+            "argon2id" => Some(KdfVariant::Argon2id),
+            _ => None,
+        }
     }
 }
 
+// Argon2 cost parameters, recorded in the store so they can be raised later
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct KdfParams {
+    pub variant: KdfVariant,
+    // Memory in KiB
+    pub mem_cost: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+}
 
-fn generate_nonce() -> [u8; 24] {
-    let mut dest = [0u8; 24];
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            variant: KdfVariant::Argon2id,
+            mem_cost: 64 * 1024,
+            time_cost: 3,
+            lanes: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    fn config(&self) -> Config<'_> {
+        let variant = match self.variant {
+            KdfVariant::Argon2i => Variant::Argon2i,
+            KdfVariant::Argon2d => Variant::Argon2d,
+            KdfVariant::Argon2id => Variant::Argon2id,
+        };
+        Config {
+            variant,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            hash_length: KEY_LENGTH,
+            ..Config::default()
+        }
+    }
+}
+
+// The keyfile is the Argon2 secret here as well, or the pin could be guessed from the hash alone
+pub fn encrypt_pw(pw: &str, params: &KdfParams, keyfile: Option<&[u8]>) -> Result<String, Error> {
+    let salt = generate_salt();
+    let config = Config {
+        secret: keyfile.unwrap_or_default(),
+        ..params.config()
+    };
+    argon2::hash_encoded(pw.as_bytes(), &salt, &config)
+}
+
+pub fn decrypt_pw(hash: &str, pw: &str, keyfile: Option<&[u8]>) -> bool {
+    let verification =
+        argon2::verify_encoded_ext(hash, pw.as_bytes(), keyfile.unwrap_or_default(), &[]);
+    verification.unwrap_or_default()
+}
+
+// Derive the 32 byte key used to encrypt the account store. A keyfile is mixed in as the
+// Argon2 secret, so the key can't be derived from the pin alone.
+pub fn derive_key(
+    pw: &str,
+    salt: &[u8],
+    params: &KdfParams,
+    keyfile: Option<&[u8]>,
+) -> Result<SecretBytes, Error> {
+    let config = Config {
+        secret: keyfile.unwrap_or_default(),
+        ..params.config()
+    };
+    argon2::hash_raw(pw.as_bytes(), salt, &config).map(Secret::new)
+}
+
+// Keyfiles can be any size, so only their digest is kept in memory
+pub fn hash_keyfile(contents: &[u8]) -> SecretBytes {
+    Secret::new(Sha256::digest(contents).to_vec())
+}
+
+// HMAC-SHA256 in hex, which stands in for data that must not be stored in the clear
+pub fn keyed_digest(key: &[u8], data: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    HEXLOWER.encode(&mac.finalize().into_bytes())
+}
+
+pub fn generate_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+fn generate_nonce() -> [u8; NONCE_LENGTH] {
+    let mut dest = [0u8; NONCE_LENGTH];
     OsRng.fill_bytes(&mut dest);
     dest
 }
 
-pub fn encrypt_string(text: &str, salt: &Vec<u8>) -> Result<(Vec<u8>, Vec<u8>), String> {
-    let key = Key::from_slice(&salt); // 32-bytes
+pub fn encrypt_string(text: &str, key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let key = Key::from_slice(key); // 32-bytes
     let aead = XChaCha20Poly1305::new(key);
 
     let nonce_seed = generate_nonce();
@@ -41,42 +156,214 @@ pub fn encrypt_string(text: &str, salt: &Vec<u8>) -> Result<(Vec<u8>, Vec<u8>),
 }
 
 pub fn decrypt_string(
-    ciphertext: &Vec<u8>,
-    salt: &Vec<u8>,
-    nonce_seed: &Vec<u8>,
-) -> Result<String, String> {
-    let nonce = XNonce::from_slice(&nonce_seed);
-    let key = Key::from_slice(&salt); // 32-bytes
+    ciphertext: &[u8],
+    key: &[u8],
+    nonce_seed: &[u8],
+) -> Result<Secret<String>, String> {
+    if nonce_seed.len() != NONCE_LENGTH {
+        return Err(String::from("Decryption failure: invalid nonce"));
+    }
+    let nonce = XNonce::from_slice(nonce_seed);
+    let key = Key::from_slice(key); // 32-bytes
     let aead = XChaCha20Poly1305::new(key);
 
     let plaintext_bytes = aead
         .decrypt(nonce, ciphertext.as_ref())
         .map_err(|e| format!("Decryption failure: {}", e))?;
 
+    // Converting reuses the decrypted buffer, so the only copy is wiped on drop
     let plaintext = String::from_utf8(plaintext_bytes);
     match plaintext {
-        Ok(text) => Ok(text),
-        Err(e) => Err(format!("Decryption failure: {}", e)),
+        Ok(text) => Ok(Secret::new(text)),
+        Err(e) => {
+            let message = format!("Decryption failure: {}", e.utf8_error());
+            drop(Secret::new(e.into_bytes()));
+            Err(message)
+        }
+    }
+}
+
+// A random key for the accounts of a shared store, which no pin can be derived from
+pub fn generate_key() -> SecretBytes {
+    let mut key = Secret::new(vec![0u8; KEY_LENGTH as usize]);
+    OsRng.fill_bytes(key.expose_mut());
+    key
+}
+
+pub fn encrypt_key(key: &[u8], wrapping_key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let aead = XChaCha20Poly1305::new(Key::from_slice(wrapping_key));
+    let nonce = generate_nonce();
+    let ciphertext = aead
+        .encrypt(XNonce::from_slice(&nonce), key)
+        .map_err(|e| format!("Encryption failure: {}", e))?;
+    Ok((ciphertext, nonce.to_vec()))
+}
+
+pub fn decrypt_key(
+    ciphertext: &[u8],
+    wrapping_key: &[u8],
+    nonce: &[u8],
+) -> Result<SecretBytes, String> {
+    if nonce.len() != NONCE_LENGTH {
+        return Err(String::from("Decryption failure: invalid nonce"));
     }
+    let aead = XChaCha20Poly1305::new(Key::from_slice(wrapping_key));
+    aead.decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map(Secret::new)
+        .map_err(|e| format!("Decryption failure: {}", e))
+}
+
+// A new X25519 identity for a member of a shared store, as its secret and public keys
+pub fn generate_identity() -> (SecretBytes, Vec<u8>) {
+    let mut identity = Secret::new(vec![0u8; KEY_LENGTH as usize]);
+    OsRng.fill_bytes(identity.expose_mut());
+    let public_key = get_public_key(identity.expose()).unwrap_or_default();
+    (identity, public_key)
+}
+
+fn to_key_bytes(key: &[u8]) -> Result<[u8; 32], String> {
+    <[u8; 32]>::try_from(key).map_err(|_| format!("X25519 keys are 32 bytes, not {}", key.len()))
+}
+
+pub fn get_public_key(identity: &[u8]) -> Result<Vec<u8>, String> {
+    let secret = StaticSecret::from(to_key_bytes(identity)?);
+    Ok(PublicKey::from(&secret).as_bytes().to_vec())
+}
+
+// A key encrypted for one member, with the one-off public key needed to agree on its key
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SealedKey {
+    pub ephemeral_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+// As age does for its recipients: X25519 with a one-off key, HKDF-SHA256 over the shared
+// secret salted with both public keys, then the key is encrypted with XChaCha20Poly1305
+fn member_wrapping_key(
+    secret: &StaticSecret,
+    their_public_key: &PublicKey,
+    ephemeral_key: &PublicKey,
+    recipient: &PublicKey,
+) -> Result<SecretBytes, String> {
+    let shared = secret.diffie_hellman(their_public_key);
+    // Low order points give a shared secret anyone could compute
+    if !shared.was_contributory() {
+        return Err(String::from("Invalid X25519 public key"));
+    }
+    let salt = [ephemeral_key.as_bytes().as_slice(), recipient.as_bytes()].concat();
+    let mut wrapping_key = Secret::new(vec![0u8; KEY_LENGTH as usize]);
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(MEMBER_KEY_INFO, wrapping_key.expose_mut())
+        .map_err(|e| format!("Key derivation failure: {}", e))?;
+    Ok(wrapping_key)
+}
+
+pub fn seal_key(key: &[u8], public_key: &[u8]) -> Result<SealedKey, String> {
+    let recipient = PublicKey::from(to_key_bytes(public_key)?);
+    let (ephemeral_secret, ephemeral_key) = generate_identity();
+    let ephemeral_secret = StaticSecret::from(to_key_bytes(ephemeral_secret.expose())?);
+    let ephemeral_key = PublicKey::from(to_key_bytes(&ephemeral_key)?);
+    let wrapping_key =
+        member_wrapping_key(&ephemeral_secret, &recipient, &ephemeral_key, &recipient)?;
+    let (key, nonce) = encrypt_key(key, wrapping_key.expose())?;
+    Ok(SealedKey {
+        ephemeral_key: ephemeral_key.as_bytes().to_vec(),
+        nonce,
+        key,
+    })
+}
+
+pub fn open_sealed_key(sealed_key: &SealedKey, identity: &[u8]) -> Result<SecretBytes, String> {
+    let secret = StaticSecret::from(to_key_bytes(identity)?);
+    let recipient = PublicKey::from(&secret);
+    let ephemeral_key = PublicKey::from(to_key_bytes(&sealed_key.ephemeral_key)?);
+    let wrapping_key = member_wrapping_key(&secret, &ephemeral_key, &ephemeral_key, &recipient)?;
+    decrypt_key(&sealed_key.key, wrapping_key.expose(), &sealed_key.nonce)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tests::constants::test_kdf_params;
 
     #[test]
+    #[allow(clippy::op_ref)]
     fn hashes_and_verifies_a_password() {
         let password = "some_good_password!@#";
-        let hash = encrypt_pw(password).unwrap();
+        let hash = encrypt_pw(password, &test_kdf_params(), None).unwrap();
         assert!(password != &hash);
-        assert!(decrypt_pw(&hash, password));
+        assert!(decrypt_pw(&hash, password, None));
     }
 
     #[test]
     fn fails_to_verify_a_password_with_wrong_hash() {
         let password = "some_good_password!@#";
-        let hash = encrypt_pw(password).unwrap();
-        assert!(!decrypt_pw(&hash, "wrong_password"));
+        let hash = encrypt_pw(password, &test_kdf_params(), None).unwrap();
+        assert!(!decrypt_pw(&hash, "wrong_password", None));
+    }
+
+    #[test]
+    fn needs_the_keyfile_to_verify_a_password() {
+        let password = "some_good_password!@#";
+        let hash = encrypt_pw(password, &test_kdf_params(), Some(b"keyfile")).unwrap();
+        assert!(decrypt_pw(&hash, password, Some(b"keyfile")));
+        assert!(!decrypt_pw(&hash, password, Some(b"another keyfile")));
+        assert!(!decrypt_pw(&hash, password, None));
+    }
+
+    #[test]
+    fn hashes_with_configured_params() {
+        let params = KdfParams {
+            variant: KdfVariant::Argon2id,
+            mem_cost: 256,
+            time_cost: 2,
+            lanes: 2,
+        };
+        let hash = encrypt_pw("123456", &params, None).unwrap();
+        assert!(
+            hash.starts_with("$argon2id$v=19$m=256,t=2,p=2$"),
+            "{}",
+            hash
+        );
+    }
+
+    #[test]
+    fn defaults_to_argon2id() {
+        let params = KdfParams::default();
+        assert_eq!(params.variant, KdfVariant::Argon2id);
+        assert_eq!(params.mem_cost, 65536);
+    }
+
+    #[test]
+    fn derives_the_same_key_from_the_same_pin_and_salt() {
+        let salt = generate_salt();
+        let key = derive_key("123456", &salt, &test_kdf_params(), None).unwrap();
+        assert_eq!(key.expose().len(), 32);
+        assert_eq!(
+            key,
+            derive_key("123456", &salt, &test_kdf_params(), None).unwrap()
+        );
+        assert!(key != derive_key("654321", &salt, &test_kdf_params(), None).unwrap());
+        assert!(key != derive_key("123456", &generate_salt(), &test_kdf_params(), None).unwrap());
+    }
+
+    #[test]
+    fn mixes_the_keyfile_into_the_key() {
+        let salt = generate_salt();
+        let keyfile = hash_keyfile(b"keyfile contents");
+        let key = derive_key("123456", &salt, &test_kdf_params(), Some(keyfile.expose())).unwrap();
+
+        assert_eq!(keyfile.expose().len(), 32);
+        assert_eq!(
+            key,
+            derive_key("123456", &salt, &test_kdf_params(), Some(keyfile.expose())).unwrap()
+        );
+        assert!(key != derive_key("123456", &salt, &test_kdf_params(), None).unwrap());
+        let other = hash_keyfile(b"other contents");
+        assert!(
+            key != derive_key("123456", &salt, &test_kdf_params(), Some(other.expose())).unwrap()
+        );
     }
 
     #[test]
@@ -86,16 +373,54 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::len_zero)]
     fn encrypts_and_decrypts_a_string() {
-        let hash = encrypt_pw("123456").unwrap();
-        let salt = hash.into_bytes()[..32].to_vec();
+        let key = derive_key("123456", &generate_salt(), &test_kdf_params(), None).unwrap();
 
         let text = "some_text";
-        let (ciphertext, nonce) = encrypt_string(text, &salt).unwrap();
+        let (ciphertext, nonce) = encrypt_string(text, key.expose()).unwrap();
         assert!(ciphertext.len() > 0);
         assert!(ciphertext != text.as_bytes());
 
-        let decrypted_text = decrypt_string(&ciphertext, &salt, &nonce).unwrap();
-        assert_eq!(text, decrypted_text);
+        let decrypted_text = decrypt_string(&ciphertext, key.expose(), &nonce).unwrap();
+        assert_eq!(text, decrypted_text.expose());
+    }
+
+    #[test]
+    fn refuses_a_nonce_of_the_wrong_length() {
+        let key = derive_key("123456", &generate_salt(), &test_kdf_params(), None).unwrap();
+        let (ciphertext, nonce) = encrypt_string("some_text", key.expose()).unwrap();
+
+        assert_eq!(
+            decrypt_string(&ciphertext, key.expose(), &nonce[..12]).unwrap_err(),
+            "Decryption failure: invalid nonce"
+        );
+    }
+
+    #[test]
+    fn seals_a_key_for_a_member() {
+        let key = generate_key();
+        let (identity, public_key) = generate_identity();
+        let (other_identity, _) = generate_identity();
+
+        let sealed_key = seal_key(key.expose(), &public_key).unwrap();
+
+        assert_eq!(
+            open_sealed_key(&sealed_key, identity.expose()).unwrap(),
+            key
+        );
+        assert!(open_sealed_key(&sealed_key, other_identity.expose()).is_err());
+        assert_ne!(sealed_key.key, *key.expose());
+    }
+
+    #[test]
+    fn refuses_low_order_public_keys() {
+        let key = generate_key();
+
+        assert_eq!(
+            seal_key(key.expose(), &[0u8; 32]).unwrap_err(),
+            "Invalid X25519 public key"
+        );
+        assert!(seal_key(key.expose(), &[1u8; 31]).is_err());
     }
 }
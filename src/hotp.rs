@@ -1,14 +1,49 @@
 use hmac::{Hmac, Mac};
 use sha1::Sha1;
+use sha2::{Sha256, Sha512};
 use std::io::{Error, ErrorKind};
 
-use crate::account::{Account, OtpType};
+use crate::account::{Account, Algorithm, OtpType};
 
 type HmacSha1 = Hmac<Sha1>;
+type HmacSha256 = Hmac<Sha256>;
+type HmacSha512 = Hmac<Sha512>;
+
+// Accounts left on the default algorithm keep the codes of the first version, which read the
+// truncation offset from byte 19 and counted HOTP with four bytes. Those given an algorithm
+// follow RFC 4226 and 6238, which read the offset from the last byte and count with eight.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codes {
+    Legacy,
+    Rfc,
+}
+
+// Uses the algorithm and number of digits configured for the account. The counter is the
+// eight bytes of RFC 4226, or the four the first version used for legacy codes.
+pub fn get_hotp(account: &Account, counter: i32) -> u32 {
+    let counter = match account.get_codes() {
+        Codes::Legacy => counter.to_be_bytes().to_vec(),
+        Codes::Rfc => i64::from(counter).to_be_bytes().to_vec(),
+    };
+    get_code(
+        account.key.expose().as_bytes(),
+        account.get_algorithm(),
+        &counter,
+        account.get_digits(),
+        account.get_codes(),
+    )
+}
 
-pub fn get_hotp(secret: &str, counter: i32) -> u32 {
-    let hmac = make_hmac(secret.as_bytes(), counter);
-    truncate(hmac)
+// HMAC the moving factor and truncate it to a code, shared with TOTP
+pub fn get_code(
+    secret: &[u8],
+    algorithm: Algorithm,
+    moving_factor: &[u8],
+    digits: u32,
+    codes: Codes,
+) -> u32 {
+    let hmac = make_hmac(secret, algorithm, moving_factor);
+    truncate(hmac, digits, codes)
 }
 
 pub fn validate_hotp(account: &Account, code: u32) -> Result<(i32, u32), Error> {
@@ -23,35 +58,46 @@ pub fn validate_hotp(account: &Account, code: u32) -> Result<(i32, u32), Error>
         }
     };
 
-    println!("entered: {}", code);
-
     for i in counter..counter + window_size {
-        let test_code = get_hotp(&account.key, i);
-        println!("Trying {}", test_code);
+        let test_code = get_hotp(account, i);
         if test_code == code {
             return Ok((i + 1, test_code));
         }
     }
 
-    Err(Error::new(ErrorKind::Other, "Invalid code"))
+    Err(Error::other("Invalid code"))
 }
 
-// HMAC_SHA-1 -> 20 byte string
-fn make_hmac(secret: &[u8], counter: i32) -> Vec<u8> {
-    let mut mac =
-        HmacSha1::new_from_slice(secret).expect("Problem with secret, failed to initialize HMAC");
-    mac.update(&counter.to_be_bytes());
-    let result = mac.finalize();
-
-    result.into_bytes().to_vec()
+// HMAC_SHA-1 -> 20 byte string, HMAC_SHA-256 -> 32 and HMAC_SHA-512 -> 64
+fn make_hmac(secret: &[u8], algorithm: Algorithm, moving_factor: &[u8]) -> Vec<u8> {
+    match algorithm {
+        Algorithm::SHA1 => {
+            let mut mac = HmacSha1::new_from_slice(secret)
+                .expect("Problem with secret, failed to initialize HMAC");
+            mac.update(moving_factor);
+            mac.finalize().into_bytes().to_vec()
+        }
+        Algorithm::SHA256 => {
+            let mut mac = HmacSha256::new_from_slice(secret)
+                .expect("Problem with secret, failed to initialize HMAC");
+            mac.update(moving_factor);
+            mac.finalize().into_bytes().to_vec()
+        }
+        Algorithm::SHA512 => {
+            let mut mac = HmacSha512::new_from_slice(secret)
+                .expect("Problem with secret, failed to initialize HMAC");
+            mac.update(moving_factor);
+            mac.finalize().into_bytes().to_vec()
+        }
+    }
 }
 
 // reduce to 4 byte string
 // then s to num mod 10^Digit
-fn truncate(hmac: Vec<u8>) -> u32 {
-    let base_code = dynamic_truncation(hmac);
+fn truncate(hmac: Vec<u8>, digits: u32, codes: Codes) -> u32 {
+    let base_code = dynamic_truncation(hmac, codes);
 
-    base_code % u32::pow(10, 6)
+    base_code % u32::pow(10, digits)
 }
 
 // DT(String) // String = String[0]...String[19]
@@ -65,34 +111,90 @@ fn truncate(hmac: Vec<u8>) -> u32 {
 //    | (hmac_result[offset+1] & 0xff) << 16
 //    | (hmac_result[offset+2] & 0xff) <<  8
 //    | (hmac_result[offset+3] & 0xff) ;
-fn dynamic_truncation(hmac: Vec<u8>) -> u32 {
-    let offset = (hmac[19] & 0xf) as usize;
-    let code = (hmac[offset] as u32 & 0x7f) << 24
+
+// RFC 6238 takes the offset from the last byte, which is String[19] only for SHA-1
+fn dynamic_truncation(hmac: Vec<u8>, codes: Codes) -> u32 {
+    let offset = match codes {
+        Codes::Legacy => hmac[19] & 0xf,
+        Codes::Rfc => hmac[hmac.len() - 1] & 0xf,
+    } as usize;
+    (hmac[offset] as u32 & 0x7f) << 24
         | (hmac[offset + 1] as u32 & 0xff) << 16
         | (hmac[offset + 2] as u32 & 0xff) << 8
-        | (hmac[offset + 3] as u32 & 0xff);
-    code
+        | (hmac[offset + 3] as u32 & 0xff)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::totp::get_totp;
 
     const SECRET: &str = "N5WUS53LQBPNVSEE6CH5WHATMVAONRMJ";
+    const TOTP_SECRET: &str = "BS5LINH6DJQY2Z4KEXCSUUBA5DXMVMXCXIDBSB2VSR42VJZBUMLQ";
 
     fn get_test_account() -> Account {
         Account::new(SECRET.to_string(), OtpType::HOTP(Some(0)))
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn gets_an_otp_value() {
+        let account = get_test_account();
         let expected_codes = vec![852775, 551063, 206217, 660610, 418804];
         for c in 0..5 {
-            let otp = get_hotp(SECRET, c);
+            let otp = get_hotp(&account, c);
             assert_eq!(expected_codes[c as usize], otp);
         }
     }
 
+    #[test]
+    fn gets_an_otp_value_with_account_settings() {
+        let mut account = get_test_account();
+        account.digits = Some(8);
+        assert_eq!(get_hotp(&account, 0) % 1_000_000, 852775);
+
+        account.algorithm = Some(Algorithm::SHA512);
+        assert_ne!(get_hotp(&account, 0) % 1_000_000, 852775);
+    }
+
+    // RFC 6238 Appendix B, with the seed for each algorithm repeated to its key length
+    #[test]
+    fn matches_the_rfc_6238_test_vectors() {
+        let seed = b"1234567890";
+        let vectors: [(u64, [u32; 3]); 6] = [
+            (59, [94287082, 46119246, 90693936]),
+            (1111111109, [7081804, 68084774, 25091201]),
+            (1111111111, [14050471, 67062674, 99943326]),
+            (1234567890, [89005924, 91819424, 93441116]),
+            (2000000000, [69279037, 90698825, 38618901]),
+            (20000000000, [65353130, 77737706, 47863826]),
+        ];
+        let algorithms = [
+            (Algorithm::SHA1, 20),
+            (Algorithm::SHA256, 32),
+            (Algorithm::SHA512, 64),
+        ];
+        for (time, codes) in vectors {
+            for ((algorithm, key_length), code) in algorithms.iter().zip(codes) {
+                let secret = seed.repeat(7)[..*key_length].to_vec();
+                let moving_factor = (time / 30).to_be_bytes();
+                assert_eq!(
+                    get_code(&secret, *algorithm, &moving_factor, 8, Codes::Rfc),
+                    code
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reads_the_rfc_offset_only_for_an_explicit_algorithm() {
+        let mut account = Account::new(String::from(TOTP_SECRET), OtpType::TOTP);
+        assert_eq!(get_totp(&account, 55077978), 335913);
+
+        account.algorithm = Some(Algorithm::SHA256);
+        assert_eq!(get_totp(&account, 55077978), 532036);
+    }
+
     #[test]
     fn validates_an_otp_value() {
         let account = get_test_account();
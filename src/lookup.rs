@@ -0,0 +1,147 @@
+use unicode_normalization::UnicodeNormalization;
+
+const MAX_SUGGESTIONS: usize = 3;
+
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    Found(String),
+    Ambiguous(Vec<String>),
+    NotFound(Vec<String>),
+}
+
+// Compatibility-normalized and lowercased, so "Ｇithub" and "GitHub" compare equal
+pub fn normalize(name: &str) -> String {
+    name.nfkc().collect::<String>().to_lowercase()
+}
+
+// Find an account by exact name, then by normalized name, then by unique prefix.
+// Misses come back with the closest names as suggestions.
+pub fn find_account(query: &str, names: &[String]) -> Lookup {
+    if names.iter().any(|name| name == query) {
+        return Lookup::Found(String::from(query));
+    }
+
+    let query = normalize(query);
+    let normalized: Vec<(String, &String)> =
+        names.iter().map(|name| (normalize(name), name)).collect();
+
+    let exact: Vec<String> = normalized
+        .iter()
+        .filter(|(normalized, _)| *normalized == query)
+        .map(|(_, name)| String::from(*name))
+        .collect();
+    let matches = match exact.is_empty() {
+        true => normalized
+            .iter()
+            .filter(|(normalized, _)| normalized.starts_with(&query))
+            .map(|(_, name)| String::from(*name))
+            .collect(),
+        false => exact,
+    };
+
+    match matches.len() {
+        0 => Lookup::NotFound(suggest(&query, &normalized)),
+        1 => Lookup::Found(matches.into_iter().next().unwrap_or_default()),
+        _ => Lookup::Ambiguous(matches),
+    }
+}
+
+// Names within a few edits of the query, or containing it, closest first
+fn suggest(query: &str, normalized: &[(String, &String)]) -> Vec<String> {
+    let max_distance = (query.chars().count() / 3).max(2);
+    let mut suggestions: Vec<(usize, &String)> = normalized
+        .iter()
+        .filter_map(|(normalized, name)| {
+            let distance = strsim::levenshtein(query, normalized);
+            match distance <= max_distance || normalized.contains(query) {
+                true => Some((distance, *name)),
+                false => None,
+            }
+        })
+        .collect();
+    suggestions.sort();
+    suggestions
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, name)| String::from(name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        ["github", "github-work", "gitlab", "Pets.com", "café"]
+            .iter()
+            .map(|name| String::from(*name))
+            .collect()
+    }
+
+    #[test]
+    fn finds_exact_names() {
+        assert_eq!(
+            find_account("github", &names()),
+            Lookup::Found(String::from("github"))
+        );
+    }
+
+    #[test]
+    fn finds_names_ignoring_case() {
+        assert_eq!(
+            find_account("PETS.COM", &names()),
+            Lookup::Found(String::from("Pets.com"))
+        );
+    }
+
+    #[test]
+    fn finds_names_with_different_unicode_forms() {
+        // "e" followed by a combining acute accent
+        assert_eq!(
+            find_account("cafe\u{301}", &names()),
+            Lookup::Found(String::from("café"))
+        );
+        // Fullwidth letters
+        assert_eq!(
+            find_account("ＧＩＴＬＡＢ", &names()),
+            Lookup::Found(String::from("gitlab"))
+        );
+    }
+
+    #[test]
+    fn finds_unique_prefixes() {
+        assert_eq!(
+            find_account("pet", &names()),
+            Lookup::Found(String::from("Pets.com"))
+        );
+        assert_eq!(
+            find_account("github-", &names()),
+            Lookup::Found(String::from("github-work"))
+        );
+    }
+
+    #[test]
+    fn reports_ambiguous_prefixes() {
+        assert_eq!(
+            find_account("git", &names()),
+            Lookup::Ambiguous(vec![
+                String::from("github"),
+                String::from("github-work"),
+                String::from("gitlab"),
+            ])
+        );
+    }
+
+    #[test]
+    fn suggests_close_names() {
+        assert_eq!(
+            find_account("gihtub", &names()),
+            Lookup::NotFound(vec![String::from("github")])
+        );
+        assert_eq!(
+            find_account("work", &names()),
+            Lookup::NotFound(vec![String::from("github-work")])
+        );
+        assert_eq!(find_account("bank", &names()), Lookup::NotFound(vec![]));
+    }
+}
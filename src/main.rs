@@ -1,20 +1,37 @@
-use clap::command;
+use clap::{arg, command};
 use writer::ReadLine;
 
 use crate::account::{AccountStore, AccountStoreOperations};
-use crate::cmd::CommandType::{Add, Delete, Generate, Get, Init, List, Validate};
-use crate::totp::Clock;
-use crate::utils::validate_pin;
-use crate::writer::{OtpReader, OtpWriter};
+use crate::audit::{AuditEvent, Outcome};
+use crate::cmd::count_of;
+use crate::cmd::CommandType::{
+    Add, Agent, Audit, Credential, Delete, Edit, Generate, Get, Info, Init, KdfBenchmark, List,
+    Lock, Member, Passwd, Recovery, Rename, Rotate, Trash, Validate, Verify,
+};
+use crate::secret::Secret;
+use crate::secret::SecretBytes;
+use crate::storage::{find_directory, get_directory, BackendType};
+use crate::totp::{get_unix_time, Clock, GetTime};
+use crate::utils::{read_identity, read_keyfile, validate_pin};
+use crate::writer::{OtpReader, OtpWriter, OutErr};
 
 mod account;
+mod agent;
+mod audit;
 mod cmd;
 mod crypto;
 mod hotp;
+mod lookup;
+mod migration;
+mod pin;
+mod policy;
+mod secret;
+mod storage;
 #[cfg(test)]
 mod tests;
 mod totp;
 mod utils;
+mod verify;
 mod writer;
 
 /*
@@ -36,97 +53,489 @@ TOTP https://datatracker.ietf.org/doc/html/rfc6238
 */
 
 fn main() {
-    let mut account_store = AccountStore::new().expect("Unable to initialize store");
     let mut writer = OtpWriter::new();
+    let mut reader = OtpReader::new();
     let cmd = command!("otp")
         .about("Time-based and counter-based one-time password generator")
         .version("v0.1.0")
         .subcommand_required(true)
+        .arg(
+            arg!(--keyfile <PATH> "Keyfile to unlock the account store with, or to require on init")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(--identity <PATH> "Unlock a shared account store with a member identity instead of the pin")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(--"pin-file" <PATH> "Read the pin from the first line of a file")
+                .required(false)
+                .global(true),
+        )
         .subcommand(cmd::init::subcommand())
         .subcommand(cmd::generate::subcommand())
         .subcommand(cmd::add::subcommand())
         .subcommand(cmd::delete::subcommand())
+        .subcommand(cmd::trash::subcommand())
         .subcommand(cmd::list::subcommand())
         .subcommand(cmd::get::subcommand())
-        .subcommand(cmd::validate::subcommand());
+        .subcommand(cmd::info::subcommand())
+        .subcommand(cmd::rename::subcommand())
+        .subcommand(cmd::rotate::subcommand())
+        .subcommand(cmd::edit::subcommand())
+        .subcommand(cmd::recovery::subcommand())
+        .subcommand(cmd::credential::subcommand())
+        .subcommand(cmd::member::subcommand())
+        .subcommand(cmd::validate::subcommand())
+        .subcommand(cmd::passwd::subcommand())
+        .subcommand(cmd::audit::subcommand())
+        .subcommand(cmd::kdf_benchmark::subcommand())
+        .subcommand(cmd::agent::subcommand())
+        .subcommand(cmd::lock::subcommand())
+        .subcommand(cmd::verify::subcommand());
 
     let matches = cmd.get_matches();
+    let keyfile = match matches.value_of("keyfile").map(read_keyfile).transpose() {
+        Ok(keyfile) => keyfile,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    let identity = match matches.value_of("identity").map(read_identity).transpose() {
+        Ok(identity) => identity,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    // Verify opens the store itself, so it can report on one that no longer opens, and
+    // read-only, so that it changes nothing
+    if let Some((verify_cmd, verify_args)) = matches.subcommand() {
+        if verify_cmd == Verify.as_str() {
+            let passed = cmd::verify::run_verify(
+                verify_args,
+                storage::open_read_only(&find_directory()),
+                |account_store, writer| {
+                    account_store.set_keyfile(keyfile);
+                    unlock_read_only(
+                        account_store,
+                        matches.value_of("pin-file"),
+                        identity.as_ref(),
+                        &mut reader,
+                        writer,
+                    )
+                },
+                &mut writer,
+            );
+            if !passed {
+                std::process::exit(1);
+            }
+            return;
+        }
+    }
+    match storage::secure_directory(&get_directory()) {
+        Ok(warnings) => {
+            for warning in warnings {
+                writer.write_err(&format!("Warning: {}\n", warning));
+            }
+        }
+        Err(err) => {
+            writer.write_err(&format!("Refusing to use the account store: {}\n", err));
+            std::process::exit(1);
+        }
+    }
+    let mut account_store = match AccountStore::new() {
+        Ok(account_store) => account_store,
+        Err(err) => {
+            writer.write_err(&format!(
+                "Unable to open the account store: {}\nRun 'otp verify' to diagnose the problem\n",
+                err
+            ));
+            std::process::exit(1);
+        }
+    };
+    // A new store can be kept in another backend, which has to be open before the keyfile is set
+    if let Some((init_cmd, init_args)) = matches.subcommand() {
+        if init_cmd == Init.as_str() && !account_store.is_initialized() {
+            if let Some(backend_type) = init_args
+                .value_of("backend")
+                .and_then(BackendType::from_str)
+            {
+                let backend = storage::create(backend_type, &get_directory())
+                    .expect("Unable to create the storage backend");
+                account_store = AccountStore::open(backend).expect("Unable to initialize store");
+            }
+        }
+    }
+    if keyfile.is_some() {
+        account_store.set_keyfile(keyfile);
+    }
     match matches.subcommand() {
         Some((init_cmd, init_args))
             if init_cmd == Init.as_str() && !account_store.is_initialized() =>
         {
-            cmd::init::run_init(init_args, &mut account_store, &mut writer)
+            cmd::init::run_init(init_args, &mut account_store, &mut reader, &mut writer)
         }
         Some((gen_cmd, generate_args)) if gen_cmd == Generate.as_str() => {
             cmd::generate::run_generate(generate_args, &mut writer)
         }
-        Some((list_cmd, _)) if list_cmd == List.as_str() => {
-            cmd::list::run_list(&account_store, &mut writer)
+        Some((member_cmd, member_args))
+            if member_cmd == Member.as_str() && cmd::member::is_keygen(member_args) =>
+        {
+            cmd::member::run_keygen(member_args, &mut writer)
+        }
+        Some((benchmark_cmd, benchmark_args)) if benchmark_cmd == KdfBenchmark.as_str() => {
+            cmd::kdf_benchmark::run_kdf_benchmark(benchmark_args, &mut writer)
+        }
+        Some((list_cmd, list_args))
+            if list_cmd == List.as_str()
+                && cmd::list::lists_without_unlock(list_args, &account_store) =>
+        {
+            cmd::list::run_list(list_args, &account_store, &mut writer)
         }
-        Some((val_cmd, validate_args)) if val_cmd == Validate.as_str() => {
-            cmd::validate::run_validate(validate_args, &account_store, &mut writer, &Clock::new())
+        Some((lock_cmd, lock_args)) if lock_cmd == Lock.as_str() => {
+            cmd::lock::run_lock(lock_args, |name| std::env::var(name).ok(), &mut writer)
         }
-        // These subcommands require a pin
+        // These subcommands require a pin to unlock the store
         Some(subcommand) => {
-            match check_pin(&account_store, &mut OtpReader::new()) {
+            // A running agent spares the pin, except for changing it or starting another agent
+            let use_agent = subcommand.0 != Passwd.as_str() && subcommand.0 != Agent.as_str();
+            // Only the pin may change itself
+            let identity = identity
+                .as_ref()
+                .filter(|_| subcommand.0 != Passwd.as_str());
+            let unlocked = unlock_store(
+                &mut account_store,
+                matches.value_of("pin-file"),
+                identity,
+                use_agent,
+                &mut reader,
+                &mut writer,
+            );
+            match unlocked {
                 Ok(_) => match subcommand {
                     (init_cmd, init_args) if init_cmd == Init.as_str() => {
-                        cmd::init::run_init(init_args, &mut account_store, &mut writer)
+                        cmd::init::run_init(init_args, &mut account_store, &mut reader, &mut writer)
                     }
                     (add_cmd, add_args) if add_cmd == Add.as_str() => {
-                        cmd::add::run_add(add_args, &mut account_store, &mut writer)
+                        cmd::add::run_add(add_args, &mut account_store, &mut writer, &Clock::new())
                     }
                     (delete_cmd, delete_args) if delete_cmd == Delete.as_str() => {
-                        cmd::delete::run_delete(delete_args, &mut account_store, &mut writer)
+                        cmd::delete::run_delete(
+                            delete_args,
+                            &mut account_store,
+                            &mut reader,
+                            &mut writer,
+                            &Clock::new(),
+                        )
+                    }
+                    (list_cmd, list_args) if list_cmd == List.as_str() => {
+                        cmd::list::run_list(list_args, &account_store, &mut writer)
+                    }
+                    (val_cmd, validate_args) if val_cmd == Validate.as_str() => {
+                        cmd::validate::run_validate(
+                            validate_args,
+                            &mut account_store,
+                            &mut reader,
+                            &mut writer,
+                            &Clock::new(),
+                        )
+                    }
+                    (get_cmd, get_args) if get_cmd == Get.as_str() => cmd::get::run_get(
+                        get_args,
+                        &mut account_store,
+                        &mut reader,
+                        &mut writer,
+                        &Clock::new(),
+                    ),
+                    (rename_cmd, rename_args) if rename_cmd == Rename.as_str() => {
+                        cmd::rename::run_rename(rename_args, &mut account_store, &mut writer)
+                    }
+                    (rotate_cmd, rotate_args) if rotate_cmd == Rotate.as_str() => {
+                        cmd::rotate::run_rotate(
+                            rotate_args,
+                            &mut account_store,
+                            &mut reader,
+                            &mut writer,
+                            &Clock::new(),
+                        )
+                    }
+                    (edit_cmd, edit_args) if edit_cmd == Edit.as_str() => {
+                        cmd::edit::run_edit(edit_args, &mut account_store, &mut writer)
+                    }
+                    (info_cmd, info_args) if info_cmd == Info.as_str() => {
+                        cmd::info::run_info(info_args, &account_store, &mut writer)
+                    }
+                    (trash_cmd, trash_args) if trash_cmd == Trash.as_str() => {
+                        cmd::trash::run_trash(
+                            trash_args,
+                            &mut account_store,
+                            &mut reader,
+                            &mut writer,
+                            &Clock::new(),
+                        )
+                    }
+                    (recovery_cmd, recovery_args) if recovery_cmd == Recovery.as_str() => {
+                        cmd::recovery::run_recovery(
+                            recovery_args,
+                            &mut account_store,
+                            &mut reader,
+                            &mut writer,
+                            &Clock::new(),
+                        )
                     }
-                    (get_cmd, get_args) if get_cmd == Get.as_str() => {
-                        cmd::get::run_get(get_args, &mut account_store, &mut writer, &Clock::new())
+                    (credential_cmd, credential_args) if credential_cmd == Credential.as_str() => {
+                        cmd::credential::run_credential(
+                            credential_args,
+                            &mut account_store,
+                            &mut reader,
+                            &mut writer,
+                        )
+                    }
+                    (member_cmd, member_args) if member_cmd == Member.as_str() => {
+                        cmd::member::run_member(member_args, &mut account_store, &mut writer)
+                    }
+                    (audit_cmd, audit_args) if audit_cmd == Audit.as_str() => {
+                        cmd::audit::run_audit(audit_args, &mut account_store, &mut writer)
+                    }
+                    (agent_cmd, agent_args) if agent_cmd == Agent.as_str() => {
+                        cmd::agent::run_agent(
+                            agent_args,
+                            &account_store,
+                            |name| std::env::var(name).ok(),
+                            &mut writer,
+                        )
+                    }
+                    (passwd_cmd, passwd_args) if passwd_cmd == Passwd.as_str() => {
+                        cmd::passwd::run_passwd(
+                            passwd_args,
+                            &mut account_store,
+                            &mut reader,
+                            &mut writer,
+                        )
                     }
                     _ => println!("Unknown subcommand"),
                 },
-                Err(err) => println!("{}", err),
+                Err(err) => {
+                    writer.write_err(&format!("{}\n", err));
+                    std::process::exit(1);
+                }
             };
         }
         _ => unreachable!("No commands were supplied!"),
     };
 }
 
-fn check_pin(
+// Unlocks with a member identity when one is given, then with the agent when it may be used
+// and is running, otherwise with the pin
+fn unlock_store(
+    account_store: &mut impl AccountStoreOperations,
+    pin_file: Option<&str>,
+    identity: Option<&SecretBytes>,
+    use_agent: bool,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+) -> Result<(), String> {
+    if let Some(identity) = identity {
+        return account_store
+            .unlock_with_identity(identity.expose())
+            .map_err(|err| format!("Unable to unlock the account store: {}", err));
+    }
+    let var = |name: &str| std::env::var(name).ok();
+    if use_agent && pin_file.is_none() && agent::unlock_with_agent(account_store, var, writer) {
+        return Ok(());
+    }
+    pin::read_given_pin(pin_file, var, writer)
+        .and_then(|given_pin| check_pin(account_store, reader, writer, given_pin, &Clock::new()))
+}
+
+// Unlocks a store opened read-only. A wrong pin isn't counted or audited and can't destroy
+// the store, though the wait left by earlier failures still applies.
+fn unlock_read_only(
+    account_store: &mut impl AccountStoreOperations,
+    pin_file: Option<&str>,
+    identity: Option<&SecretBytes>,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+) -> Result<(), String> {
+    if let Some(identity) = identity {
+        return account_store
+            .unlock_with_identity(identity.expose())
+            .map_err(|err| format!("Unable to unlock the account store: {}", err));
+    }
+    let var = |name: &str| std::env::var(name).ok();
+    if pin_file.is_none() && agent::unlock_with_agent(account_store, var, writer) {
+        return Ok(());
+    }
+    pin::read_given_pin(pin_file, var, writer).and_then(|given_pin| {
+        check_pin_read_only(account_store, reader, writer, given_pin, &Clock::new())
+    })
+}
+
+fn check_pin_read_only(
+    account_store: &mut impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+    given_pin: Option<Secret<String>>,
+    clock: &impl GetTime,
+) -> Result<(), String> {
+    if !account_store.is_initialized() {
+        return Err(String::from(
+            "No existing pin found. Run the 'init' command.",
+        ));
+    }
+    let delay = account_store.get_unlock_delay(get_unix_time(clock));
+    if delay > 0 {
+        return Err(format!(
+            "Too many failed attempts, try again in {}",
+            count_of(delay as usize, "second")
+        ));
+    }
+    let buffer = match given_pin {
+        Some(pin) => pin,
+        None => read_pin(account_store, reader, writer)?,
+    };
+    let pin = buffer.expose().trim();
+    validate_pin(pin, account_store)?;
+    account_store
+        .unlock(pin)
+        .map_err(|err| format!("Unable to unlock the account store: {}", err))
+}
+
+// Prompts go to stderr so stdout only carries the command's output
+fn read_pin(
     account_store: &impl AccountStoreOperations,
     reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+) -> Result<Secret<String>, String> {
+    writer.write_err(&format!(
+        "Enter your {}:\n",
+        account_store.get_pin_policy().mode.as_str()
+    ));
+    let mut buffer = Secret::new(String::new());
+    reader.read_secret(buffer.expose_mut());
+    if buffer.expose().is_empty() {
+        return Err(String::from("No pin entered"));
+    }
+    Ok(buffer)
+}
+
+// Enough tries for a typo, without letting scripts guess forever
+const MAX_PIN_ATTEMPTS: usize = 3;
+
+// Audits and counts a failed unlock, failing once it has destroyed the store
+fn record_failed_unlock(
+    account_store: &mut impl AccountStoreOperations,
+    writer: &mut impl OutErr,
+    clock: &impl GetTime,
+) -> Result<(), String> {
+    let timestamp = get_unix_time(clock);
+    let event = AuditEvent {
+        timestamp,
+        operation: String::from("unlock"),
+        account: None,
+        outcome: Outcome::Failure,
+    };
+    if let Err(err) = account_store.audit(event) {
+        writer.write_err(&format!("Unable to write the audit log: {}\n", err));
+    }
+    match account_store.record_failed_unlock(timestamp) {
+        Ok(true) => Err(String::from(
+            "Too many failed attempts, the account store was destroyed",
+        )),
+        Ok(false) => Ok(()),
+        Err(err) => {
+            writer.write_err(&format!("Unable to count the failed attempt: {}\n", err));
+            Ok(())
+        }
+    }
+}
+
+fn check_pin(
+    account_store: &mut impl AccountStoreOperations,
+    reader: &mut impl ReadLine,
+    writer: &mut impl OutErr,
+    given_pin: Option<Secret<String>>,
+    clock: &impl GetTime,
 ) -> Result<(), String> {
     if !account_store.is_initialized() {
         return Err(String::from(
             "No existing pin found. Run the 'init' command.",
         ));
-    } else {
-        loop {
-            println!("Enter your pin:");
+    }
+
+    // Nobody is there to correct a pin passed in up front, so it gets a single try
+    let attempts = match given_pin {
+        Some(_) => 1,
+        None => MAX_PIN_ATTEMPTS,
+    };
+    let mut given_pin = given_pin;
+    for _ in 0..attempts {
+        // Failures are counted across runs, so starting again doesn't skip the wait
+        let delay = account_store.get_unlock_delay(get_unix_time(clock));
+        if delay > 0 {
+            return Err(format!(
+                "Too many failed attempts, try again in {}",
+                count_of(delay as usize, "second")
+            ));
+        }
 
-            let mut pin = String::new();
-            reader.read_line(&mut pin);
+        let buffer = match given_pin.take() {
+            Some(pin) => pin,
+            None => read_pin(account_store, reader, writer)?,
+        };
+        let pin = buffer.expose().trim();
 
-            match validate_pin(pin.trim(), account_store) {
-                Ok(_) => break,
-                Err(err) => println!("{}", err),
+        match validate_pin(pin, account_store) {
+            // The counter only starts again once the keyfile fits too
+            Ok(_) => match account_store.unlock(pin) {
+                Ok(_) => {
+                    if let Err(err) = account_store.reset_failed_unlocks() {
+                        writer
+                            .write_err(&format!("Unable to reset the failed attempts: {}\n", err));
+                    }
+                    return Ok(());
+                }
+                Err(err) => {
+                    record_failed_unlock(account_store, writer, clock)?;
+                    return Err(format!("Unable to unlock the account store: {}", err));
+                }
+            },
+            Err(err) => {
+                writer.write_err(&format!("{}\n", err));
+                record_failed_unlock(account_store, writer, clock)?;
             }
         }
-        Ok(())
     }
+    Err(format!(
+        "Giving up after {}",
+        count_of(attempts, "failed attempt")
+    ))
 }
 
 #[cfg(test)]
 mod main_tests {
     use super::*;
     use crate::account::tests::{create_empty_store, get_mock_store};
+    use crate::crypto::hash_keyfile;
     use crate::tests::constants::*;
     use crate::tests::mocks::*;
 
     #[test]
     fn checks_for_account_store_initialized() {
-        let account_store = create_empty_store();
+        let mut account_store = create_empty_store();
+        let mut writer = MockOtpWriter::new();
 
-        let result = check_pin(&account_store, &mut MockOtpReader::new(PIN));
+        let result = check_pin(
+            &mut account_store,
+            &mut MockOtpReader::new(PIN),
+            &mut writer,
+            None,
+            &MockClock::new(),
+        );
         assert!(result.is_err());
 
         let err = result.unwrap_err();
@@ -137,10 +546,227 @@ mod main_tests {
 
     #[test]
     fn verifies_pin() {
-        let account_store = get_mock_store();
+        let mut account_store = get_mock_store();
         let mut reader = MockOtpReader::new(PIN);
+        let mut writer = MockOtpWriter::new();
 
-        let result = check_pin(&account_store, &mut reader);
+        let result = check_pin(
+            &mut account_store,
+            &mut reader,
+            &mut writer,
+            None,
+            &MockClock::new(),
+        );
         assert!(result.is_ok());
+        assert_eq!(reader.secret_reads, 1);
+        assert_eq!(writer.out, Vec::new());
+        assert_eq!(String::from_utf8(writer.err).unwrap(), "Enter your pin:\n");
+    }
+
+    #[test]
+    fn gives_up_after_repeated_failures() {
+        let mut account_store = get_mock_store();
+        let mut reader = MockOtpReader::new("000000");
+        let mut writer = MockOtpWriter::new();
+
+        let result = check_pin(
+            &mut account_store,
+            &mut reader,
+            &mut writer,
+            None,
+            &MockClock::new(),
+        );
+
+        assert_eq!(
+            result,
+            Err(String::from("Giving up after 3 failed attempts"))
+        );
+        assert_eq!(
+            String::from_utf8(writer.err).unwrap(),
+            "Enter your pin:\nInvalid pin\n".repeat(3)
+        );
+        assert_eq!(writer.out, Vec::new());
+    }
+
+    #[test]
+    fn stops_at_the_end_of_input() {
+        let mut account_store = get_mock_store();
+        let mut reader = MockOtpReader::new("");
+        let mut writer = MockOtpWriter::new();
+
+        let result = check_pin(
+            &mut account_store,
+            &mut reader,
+            &mut writer,
+            None,
+            &MockClock::new(),
+        );
+
+        assert_eq!(result, Err(String::from("No pin entered")));
+    }
+
+    #[test]
+    fn tries_a_given_pin_once_without_prompting() {
+        let mut account_store = get_mock_store();
+        let mut reader = MockOtpReader::new(PIN);
+        let mut writer = MockOtpWriter::new();
+
+        let result = check_pin(
+            &mut account_store,
+            &mut reader,
+            &mut writer,
+            Some(Secret::new(String::from("000000"))),
+            &MockClock::new(),
+        );
+
+        assert_eq!(
+            result,
+            Err(String::from("Giving up after 1 failed attempt"))
+        );
+        assert_eq!(String::from_utf8(writer.err).unwrap(), "Invalid pin\n");
+        assert_eq!(reader.input, Vec::new());
+
+        let mut writer = MockOtpWriter::new();
+        let given_pin = Some(Secret::new(String::from(PIN)));
+        assert!(check_pin(
+            &mut account_store,
+            &mut reader,
+            &mut writer,
+            given_pin,
+            &MockClock::new()
+        )
+        .is_ok());
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn waits_after_repeated_failures() {
+        let mut account_store = get_mock_store();
+        let mut reader = MockOtpReader::new("000000");
+        let mut writer = MockOtpWriter::new();
+        let clock = MockClock::new();
+
+        assert!(check_pin(&mut account_store, &mut reader, &mut writer, None, &clock).is_err());
+
+        let mut reader = MockOtpReader::new(PIN);
+        let result = check_pin(&mut account_store, &mut reader, &mut writer, None, &clock);
+
+        assert_eq!(
+            result,
+            Err(String::from(
+                "Too many failed attempts, try again in 5 seconds"
+            ))
+        );
+        assert_eq!(reader.secret_reads, 0);
+    }
+
+    #[test]
+    fn destroys_the_store_after_the_last_attempt() {
+        let mut account_store = get_mock_store();
+        account_store.set_wipe_after(Some(3));
+        let mut reader = MockOtpReader::new("000000");
+        let mut writer = MockOtpWriter::new();
+
+        let result = check_pin(
+            &mut account_store,
+            &mut reader,
+            &mut writer,
+            None,
+            &MockClock::new(),
+        );
+
+        assert_eq!(
+            result,
+            Err(String::from(
+                "Too many failed attempts, the account store was destroyed"
+            ))
+        );
+        assert!(!account_store.is_initialized());
+    }
+
+    #[test]
+    fn counts_a_correct_pin_without_the_keyfile_as_a_failure() {
+        let mut account_store = create_empty_store();
+        account_store.set_keyfile(Some(hash_keyfile(b"keyfile")));
+        account_store.set_wipe_after(Some(2));
+        account_store.set_pin(PIN, test_kdf_params()).unwrap();
+        account_store.save().unwrap();
+        account_store.set_keyfile(None);
+        let mut reader = MockOtpReader::new("");
+        let mut writer = MockOtpWriter::new();
+        let clock = MockClock::new();
+
+        let given_pin = Some(Secret::new(String::from("000000")));
+        assert!(check_pin(
+            &mut account_store,
+            &mut reader,
+            &mut writer,
+            given_pin,
+            &clock
+        )
+        .is_err());
+
+        let given_pin = Some(Secret::new(String::from(PIN)));
+        let result = check_pin(
+            &mut account_store,
+            &mut reader,
+            &mut writer,
+            given_pin,
+            &clock,
+        );
+
+        assert_eq!(
+            result,
+            Err(String::from(
+                "Too many failed attempts, the account store was destroyed"
+            ))
+        );
+        assert!(!account_store.is_initialized());
+    }
+
+    // Every file in the store's directory with its contents
+    fn read_directory(directory: &std::path::Path) -> Vec<(std::path::PathBuf, Vec<u8>)> {
+        let mut files = std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let contents = std::fs::read(&path).unwrap();
+                (path, contents)
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn verifies_a_wrong_pin_without_changing_the_store() {
+        let directory = crate::storage::tests::get_test_directory("verify-wipe");
+        let backend = storage::create(BackendType::File, &directory).unwrap();
+        let mut account_store = AccountStore::open(backend).unwrap();
+        account_store.set_wipe_after(Some(1));
+        account_store.set_pin(PIN, test_kdf_params()).unwrap();
+        account_store.save().unwrap();
+        let files = read_directory(&directory);
+
+        let backend = storage::open_read_only(&directory).unwrap();
+        let mut account_store = AccountStore::open(backend).unwrap();
+        let mut writer = MockOtpWriter::new();
+        let result = check_pin_read_only(
+            &mut account_store,
+            &mut MockOtpReader::new(PIN),
+            &mut writer,
+            Some(Secret::new(String::from("000000"))),
+            &MockClock::new(),
+        );
+
+        assert_eq!(result, Err(String::from("Invalid pin")));
+        assert!(account_store.is_initialized());
+        assert_eq!(read_directory(&directory), files);
+
+        let backend = storage::open(&directory).unwrap();
+        let mut account_store = AccountStore::open(backend).unwrap();
+        assert_eq!(account_store.get_unlock_delay(0), 0);
+        assert!(account_store.unlock(PIN).is_ok());
+        std::fs::remove_dir_all(directory).unwrap();
     }
 }
@@ -0,0 +1,156 @@
+use serde::Deserialize;
+use std::io::{Error, ErrorKind, Result};
+use toml::value::{Table, Value};
+use zeroize::Zeroize;
+
+use crate::secret::Secret;
+
+// Version of the accounts TOML written by this build. When a change to the vault or an account
+// needs more than a serde default to read older stores, bump it and add a migration below.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// The migration at index i takes a vault from version i to i + 1
+const MIGRATIONS: [fn(&mut Table); SCHEMA_VERSION as usize] = [wrap_account_map];
+
+#[derive(Deserialize)]
+struct Header {
+    version: Option<u32>,
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn parse(contents: &str) -> Result<Table> {
+    toml::from_str::<Table>(contents)
+        .map_err(|err| invalid_data(format!("Deserialization failure: {}", err)))
+}
+
+// The parsed copy holds the account keys, so it is wiped like any other secret
+fn wipe(value: &mut Value) {
+    match value {
+        Value::String(string) => string.zeroize(),
+        Value::Array(values) => values.iter_mut().for_each(wipe),
+        Value::Table(table) => table.iter_mut().for_each(|(_, value)| wipe(value)),
+        _ => (),
+    }
+}
+
+// Vaults from before the version field are told apart by their shape. Version 0 is the map of
+// accounts on its own, version 1 the same accounts under a table next to the trash.
+fn detect_version(vault: &Table) -> u32 {
+    let is_vault = vault
+        .iter()
+        .all(|(key, value)| match (key.as_str(), value) {
+            ("audit_key", Value::String(_)) | ("trash", Value::Array(_)) => true,
+            ("accounts", Value::Table(accounts)) => accounts.values().all(Value::is_table),
+            _ => false,
+        });
+    match is_vault {
+        true => 1,
+        false => 0,
+    }
+}
+
+// The version the accounts TOML was written with, refusing any newer than this build reads
+pub fn get_version(contents: &str) -> Result<u32> {
+    let version = match toml::from_str::<Header>(contents) {
+        Ok(Header {
+            version: Some(version),
+        }) => version,
+        // Nothing was saved yet, so there is nothing to migrate
+        _ if contents.trim().is_empty() => SCHEMA_VERSION,
+        _ => {
+            let vault = parse(contents)?;
+            let version = detect_version(&vault);
+            wipe(&mut Value::Table(vault));
+            version
+        }
+    };
+    match version > SCHEMA_VERSION {
+        true => Err(invalid_data(format!(
+            "The account store uses schema version {}, but this version of otp only reads up to {}. Upgrade otp to open it",
+            version, SCHEMA_VERSION
+        ))),
+        false => Ok(version),
+    }
+}
+
+// Applies every migration from the given version on, returning the TOML of the current schema
+pub fn migrate(contents: &str, version: u32) -> Result<Secret<String>> {
+    let mut vault = parse(contents)?;
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut vault);
+    }
+    vault.insert(
+        String::from("version"),
+        Value::Integer(i64::from(SCHEMA_VERSION)),
+    );
+    // As a value the table writes its plain values first, which TOML requires
+    let mut vault = Value::Table(vault);
+    let migrated = toml::to_string(&vault)
+        .map_err(|err| invalid_data(format!("Unable to write the migrated accounts: {}", err)));
+    wipe(&mut vault);
+    migrated.map(Secret::new)
+}
+
+// The first stores held just the map of accounts
+fn wrap_account_map(vault: &mut Table) {
+    let accounts = std::mem::take(vault);
+    vault.insert(String::from("accounts"), Value::Table(accounts));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_versions_from_before_the_version_field() {
+        assert_eq!(
+            get_version("[github]\nkey = \"A\"\n\n[github.otp_type]\ntype = \"TOTP\"\n").unwrap(),
+            0
+        );
+        // An account may well be called accounts
+        assert_eq!(
+            get_version("[accounts]\nkey = \"A\"\n\n[accounts.otp_type]\ntype = \"TOTP\"\n")
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            get_version("audit_key = \"A\"\n\n[accounts.github]\nkey = \"A\"\n").unwrap(),
+            1
+        );
+        assert_eq!(get_version("").unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn reads_the_version_field() {
+        assert_eq!(get_version("version = 1\n").unwrap(), 1);
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let err = get_version("version = 99\n").unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "The account store uses schema version 99, but this version of otp only reads up to 1. Upgrade otp to open it"
+        );
+    }
+
+    #[test]
+    fn wraps_the_account_map() {
+        let migrated = migrate(
+            "[github]\nkey = \"A\"\n\n[github.otp_type]\ntype = \"TOTP\"\n",
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(
+            migrated.expose(),
+            "version = 1\n[accounts.github]\nkey = \"A\"\n\n\
+             [accounts.github.otp_type]\ntype = \"TOTP\"\n"
+        );
+    }
+}
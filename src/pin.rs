@@ -0,0 +1,148 @@
+use std::fs;
+
+use crate::secret::Secret;
+use crate::writer::OutErr;
+
+pub const PIN_FD_VAR: &str = "OTP_PIN_FD";
+pub const PIN_VAR: &str = "OTP_PIN";
+
+// Only the first line counts, so files written with echo or a trailing newline work
+fn first_line(contents: Secret<String>) -> Secret<String> {
+    let line = contents.expose().lines().next().unwrap_or_default();
+    Secret::new(String::from(line))
+}
+
+fn read_file(path: &str) -> Result<Secret<String>, String> {
+    fs::read_to_string(path)
+        .map(Secret::new)
+        .map_err(|err| format!("Unable to read pin file {}: {}", path, err))
+}
+
+#[cfg(unix)]
+fn read_fd(fd: &str) -> Result<Secret<String>, String> {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    let fd = match fd.parse::<i32>() {
+        Ok(fd) if fd >= 0 && fd != 1 && fd != 2 => fd,
+        _ => {
+            return Err(format!(
+                "{} is not a readable file descriptor: {}",
+                PIN_FD_VAR, fd
+            ))
+        }
+    };
+    // The descriptor was handed over just for the pin, so it is read to the end and closed
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    let mut contents = Secret::new(String::new());
+    file.read_to_string(contents.expose_mut())
+        .map_err(|err| format!("Unable to read the pin from {}: {}", PIN_FD_VAR, err))?;
+    Ok(contents)
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: &str) -> Result<Secret<String>, String> {
+    Err(format!("{} is only supported on Unix", PIN_FD_VAR))
+}
+
+// A pin given up front for scripts, from --pin-file, OTP_PIN_FD or OTP_PIN in that order.
+// Returns None when the pin should be asked for instead.
+pub fn read_given_pin(
+    pin_file: Option<&str>,
+    var: impl Fn(&str) -> Option<String>,
+    writer: &mut impl OutErr,
+) -> Result<Option<Secret<String>>, String> {
+    let contents = match (pin_file, var(PIN_FD_VAR), var(PIN_VAR)) {
+        (Some(path), _, _) => read_file(path)?,
+        (None, Some(fd), _) => read_fd(&fd)?,
+        (None, None, Some(pin)) => {
+            writer.write_err(&format!(
+                "Warning: {} can be read by other processes, use --pin-file or {} instead\n",
+                PIN_VAR, PIN_FD_VAR
+            ));
+            Secret::new(pin)
+        }
+        (None, None, None) => return Ok(None),
+    };
+    Ok(Some(first_line(contents)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::constants::PIN;
+    use crate::tests::mocks::MockOtpWriter;
+
+    fn no_vars(_: &str) -> Option<String> {
+        None
+    }
+
+    fn read(
+        pin_file: Option<&str>,
+        vars: &[(&str, &str)],
+    ) -> (Result<Option<String>, String>, String) {
+        let mut writer = MockOtpWriter::new();
+        let lookup = |name: &str| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| String::from(*value))
+        };
+        let pin = read_given_pin(pin_file, lookup, &mut writer)
+            .map(|pin| pin.map(|pin| pin.expose().clone()));
+        (pin, String::from_utf8(writer.err).unwrap())
+    }
+
+    #[test]
+    fn asks_when_no_pin_is_given() {
+        let mut writer = MockOtpWriter::new();
+        assert!(read_given_pin(None, no_vars, &mut writer)
+            .unwrap()
+            .is_none());
+        assert_eq!(writer.err, Vec::new());
+    }
+
+    #[test]
+    fn reads_the_first_line_of_a_pin_file() {
+        let path = std::env::temp_dir().join(format!("otp-pin-file-{}", std::process::id()));
+        fs::write(&path, format!("{}\nignored\n", PIN)).unwrap();
+
+        let (pin, err) = read(path.to_str(), &[(PIN_VAR, "000000")]);
+
+        assert_eq!(pin, Ok(Some(String::from(PIN))));
+        assert_eq!(err, "");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reports_missing_pin_files() {
+        let (pin, _) = read(Some("/nonexistent/pin"), &[]);
+
+        assert!(pin
+            .unwrap_err()
+            .starts_with("Unable to read pin file /nonexistent/pin: "));
+    }
+
+    #[test]
+    fn warns_about_the_pin_variable() {
+        let (pin, err) = read(None, &[(PIN_VAR, PIN)]);
+
+        assert_eq!(pin, Ok(Some(String::from(PIN))));
+        assert_eq!(
+            err,
+            "Warning: OTP_PIN can be read by other processes, use --pin-file or OTP_PIN_FD instead\n"
+        );
+    }
+
+    #[test]
+    fn rejects_output_file_descriptors() {
+        let (pin, _) = read(None, &[(PIN_FD_VAR, "2"), (PIN_VAR, PIN)]);
+
+        assert_eq!(
+            pin,
+            Err(String::from(
+                "OTP_PIN_FD is not a readable file descriptor: 2"
+            ))
+        );
+    }
+}
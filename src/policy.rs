@@ -0,0 +1,384 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinMode {
+    Pin,
+    Passphrase,
+}
+
+impl PinMode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            PinMode::Pin => "pin",
+            PinMode::Passphrase => "passphrase",
+        }
+    }
+
+    fn display_name(&self) -> &str {
+        match self {
+            PinMode::Pin => "PIN",
+            PinMode::Passphrase => "Passphrase",
+        }
+    }
+}
+
+// Failed unlocks allowed before every further attempt has to wait, enough for a few typos
+pub const FREE_UNLOCK_ATTEMPTS: u32 = 3;
+const MAX_UNLOCK_DELAY_SECS: u64 = 60 * 60;
+
+// Seconds to wait after this many failed unlocks, doubling from 5 seconds up to an hour
+pub fn unlock_delay(failures: u32) -> u64 {
+    if failures < FREE_UNLOCK_ATTEMPTS {
+        return 0;
+    }
+    let doublings = (failures - FREE_UNLOCK_ATTEMPTS).min(16);
+    (5 << doublings).min(MAX_UNLOCK_DELAY_SECS)
+}
+
+// Whether listing account names needs the pin, or reads an index of names kept unlocked
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Listing {
+    #[default]
+    Unlock,
+    Index,
+}
+
+impl Listing {
+    pub fn from_str(listing: &str) -> Option<Self> {
+        match listing {
+            "unlock" => Some(Listing::Unlock),
+            "index" => Some(Listing::Index),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CharClass {
+    Digits,
+    Lowercase,
+    Uppercase,
+    Symbols,
+    Space,
+}
+
+impl CharClass {
+    pub fn as_str(&self) -> &str {
+        match self {
+            CharClass::Digits => "digits",
+            CharClass::Lowercase => "lowercase",
+            CharClass::Uppercase => "uppercase",
+            CharClass::Symbols => "symbols",
+            CharClass::Space => "space",
+        }
+    }
+
+    pub fn from_str(class: &str) -> Option<Self> {
+        match class {
+            "digits" => Some(CharClass::Digits),
+            "lowercase" => Some(CharClass::Lowercase),
+            "uppercase" => Some(CharClass::Uppercase),
+            "symbols" => Some(CharClass::Symbols),
+            "space" => Some(CharClass::Space),
+            _ => None,
+        }
+    }
+
+    pub fn all() -> Vec<CharClass> {
+        vec![
+            CharClass::Digits,
+            CharClass::Lowercase,
+            CharClass::Uppercase,
+            CharClass::Symbols,
+            CharClass::Space,
+        ]
+    }
+
+    // Any character outside ASCII letters, digits and space counts as a symbol
+    fn of(c: char) -> Self {
+        match c {
+            '0'..='9' => CharClass::Digits,
+            'a'..='z' => CharClass::Lowercase,
+            'A'..='Z' => CharClass::Uppercase,
+            ' ' => CharClass::Space,
+            _ => CharClass::Symbols,
+        }
+    }
+
+    // Number of characters an attacker has to try for each position
+    fn pool_size(&self) -> f64 {
+        match self {
+            CharClass::Digits => 10.0,
+            CharClass::Lowercase | CharClass::Uppercase => 26.0,
+            CharClass::Symbols => 33.0,
+            CharClass::Space => 1.0,
+        }
+    }
+}
+
+// Rules a new pin or passphrase must follow, recorded in the store
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct PinPolicy {
+    pub mode: PinMode,
+    pub min_length: usize,
+    pub max_length: usize,
+    pub allowed: Vec<CharClass>,
+}
+
+// Stores created before policies were recorded accept any 4-6 character pin
+impl Default for PinPolicy {
+    fn default() -> Self {
+        PinPolicy::new(PinMode::Pin)
+    }
+}
+
+impl PinPolicy {
+    pub fn new(mode: PinMode) -> Self {
+        let (min_length, max_length) = match mode {
+            PinMode::Pin => (4, 6),
+            PinMode::Passphrase => (12, 128),
+        };
+        PinPolicy {
+            mode,
+            min_length,
+            max_length,
+            allowed: CharClass::all(),
+        }
+    }
+
+    pub fn validate(&self, pin: &str) -> Result<(), String> {
+        let length = pin.chars().count();
+        if length < self.min_length || length > self.max_length {
+            return Err(format!(
+                "{} must be between {} and {} characters",
+                self.mode.display_name(),
+                self.min_length,
+                self.max_length
+            ));
+        }
+
+        if pin
+            .chars()
+            .any(|c| !self.allowed.contains(&CharClass::of(c)))
+        {
+            let allowed = self
+                .allowed
+                .iter()
+                .map(|class| class.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ");
+            return Err(format!(
+                "{} may only contain {}",
+                self.mode.display_name(),
+                allowed
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Strength {
+    pub bits: f64,
+}
+
+impl Strength {
+    pub fn label(&self) -> &str {
+        match self.bits {
+            bits if bits < 30.0 => "very weak",
+            bits if bits < 50.0 => "weak",
+            bits if bits < 70.0 => "fair",
+            bits if bits < 100.0 => "strong",
+            _ => "very strong",
+        }
+    }
+}
+
+// RFC 4226 requires shared secrets of at least 128 bits, and recommends 160
+pub const MIN_SECRET_BITS: usize = 128;
+
+// Why a decoded shared secret is too weak to rely on, if it is
+pub fn secret_weakness(secret: &[u8]) -> Option<String> {
+    let bits = secret.len() * 8;
+    if bits < MIN_SECRET_BITS {
+        return Some(format!(
+            "it is only {} bits long, RFC 4226 requires at least {}",
+            bits, MIN_SECRET_BITS
+        ));
+    }
+    if secret.iter().all(|byte| *byte == 0) {
+        return Some(String::from("it is all zeros"));
+    }
+    // The shortest run of bytes that the whole secret repeats, if any
+    if let Some(period) = (1..=secret.len() / 2).find(|period| {
+        secret
+            .iter()
+            .enumerate()
+            .all(|(index, byte)| *byte == secret[index % period])
+    }) {
+        return Some(format!("it repeats the same {}", count_bytes(period)));
+    }
+    // Random secrets this long almost never repeat half of their bytes
+    let mut distinct = secret.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if distinct.len() * 2 < secret.len() {
+        return Some(format!(
+            "it only uses {} distinct byte values",
+            distinct.len()
+        ));
+    }
+    None
+}
+
+fn count_bytes(count: usize) -> String {
+    match count {
+        1 => String::from("byte"),
+        count => format!("{} bytes", count),
+    }
+}
+
+// Rough brute force estimate from length and the character classes used
+pub fn estimate_strength(pin: &str) -> Strength {
+    let mut classes: Vec<CharClass> = Vec::new();
+    for class in pin.chars().map(CharClass::of) {
+        if !classes.contains(&class) {
+            classes.push(class);
+        }
+    }
+    let pool: f64 = classes.iter().map(|class| class.pool_size()).sum();
+
+    // A single repeated character is no stronger than one character
+    let mut unique: Vec<char> = pin.chars().collect();
+    unique.sort_unstable();
+    unique.dedup();
+    let length = match unique.len() {
+        1 => 1,
+        _ => pin.chars().count(),
+    };
+
+    let bits = match pool {
+        pool if pool > 1.0 => length as f64 * pool.log2(),
+        _ => 0.0,
+    };
+    Strength { bits }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_policy_accepts_4_to_6_characters() {
+        let policy = PinPolicy::default();
+        assert_eq!(policy.validate("1234"), Ok(()));
+        assert_eq!(policy.validate("abc!56"), Ok(()));
+        assert_eq!(
+            policy.validate("123"),
+            Err(String::from("PIN must be between 4 and 6 characters"))
+        );
+        assert_eq!(
+            policy.validate("1234567"),
+            Err(String::from("PIN must be between 4 and 6 characters"))
+        );
+    }
+
+    #[test]
+    fn passphrase_policy_accepts_long_passphrases() {
+        let policy = PinPolicy::new(PinMode::Passphrase);
+        assert_eq!(policy.validate("correct horse battery staple"), Ok(()));
+        assert_eq!(
+            policy.validate("too short"),
+            Err(String::from(
+                "Passphrase must be between 12 and 128 characters"
+            ))
+        );
+    }
+
+    #[test]
+    fn counts_characters_rather_than_bytes() {
+        let policy = PinPolicy::default();
+        assert_eq!(policy.validate("ключ"), Ok(()));
+    }
+
+    #[test]
+    fn rejects_characters_outside_allowed_classes() {
+        let policy = PinPolicy {
+            allowed: vec![CharClass::Digits],
+            ..PinPolicy::default()
+        };
+        assert_eq!(policy.validate("1234"), Ok(()));
+        assert_eq!(
+            policy.validate("12a4"),
+            Err(String::from("PIN may only contain digits"))
+        );
+    }
+
+    #[test]
+    fn estimates_digit_pins_as_very_weak() {
+        let strength = estimate_strength("123456");
+        assert!((strength.bits - 19.93).abs() < 0.01, "{}", strength.bits);
+        assert_eq!(strength.label(), "very weak");
+    }
+
+    #[test]
+    fn estimates_mixed_passphrases_as_strong() {
+        let strength = estimate_strength("Correct horse battery staple 9");
+        assert_eq!(strength.label(), "very strong");
+    }
+
+    #[test]
+    fn estimates_repeated_characters_as_one_character() {
+        let strength = estimate_strength("aaaaaaaaaaaaaaaa");
+        assert!((strength.bits - 26f64.log2()).abs() < 0.01);
+        assert_eq!(strength.label(), "very weak");
+    }
+
+    #[test]
+    fn delays_unlocking_after_repeated_failures() {
+        assert_eq!(unlock_delay(0), 0);
+        assert_eq!(unlock_delay(2), 0);
+        assert_eq!(unlock_delay(3), 5);
+        assert_eq!(unlock_delay(4), 10);
+        assert_eq!(unlock_delay(12), 2560);
+        assert_eq!(unlock_delay(13), 3600);
+        assert_eq!(unlock_delay(u32::MAX), 3600);
+    }
+
+    #[test]
+    fn accepts_random_secrets() {
+        let secret = data_encoding::BASE32_NOPAD
+            .decode(b"FGCZ6RHPYYYFOEKRQNNF3Z2JKKANZXNX")
+            .unwrap();
+        assert_eq!(secret_weakness(&secret), None);
+    }
+
+    #[test]
+    fn finds_weak_secrets() {
+        assert_eq!(
+            secret_weakness(&[7; 10]),
+            Some(String::from(
+                "it is only 80 bits long, RFC 4226 requires at least 128"
+            ))
+        );
+        assert_eq!(
+            secret_weakness(&[0; 20]),
+            Some(String::from("it is all zeros"))
+        );
+        assert_eq!(
+            secret_weakness(&[9; 20]),
+            Some(String::from("it repeats the same byte"))
+        );
+        assert_eq!(
+            secret_weakness(&b"abcdabcdabcdabcdabcd"[..]),
+            Some(String::from("it repeats the same 4 bytes"))
+        );
+        assert_eq!(
+            secret_weakness(&b"aabbaabbabcbabaccbab"[..]),
+            Some(String::from("it only uses 3 distinct byte values"))
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use zeroize::Zeroize;
+
+// Wrapper for secret values that wipes them when dropped and never prints them
+pub struct Secret<T: Zeroize> {
+    value: T,
+    // Address and length of memory locked with mlock, released after wiping
+    locked: Option<(usize, usize)>,
+}
+
+// Base32 secret key of an account
+pub type SecretKey = Secret<String>;
+// Derived keys and other raw secret bytes
+pub type SecretBytes = Secret<Vec<u8>>;
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret {
+            value,
+            locked: None,
+        }
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.value
+    }
+
+    pub fn expose_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> Secret<T> {
+    // Keep the value out of swap and core dumps, where supported. The value must not grow
+    // afterwards, since reallocating would move it out of the locked memory.
+    pub fn lock(&mut self) {
+        let bytes = self.value.as_ref();
+        if self.locked.is_none() && lock_memory(bytes) {
+            self.locked = Some((bytes.as_ptr() as usize, bytes.len()));
+        }
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.value.zeroize();
+        if let Some((address, length)) = self.locked.take() {
+            unlock_memory(address, length);
+        }
+    }
+}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<T: Zeroize + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Secret::new(self.value.clone())
+    }
+}
+
+impl<T: Zeroize + PartialEq> PartialEq for Secret<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl From<&str> for SecretKey {
+    fn from(value: &str) -> Self {
+        Secret::new(String::from(value))
+    }
+}
+
+impl<T: Zeroize + Serialize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+impl<'de, T: Zeroize + Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Secret::new)
+    }
+}
+
+#[cfg(all(unix, feature = "mlock"))]
+fn lock_memory(bytes: &[u8]) -> bool {
+    if bytes.is_empty() {
+        return false;
+    }
+    unsafe { libc::mlock(bytes.as_ptr() as *const libc::c_void, bytes.len()) == 0 }
+}
+
+#[cfg(all(unix, feature = "mlock"))]
+fn unlock_memory(address: usize, length: usize) {
+    unsafe {
+        libc::munlock(address as *const libc::c_void, length);
+    }
+}
+
+#[cfg(not(all(unix, feature = "mlock")))]
+fn lock_memory(_bytes: &[u8]) -> bool {
+    false
+}
+
+#[cfg(not(all(unix, feature = "mlock")))]
+fn unlock_memory(_address: usize, _length: usize) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::ManuallyDrop;
+    use std::ptr;
+
+    #[test]
+    fn redacts_debug_output() {
+        let secret = SecretKey::from("JBSWY3DPEHPK3PXP");
+        assert_eq!(format!("{:?}", secret), "[REDACTED]");
+    }
+
+    // The storage of a ManuallyDrop outlives the drop, so the wiped bytes can still be read
+    #[test]
+    fn wipes_the_value_when_dropped() {
+        let mut secret = ManuallyDrop::new(Secret::new([7u8; 32]));
+        let value = ptr::addr_of!(secret.value);
+
+        unsafe { ptr::drop_in_place(&mut *secret) };
+
+        assert_eq!(unsafe { *value }, [0u8; 32]);
+    }
+
+    #[test]
+    fn serializes_as_the_inner_value() {
+        #[derive(Deserialize, Serialize)]
+        struct Wrapper {
+            key: SecretKey,
+        }
+
+        let wrapper = Wrapper {
+            key: SecretKey::from("JBSWY3DPEHPK3PXP"),
+        };
+        let serialized = toml::to_string(&wrapper).unwrap();
+        assert_eq!(serialized, "key = \"JBSWY3DPEHPK3PXP\"\n");
+
+        let deserialized: Wrapper = toml::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.key.expose(), "JBSWY3DPEHPK3PXP");
+    }
+
+    #[test]
+    fn locks_without_changing_the_value() {
+        let mut secret = SecretBytes::new(vec![7; 32]);
+        secret.lock();
+        assert_eq!(secret.expose(), &vec![7; 32]);
+    }
+}
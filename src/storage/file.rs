@@ -0,0 +1,424 @@
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{BufReader, Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use super::{
+    create_backup_directory, overwrite_and_remove, private_options, read_only_error,
+    wipe_directory, StorageBackend,
+};
+
+const FILE_NAME: &str = "accounts.txt";
+const SECRETS_FILE_NAME: &str = "secrets.txt";
+const COMMIT_FILE_NAME: &str = "commit.pending";
+const AUDIT_FILE_NAME: &str = "audit.log";
+const AUDIT_PENDING_FILE_NAME: &str = "audit.pending";
+const TEMP_EXTENSION: &str = "tmp";
+enum FileType {
+    Accounts,
+    Secrets,
+    Commit,
+    Audit,
+    AuditPending,
+}
+
+// Encrypted accounts and plain TOML secrets in separate files, replaced together
+pub struct FileBackend {
+    directory: PathBuf,
+    read_only: bool,
+}
+
+impl FileBackend {
+    pub fn open(directory: &Path) -> Result<FileBackend> {
+        let backend = FileBackend {
+            directory: directory.to_path_buf(),
+            read_only: false,
+        };
+        recover_commit(&backend.get_path(FileType::Commit))?;
+        Ok(backend)
+    }
+
+    // Leaves an interrupted commit to be finished by the next open, and reads what it staged
+    pub fn open_read_only(directory: &Path) -> FileBackend {
+        FileBackend {
+            directory: directory.to_path_buf(),
+            read_only: true,
+        }
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        match self.read_only {
+            true => Err(read_only_error()),
+            false => Ok(()),
+        }
+    }
+
+    // Missing files read as empty rather than being created
+    fn load_committed(&self, file_type: FileType) -> Result<Vec<u8>> {
+        let path = committed_path(&self.get_path(FileType::Commit), &self.get_path(file_type))?;
+        match fs::read(path) {
+            Ok(contents) => Ok(contents),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn load_string(&self, file_type: FileType) -> Result<String> {
+        match self.read_only {
+            true => String::from_utf8(self.load_committed(file_type)?)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err)),
+            false => load_file_to_string(&self.get_path(file_type)),
+        }
+    }
+
+    fn get_path(&self, file_type: FileType) -> PathBuf {
+        let filename = match file_type {
+            FileType::Accounts => FILE_NAME,
+            FileType::Secrets => SECRETS_FILE_NAME,
+            FileType::Commit => COMMIT_FILE_NAME,
+            FileType::Audit => AUDIT_FILE_NAME,
+            FileType::AuditPending => AUDIT_PENDING_FILE_NAME,
+        };
+        self.directory.join(filename)
+    }
+
+    fn append(&self, file_type: FileType, contents: &str) -> Result<()> {
+        self.check_writable()?;
+        let mut file = private_options()
+            .create(true)
+            .append(true)
+            .open(self.get_path(file_type))?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    }
+}
+
+impl StorageBackend for FileBackend {
+    fn load_secrets(&self) -> Result<String> {
+        self.load_string(FileType::Secrets)
+    }
+
+    fn load_accounts(&self) -> Result<Vec<u8>> {
+        match self.read_only {
+            true => self.load_committed(FileType::Accounts),
+            false => load_file_to_vec(&self.get_path(FileType::Accounts)),
+        }
+    }
+
+    fn commit(&mut self, secrets: &str, accounts: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        commit_files(
+            &self.get_path(FileType::Commit),
+            &[
+                (self.get_path(FileType::Accounts), accounts.to_vec()),
+                (
+                    self.get_path(FileType::Secrets),
+                    secrets.as_bytes().to_vec(),
+                ),
+            ],
+        )
+    }
+
+    fn load_audit_log(&self) -> Result<String> {
+        self.load_string(FileType::Audit)
+    }
+
+    fn append_audit_log(&mut self, lines: &str) -> Result<()> {
+        self.append(FileType::Audit, lines)
+    }
+
+    fn load_pending_audit(&self) -> Result<String> {
+        let path = self.get_path(FileType::AuditPending);
+        match path.exists() {
+            true => fs::read_to_string(path),
+            false => Ok(String::new()),
+        }
+    }
+
+    fn append_pending_audit(&mut self, lines: &str) -> Result<()> {
+        self.append(FileType::AuditPending, lines)
+    }
+
+    fn clear_pending_audit(&mut self) -> Result<()> {
+        self.check_writable()?;
+        let path = self.get_path(FileType::AuditPending);
+        match path.exists() {
+            true => fs::remove_file(path),
+            false => Ok(()),
+        }
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        self.check_writable()?;
+        for file_type in [
+            FileType::Commit,
+            FileType::Accounts,
+            FileType::Secrets,
+            FileType::Audit,
+            FileType::AuditPending,
+        ] {
+            let path = self.get_path(file_type);
+            overwrite_and_remove(&get_temp_path(&path))?;
+            overwrite_and_remove(&path)?;
+        }
+        wipe_directory(&self.directory.join("backups"))
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec![
+            self.get_path(FileType::Secrets),
+            self.get_path(FileType::Accounts),
+        ]
+    }
+
+    fn backup(&mut self, name: &str) -> Result<String> {
+        self.check_writable()?;
+        let backup = create_backup_directory(&self.directory, name)?;
+        write_synced(&backup.join(FILE_NAME), &self.load_accounts()?)?;
+        write_synced(
+            &backup.join(SECRETS_FILE_NAME),
+            self.load_secrets()?.as_bytes(),
+        )?;
+        Ok(backup.display().to_string())
+    }
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+fn get_temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".");
+    temp_path.push(TEMP_EXTENSION);
+    PathBuf::from(temp_path)
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut file = private_options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+// Replace several files so that either all or none of the new contents are visible.
+// Every file is staged next to its target, then the commit marker is renamed into place.
+// Once the marker exists the commit is complete and is rolled forward on the next load.
+fn commit_files(commit_path: &Path, files: &[(PathBuf, Vec<u8>)]) -> Result<()> {
+    for (path, contents) in files {
+        write_synced(&get_temp_path(path), contents)?;
+    }
+
+    let manifest = files
+        .iter()
+        .map(|(path, _)| path.to_string_lossy().into_owned())
+        .collect::<Vec<String>>()
+        .join("\n");
+    let commit_temp_path = get_temp_path(commit_path);
+    write_synced(&commit_temp_path, manifest.as_bytes())?;
+    fs::rename(&commit_temp_path, commit_path)?;
+
+    recover_commit(commit_path)
+}
+
+// Finish a commit interrupted after its marker was written, or discard one interrupted before
+fn recover_commit(commit_path: &Path) -> Result<()> {
+    if !commit_path.exists() {
+        let commit_temp_path = get_temp_path(commit_path);
+        if commit_temp_path.exists() {
+            fs::remove_file(commit_temp_path)?;
+        }
+        return Ok(());
+    }
+
+    let manifest = fs::read_to_string(commit_path)?;
+    for path in manifest.lines().map(PathBuf::from) {
+        let temp_path = get_temp_path(&path);
+        if temp_path.exists() {
+            fs::rename(temp_path, &path)?;
+        }
+    }
+
+    fs::remove_file(commit_path)
+}
+
+// Where a file's latest contents are, which is still the staged copy when a commit was
+// complete but not yet rolled forward
+fn committed_path(commit_path: &Path, path: &Path) -> Result<PathBuf> {
+    let temp_path = get_temp_path(path);
+    let staged = match fs::read_to_string(commit_path) {
+        Ok(manifest) => manifest.lines().any(|line| Path::new(line) == path),
+        Err(err) if err.kind() == ErrorKind::NotFound => false,
+        Err(err) => return Err(err),
+    };
+    match staged && temp_path.exists() {
+        true => Ok(temp_path),
+        false => Ok(path.to_path_buf()),
+    }
+}
+
+fn load_file_to_string(path: &PathBuf) -> Result<String> {
+    if !path.exists() {
+        private_options().write(true).create(true).open(path)?;
+    }
+
+    let file = File::open(path)?;
+    let mut buf_reader = BufReader::new(file);
+    let mut contents = String::new();
+    buf_reader.read_to_string(&mut contents)?;
+
+    Ok(contents)
+}
+
+fn load_file_to_vec(path: &PathBuf) -> Result<Vec<u8>> {
+    if !path.exists() {
+        private_options().write(true).create(true).open(path)?;
+    }
+
+    let file = File::open(path)?;
+    let mut buf_reader = BufReader::new(file);
+    let mut contents: Vec<u8> = Vec::new();
+    buf_reader.read_to_end(&mut contents)?;
+
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::get_test_directory;
+
+    #[test]
+    fn commits_files_together() {
+        let directory = get_test_directory("commit");
+        let commit_path = directory.join(COMMIT_FILE_NAME);
+        let accounts_path = directory.join(FILE_NAME);
+        let secrets_path = directory.join(SECRETS_FILE_NAME);
+        fs::write(&accounts_path, "old accounts").unwrap();
+        fs::write(&secrets_path, "old secrets").unwrap();
+
+        commit_files(
+            &commit_path,
+            &[
+                (accounts_path.clone(), b"new accounts".to_vec()),
+                (secrets_path.clone(), b"new secrets".to_vec()),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(&accounts_path).unwrap(), "new accounts");
+        assert_eq!(fs::read_to_string(&secrets_path).unwrap(), "new secrets");
+        assert!(!commit_path.exists());
+        assert!(!get_temp_path(&accounts_path).exists());
+        assert!(!get_temp_path(&secrets_path).exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn rolls_forward_an_interrupted_commit() {
+        let directory = get_test_directory("roll-forward");
+        let commit_path = directory.join(COMMIT_FILE_NAME);
+        let accounts_path = directory.join(FILE_NAME);
+        let secrets_path = directory.join(SECRETS_FILE_NAME);
+        fs::write(&accounts_path, "new accounts").unwrap();
+        fs::write(&secrets_path, "old secrets").unwrap();
+        fs::write(get_temp_path(&secrets_path), "new secrets").unwrap();
+        fs::write(
+            &commit_path,
+            format!(
+                "{}\n{}",
+                accounts_path.to_string_lossy(),
+                secrets_path.to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        recover_commit(&commit_path).unwrap();
+
+        assert_eq!(fs::read_to_string(&accounts_path).unwrap(), "new accounts");
+        assert_eq!(fs::read_to_string(&secrets_path).unwrap(), "new secrets");
+        assert!(!commit_path.exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn reads_an_interrupted_commit_read_only_without_finishing_it() {
+        let directory = get_test_directory("read-only");
+        let commit_path = directory.join(COMMIT_FILE_NAME);
+        let accounts_path = directory.join(FILE_NAME);
+        let secrets_path = directory.join(SECRETS_FILE_NAME);
+        fs::write(&accounts_path, "new accounts").unwrap();
+        fs::write(&secrets_path, "old secrets").unwrap();
+        fs::write(get_temp_path(&secrets_path), "new secrets").unwrap();
+        fs::write(&commit_path, secrets_path.to_string_lossy().as_bytes()).unwrap();
+
+        let mut backend = FileBackend::open_read_only(&directory);
+
+        assert_eq!(backend.load_secrets().unwrap(), "new secrets");
+        assert_eq!(backend.load_accounts().unwrap(), b"new accounts");
+        assert_eq!(backend.load_audit_log().unwrap(), "");
+        assert!(backend.commit("secrets", b"accounts").is_err());
+        assert!(backend.append_audit_log("line\n").is_err());
+        assert!(backend.destroy().is_err());
+        assert!(backend.backup("schema-0").is_err());
+        assert_eq!(fs::read_to_string(&secrets_path).unwrap(), "old secrets");
+        assert!(commit_path.exists());
+        assert!(!directory.join(AUDIT_FILE_NAME).exists());
+        assert!(!directory.join("backups").exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn discards_an_uncommitted_commit() {
+        let directory = get_test_directory("discard");
+        let commit_path = directory.join(COMMIT_FILE_NAME);
+        let accounts_path = directory.join(FILE_NAME);
+        fs::write(&accounts_path, "old accounts").unwrap();
+        fs::write(get_temp_path(&accounts_path), "new accounts").unwrap();
+        fs::write(get_temp_path(&commit_path), "partial").unwrap();
+
+        recover_commit(&commit_path).unwrap();
+
+        assert_eq!(fs::read_to_string(&accounts_path).unwrap(), "old accounts");
+        assert!(!get_temp_path(&commit_path).exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn stores_a_vault_in_files() {
+        let directory = get_test_directory("file-backend");
+        let mut backend = FileBackend::open(&directory).unwrap();
+
+        crate::storage::tests::stores_a_vault(&mut backend);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn creates_files_only_the_owner_can_read() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = get_test_directory("file-modes");
+        let mut backend = FileBackend::open(&directory).unwrap();
+        backend.commit("hash = \"a\"\n", &[1]).unwrap();
+        backend.append_audit_log("first\n").unwrap();
+
+        for path in [
+            backend.get_path(FileType::Secrets),
+            backend.get_path(FileType::Accounts),
+            backend.get_path(FileType::Audit),
+        ] {
+            let mode = fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
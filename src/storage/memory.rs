@@ -0,0 +1,87 @@
+use std::io::Result;
+use std::path::PathBuf;
+
+use super::StorageBackend;
+
+// Keeps a vault for the life of the process, for tests and embedding
+#[derive(Default)]
+pub struct MemoryBackend {
+    secrets: String,
+    accounts: Vec<u8>,
+    audit_log: String,
+    pending_audit: String,
+    backups: Vec<(String, String, Vec<u8>)>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn load_secrets(&self) -> Result<String> {
+        Ok(self.secrets.clone())
+    }
+
+    fn load_accounts(&self) -> Result<Vec<u8>> {
+        Ok(self.accounts.clone())
+    }
+
+    fn commit(&mut self, secrets: &str, accounts: &[u8]) -> Result<()> {
+        self.secrets = String::from(secrets);
+        self.accounts = accounts.to_vec();
+        Ok(())
+    }
+
+    fn load_audit_log(&self) -> Result<String> {
+        Ok(self.audit_log.clone())
+    }
+
+    fn append_audit_log(&mut self, lines: &str) -> Result<()> {
+        self.audit_log.push_str(lines);
+        Ok(())
+    }
+
+    fn load_pending_audit(&self) -> Result<String> {
+        Ok(self.pending_audit.clone())
+    }
+
+    fn append_pending_audit(&mut self, lines: &str) -> Result<()> {
+        self.pending_audit.push_str(lines);
+        Ok(())
+    }
+
+    fn clear_pending_audit(&mut self) -> Result<()> {
+        self.pending_audit.clear();
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        *self = MemoryBackend::default();
+        Ok(())
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    fn backup(&mut self, name: &str) -> Result<String> {
+        self.backups.push((
+            String::from(name),
+            self.secrets.clone(),
+            self.accounts.clone(),
+        ));
+        Ok(String::from(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_a_vault_in_memory() {
+        crate::storage::tests::stores_a_vault(&mut MemoryBackend::new());
+    }
+}
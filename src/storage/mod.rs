@@ -0,0 +1,418 @@
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+pub mod file;
+pub mod memory;
+pub mod sqlite;
+
+pub use file::FileBackend;
+pub use memory::MemoryBackend;
+pub use sqlite::SqliteBackend;
+
+// One account encrypted on its own, under an id that doesn't give away its name
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub id: String,
+    pub nonce: Vec<u8>,
+    pub contents: Vec<u8>,
+}
+
+// Where a vault keeps its secrets, encrypted accounts and audit log. The account store
+// does all of the encryption, so backends only ever see ciphertext and plain metadata.
+pub trait StorageBackend {
+    fn load_secrets(&self) -> Result<String>;
+    fn load_accounts(&self) -> Result<Vec<u8>>;
+    // The secrets hold the nonce for the accounts, so both are replaced together
+    fn commit(&mut self, secrets: &str, accounts: &[u8]) -> Result<()>;
+    // Backends with a record per account keep the accounts out of the blob, so a save only
+    // writes the accounts that changed. The others hold every account in the blob.
+    fn stores_records(&self) -> bool {
+        false
+    }
+    fn load_records(&self) -> Result<Vec<Record>> {
+        Ok(Vec::new())
+    }
+    // Commits the blob together with the changed records and the removal of the others
+    fn commit_records(
+        &mut self,
+        _secrets: &str,
+        _accounts: &[u8],
+        _changed: &[Record],
+        _removed: &[String],
+    ) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "This storage backend keeps every account in one blob",
+        ))
+    }
+    fn load_audit_log(&self) -> Result<String>;
+    fn append_audit_log(&mut self, lines: &str) -> Result<()>;
+    fn load_pending_audit(&self) -> Result<String>;
+    fn append_pending_audit(&mut self, lines: &str) -> Result<()>;
+    fn clear_pending_audit(&mut self) -> Result<()>;
+    // Overwrites and removes everything the vault wrote, beyond recovery where the disk allows
+    fn destroy(&mut self) -> Result<()>;
+    // The files a vault can't be opened without, for checking their permissions
+    fn paths(&self) -> Vec<PathBuf>;
+    // Copies the vault aside before it is rewritten in a new format, returning where it went
+    fn backup(&mut self, name: &str) -> Result<String>;
+    // Backends opened to look at a vault refuse every write, so the vault is left as it was
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BackendType {
+    File,
+    Sqlite,
+}
+
+impl BackendType {
+    pub fn from_str(backend: &str) -> Option<Self> {
+        match backend {
+            "file" => Some(BackendType::File),
+            "sqlite" => Some(BackendType::Sqlite),
+            _ => None,
+        }
+    }
+}
+
+// Only the owner may use the store, whatever the umask
+const PRIVATE_DIRECTORY_MODE: u32 = 0o700;
+const PRIVATE_FILE_MODE: u32 = 0o600;
+
+fn private_directory_builder() -> fs::DirBuilder {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, PRIVATE_DIRECTORY_MODE);
+    builder
+}
+
+// Where the store is kept, which may not exist yet
+pub fn find_directory() -> PathBuf {
+    let home = dirs::home_dir().expect("Unable to find home directory");
+    home.join(".otp")
+}
+
+pub fn get_directory() -> PathBuf {
+    let directory = find_directory();
+    private_directory_builder()
+        .recursive(true)
+        .create(&directory)
+        .expect("Unable to create .otp directory");
+    directory
+}
+
+// A new directory under backups for each copy, numbered if the name was taken before
+fn create_backup_directory(directory: &Path, name: &str) -> Result<PathBuf> {
+    let backups = directory.join("backups");
+    private_directory_builder()
+        .recursive(true)
+        .create(&backups)?;
+    let mut number = 1;
+    loop {
+        let backup = match number {
+            1 => backups.join(name),
+            number => backups.join(format!("{}-{}", name, number)),
+        };
+        match private_directory_builder().create(&backup) {
+            Ok(_) => return Ok(backup),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => number += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// Random bytes over the old contents before unlinking, so they aren't left in the free
+// blocks. Copy-on-write filesystems and SSDs may still keep older copies.
+fn overwrite_and_remove(path: &Path) -> Result<()> {
+    let length = match fs::metadata(path) {
+        Ok(metadata) => metadata.len() as usize,
+        Err(_) => return Ok(()),
+    };
+    let mut noise = vec![0u8; length];
+    OsRng.fill_bytes(&mut noise);
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.write_all(&noise)?;
+    file.sync_all()?;
+    fs::remove_file(path)
+}
+
+// Overwrites every file under the directory and removes it all. Links are removed without
+// touching what they point to.
+fn wipe_directory(directory: &Path) -> Result<()> {
+    if !directory.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        match (file_type.is_dir(), file_type.is_symlink()) {
+            (true, _) => wipe_directory(&entry.path())?,
+            (_, true) => fs::remove_file(entry.path())?,
+            _ => overwrite_and_remove(&entry.path())?,
+        }
+    }
+    fs::remove_dir(directory)
+}
+
+// Options for opening store files, which are created readable by their owner alone
+pub fn private_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, PRIVATE_FILE_MODE);
+    options
+}
+
+// Run before every load. Refuses a directory or file owned by another user, and makes private
+// any that others could reach, with a warning for each since their contents may have leaked.
+#[cfg(unix)]
+pub fn secure_directory(directory: &Path) -> Result<Vec<String>> {
+    secure_directory_for(directory, unsafe { libc::getuid() })
+}
+
+#[cfg(not(unix))]
+pub fn secure_directory(_directory: &Path) -> Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+#[cfg(unix)]
+fn secure_directory_for(directory: &Path, uid: u32) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    secure_tree(directory, uid, &mut warnings)?;
+    Ok(warnings)
+}
+
+// Subdirectories such as backups hold whole copies of the vault, so they get the same treatment
+#[cfg(unix)]
+fn secure_tree(directory: &Path, uid: u32, warnings: &mut Vec<String>) -> Result<()> {
+    secure_path(directory, PRIVATE_DIRECTORY_MODE, uid, warnings)?;
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        // Symbolic links and sockets are left alone, the agent checks its own socket
+        if file_type.is_dir() {
+            secure_tree(&entry.path(), uid, warnings)?;
+        } else if file_type.is_file() {
+            secure_path(&entry.path(), PRIVATE_FILE_MODE, uid, warnings)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn secure_path(path: &Path, mode: u32, uid: u32, warnings: &mut Vec<String>) -> Result<()> {
+    use std::io::{Error, ErrorKind};
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let metadata = fs::metadata(path)?;
+    if metadata.uid() != uid {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} is owned by another user", path.display()),
+        ));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        warnings.push(format!(
+            "{} was accessible to other users, its permissions were set to {:o}",
+            path.display(),
+            mode
+        ));
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+// A vault keeps the backend it was created with, found by the files in its directory
+pub fn open(directory: &Path) -> Result<Box<dyn StorageBackend>> {
+    match SqliteBackend::exists(directory) {
+        true => Ok(Box::new(SqliteBackend::open(directory)?)),
+        false => Ok(Box::new(FileBackend::open(directory)?)),
+    }
+}
+
+// Opens a vault without changing anything in its directory, not even creating missing files
+pub fn open_read_only(directory: &Path) -> Result<Box<dyn StorageBackend>> {
+    match SqliteBackend::exists(directory) {
+        true => Ok(Box::new(SqliteBackend::open_read_only(directory)?)),
+        false => Ok(Box::new(FileBackend::open_read_only(directory))),
+    }
+}
+
+fn read_only_error() -> Error {
+    Error::new(
+        ErrorKind::PermissionDenied,
+        "The account store was opened read-only",
+    )
+}
+
+pub fn create(backend_type: BackendType, directory: &Path) -> Result<Box<dyn StorageBackend>> {
+    match backend_type {
+        BackendType::File => Ok(Box::new(FileBackend::open(directory)?)),
+        BackendType::Sqlite => Ok(Box::new(SqliteBackend::open(directory)?)),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub fn get_test_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("otp-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    // Every backend has to hand back exactly what it was given
+    pub fn stores_a_vault(backend: &mut impl StorageBackend) {
+        assert_eq!(backend.load_secrets().unwrap(), "");
+        assert_eq!(backend.load_accounts().unwrap(), Vec::<u8>::new());
+        assert_eq!(backend.load_audit_log().unwrap(), "");
+        assert_eq!(backend.load_pending_audit().unwrap(), "");
+
+        backend.commit("hash = \"a\"\n", &[1, 2, 3]).unwrap();
+        backend.commit("hash = \"b\"\n", &[4, 5]).unwrap();
+        backend.append_audit_log("first\n").unwrap();
+        backend.append_audit_log("second\nthird\n").unwrap();
+        backend.append_pending_audit("60 unlock failure\n").unwrap();
+        backend.append_pending_audit("90 unlock failure\n").unwrap();
+
+        assert_eq!(backend.load_secrets().unwrap(), "hash = \"b\"\n");
+        assert_eq!(backend.load_accounts().unwrap(), vec![4, 5]);
+        assert_eq!(backend.load_audit_log().unwrap(), "first\nsecond\nthird\n");
+        assert_eq!(
+            backend.load_pending_audit().unwrap(),
+            "60 unlock failure\n90 unlock failure\n"
+        );
+
+        backend.clear_pending_audit().unwrap();
+        backend.clear_pending_audit().unwrap();
+        assert_eq!(backend.load_pending_audit().unwrap(), "");
+
+        backend
+            .append_pending_audit("120 unlock failure\n")
+            .unwrap();
+        backend.destroy().unwrap();
+        assert_eq!(backend.load_secrets().unwrap(), "");
+        assert_eq!(backend.load_accounts().unwrap(), Vec::<u8>::new());
+        assert_eq!(backend.load_audit_log().unwrap(), "");
+        assert_eq!(backend.load_pending_audit().unwrap(), "");
+    }
+
+    #[test]
+    fn destroys_backups_with_the_vault() {
+        for backend_type in [BackendType::File, BackendType::Sqlite] {
+            let directory = get_test_directory("destroy-backups");
+            let mut backend = create(backend_type, &directory).unwrap();
+            backend.commit("hash = \"a\"\n", &[1, 2]).unwrap();
+            backend.backup("schema-0").unwrap();
+            backend.backup("schema-0").unwrap();
+            assert!(directory.join("backups").join("schema-0").exists());
+            assert!(directory.join("backups").join("schema-0-2").exists());
+
+            backend.destroy().unwrap();
+            assert!(!directory.join("backups").exists());
+            fs::remove_dir_all(directory).unwrap();
+        }
+    }
+
+    #[test]
+    fn opens_the_backend_a_vault_was_created_with() {
+        let directory = get_test_directory("open");
+        assert!(!SqliteBackend::exists(&directory));
+
+        let mut backend = create(BackendType::Sqlite, &directory).unwrap();
+        backend.commit("hash = \"a\"\n", &[1]).unwrap();
+        drop(backend);
+
+        assert!(SqliteBackend::exists(&directory));
+        let backend = open(&directory).unwrap();
+        assert_eq!(backend.load_accounts().unwrap(), vec![1]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn makes_the_store_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = get_test_directory("private");
+        let secrets_path = directory.join("secrets.txt");
+        let accounts_path = directory.join("accounts.txt");
+        fs::write(&secrets_path, "").unwrap();
+        fs::write(&accounts_path, "").unwrap();
+        fs::set_permissions(&directory, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&secrets_path, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(&accounts_path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let warnings = secure_directory(&directory).unwrap();
+
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[1].starts_with(&format!("{} was accessible", secrets_path.display())));
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&directory), 0o700);
+        assert_eq!(mode(&secrets_path), 0o600);
+        assert_eq!(mode(&accounts_path), 0o600);
+        assert_eq!(secure_directory(&directory).unwrap(), Vec::<String>::new());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn makes_the_backups_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = get_test_directory("private-backups");
+        let mut backend = create(BackendType::File, &directory).unwrap();
+        backend.commit("hash = \"a\"\n", &[1]).unwrap();
+        let backup = PathBuf::from(backend.backup("schema-1").unwrap());
+        let backups = directory.join("backups");
+        let accounts_path = backup.join("accounts.txt");
+        fs::set_permissions(&directory, fs::Permissions::from_mode(0o700)).unwrap();
+        fs::set_permissions(&backups, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&backup, fs::Permissions::from_mode(0o750)).unwrap();
+        fs::set_permissions(&accounts_path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let warnings = secure_directory(&directory).unwrap();
+
+        assert_eq!(warnings.len(), 3);
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&backups), 0o700);
+        assert_eq!(mode(&backup), 0o700);
+        assert_eq!(mode(&accounts_path), 0o600);
+        assert_eq!(secure_directory(&directory).unwrap(), Vec::<String>::new());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_a_store_owned_by_another_user() {
+        let directory = get_test_directory("owner");
+        let uid = unsafe { libc::getuid() };
+
+        let err = secure_directory_for(&directory, uid + 1).unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            err.to_string(),
+            format!("{} is owned by another user", directory.display())
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn parses_backend_types() {
+        assert_eq!(BackendType::from_str("file"), Some(BackendType::File));
+        assert_eq!(BackendType::from_str("sqlite"), Some(BackendType::Sqlite));
+        assert_eq!(BackendType::from_str("toml"), None);
+    }
+}
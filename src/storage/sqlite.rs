@@ -0,0 +1,359 @@
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::fs;
+use std::io::{Error, Result};
+use std::path::{Path, PathBuf};
+
+use super::{
+    create_backup_directory, private_options, read_only_error, wipe_directory, Record,
+    StorageBackend,
+};
+
+const DATABASE_NAME: &str = "accounts.db";
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS vault (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        secrets TEXT NOT NULL,
+        accounts BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS accounts (
+        id TEXT PRIMARY KEY,
+        nonce BLOB NOT NULL,
+        contents BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS audit_log (
+        sequence INTEGER PRIMARY KEY,
+        line TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS audit_pending (
+        id INTEGER PRIMARY KEY,
+        line TEXT NOT NULL
+    );
+";
+
+fn to_error(err: rusqlite::Error) -> Error {
+    Error::other(format!("Database error: {}", err))
+}
+
+// A single database file, with one row per account and per audit log entry, so large
+// vaults only write what changed
+pub struct SqliteBackend {
+    connection: Connection,
+    path: PathBuf,
+    read_only: bool,
+}
+
+impl SqliteBackend {
+    pub fn exists(directory: &Path) -> bool {
+        get_path(directory).exists()
+    }
+
+    pub fn open(directory: &Path) -> Result<SqliteBackend> {
+        let path = get_path(directory);
+        // SQLite gives its journal the same permissions as the database
+        if !path.exists() {
+            private_options().write(true).create(true).open(&path)?;
+        }
+        let connection = Connection::open(&path).map_err(to_error)?;
+        connection.execute_batch(SCHEMA).map_err(to_error)?;
+        Ok(SqliteBackend {
+            connection,
+            path,
+            read_only: false,
+        })
+    }
+
+    // SQLite refuses writes on the connection, and the schema is left as it is
+    pub fn open_read_only(directory: &Path) -> Result<SqliteBackend> {
+        let path = get_path(directory);
+        let connection = Connection::open_with_flags(
+            &path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(to_error)?;
+        Ok(SqliteBackend {
+            connection,
+            path,
+            read_only: true,
+        })
+    }
+
+    // Databases from older versions lack the tables added since, until they are opened to write
+    fn has_table(&self, name: &str) -> Result<bool> {
+        self.connection
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                params![name],
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|table| table.is_some())
+            .map_err(to_error)
+    }
+
+    fn load_lines(&self, query: &str) -> Result<String> {
+        let mut statement = self.connection.prepare(query).map_err(to_error)?;
+        let lines = statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(to_error)?;
+        let mut contents = String::new();
+        for line in lines {
+            contents.push_str(&line.map_err(to_error)?);
+            contents.push('\n');
+        }
+        Ok(contents)
+    }
+
+    fn append_lines(&mut self, insert: &str, lines: &str) -> Result<()> {
+        let transaction = self.connection.transaction().map_err(to_error)?;
+        for line in lines.lines() {
+            transaction
+                .execute(insert, params![line])
+                .map_err(to_error)?;
+        }
+        transaction.commit().map_err(to_error)
+    }
+}
+
+fn get_path(directory: &Path) -> PathBuf {
+    directory.join(DATABASE_NAME)
+}
+
+impl StorageBackend for SqliteBackend {
+    fn load_secrets(&self) -> Result<String> {
+        let secrets = self
+            .connection
+            .query_row("SELECT secrets FROM vault WHERE id = 1", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()
+            .map_err(to_error)?;
+        Ok(secrets.unwrap_or_default())
+    }
+
+    fn load_accounts(&self) -> Result<Vec<u8>> {
+        let accounts = self
+            .connection
+            .query_row("SELECT accounts FROM vault WHERE id = 1", [], |row| {
+                row.get::<_, Vec<u8>>(0)
+            })
+            .optional()
+            .map_err(to_error)?;
+        Ok(accounts.unwrap_or_default())
+    }
+
+    fn commit(&mut self, secrets: &str, accounts: &[u8]) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO vault (id, secrets, accounts) VALUES (1, ?1, ?2)",
+                params![secrets, accounts],
+            )
+            .map(|_| ())
+            .map_err(to_error)
+    }
+
+    fn stores_records(&self) -> bool {
+        true
+    }
+
+    fn load_records(&self) -> Result<Vec<Record>> {
+        if !self.has_table("accounts")? {
+            return Ok(Vec::new());
+        }
+        let mut statement = self
+            .connection
+            .prepare("SELECT id, nonce, contents FROM accounts ORDER BY id")
+            .map_err(to_error)?;
+        let records = statement
+            .query_map([], |row| {
+                Ok(Record {
+                    id: row.get(0)?,
+                    nonce: row.get(1)?,
+                    contents: row.get(2)?,
+                })
+            })
+            .map_err(to_error)?;
+        records.map(|record| record.map_err(to_error)).collect()
+    }
+
+    fn commit_records(
+        &mut self,
+        secrets: &str,
+        accounts: &[u8],
+        changed: &[Record],
+        removed: &[String],
+    ) -> Result<()> {
+        let transaction = self.connection.transaction().map_err(to_error)?;
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO vault (id, secrets, accounts) VALUES (1, ?1, ?2)",
+                params![secrets, accounts],
+            )
+            .map_err(to_error)?;
+        for record in changed {
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO accounts (id, nonce, contents) VALUES (?1, ?2, ?3)",
+                    params![record.id, record.nonce, record.contents],
+                )
+                .map_err(to_error)?;
+        }
+        for id in removed {
+            transaction
+                .execute("DELETE FROM accounts WHERE id = ?1", params![id])
+                .map_err(to_error)?;
+        }
+        transaction.commit().map_err(to_error)
+    }
+
+    fn load_audit_log(&self) -> Result<String> {
+        self.load_lines("SELECT line FROM audit_log ORDER BY sequence")
+    }
+
+    fn append_audit_log(&mut self, lines: &str) -> Result<()> {
+        self.append_lines("INSERT INTO audit_log (line) VALUES (?1)", lines)
+    }
+
+    fn load_pending_audit(&self) -> Result<String> {
+        if !self.has_table("audit_pending")? {
+            return Ok(String::new());
+        }
+        self.load_lines("SELECT line FROM audit_pending ORDER BY id")
+    }
+
+    fn append_pending_audit(&mut self, lines: &str) -> Result<()> {
+        self.append_lines("INSERT INTO audit_pending (line) VALUES (?1)", lines)
+    }
+
+    fn clear_pending_audit(&mut self) -> Result<()> {
+        self.connection
+            .execute("DELETE FROM audit_pending", [])
+            .map(|_| ())
+            .map_err(to_error)
+    }
+
+    // Secure delete zeroes the freed pages and vacuum rewrites the file without them, so
+    // nothing is left behind when the database file goes
+    fn destroy(&mut self) -> Result<()> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
+        self.connection
+            .execute_batch(
+                "PRAGMA secure_delete = ON;
+                 DELETE FROM vault;
+                 DELETE FROM accounts;
+                 DELETE FROM audit_log;
+                 DELETE FROM audit_pending;
+                 VACUUM;",
+            )
+            .map_err(to_error)?;
+        fs::remove_file(&self.path)?;
+        match self.path.parent() {
+            Some(directory) => wipe_directory(&directory.join("backups")),
+            None => Ok(()),
+        }
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+
+    // A consistent copy of the whole database, audit log included
+    fn backup(&mut self, name: &str) -> Result<String> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
+        let directory = self.path.parent().unwrap_or_else(|| Path::new("."));
+        let backup = create_backup_directory(directory, name)?.join(DATABASE_NAME);
+        private_options().write(true).create(true).open(&backup)?;
+        self.connection
+            .execute("VACUUM INTO ?1", params![backup.to_string_lossy()])
+            .map_err(to_error)?;
+        Ok(backup.display().to_string())
+    }
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::get_test_directory;
+
+    #[test]
+    fn stores_a_vault_in_sqlite() {
+        let directory = get_test_directory("sqlite-backend");
+        let mut backend = SqliteBackend::open(&directory).unwrap();
+
+        crate::storage::tests::stores_a_vault(&mut backend);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn keeps_a_row_per_audit_entry() {
+        let directory = get_test_directory("sqlite-rows");
+        let mut backend = SqliteBackend::open(&directory).unwrap();
+
+        backend.append_audit_log("first\nsecond\n").unwrap();
+
+        let rows: i64 = backend
+            .connection
+            .query_row("SELECT COUNT(*) FROM audit_log", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 2);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn record(id: &str, contents: &[u8]) -> Record {
+        Record {
+            id: String::from(id),
+            nonce: vec![0; 24],
+            contents: contents.to_vec(),
+        }
+    }
+
+    #[test]
+    fn keeps_a_row_per_account() {
+        let directory = get_test_directory("sqlite-records");
+        let mut backend = SqliteBackend::open(&directory).unwrap();
+        assert!(backend.stores_records());
+
+        backend
+            .commit_records("", &[1], &[record("a", &[2]), record("b", &[3])], &[])
+            .unwrap();
+        backend
+            .commit_records("", &[4], &[record("b", &[5])], &[String::from("a")])
+            .unwrap();
+
+        assert_eq!(backend.load_accounts().unwrap(), vec![4]);
+        assert_eq!(backend.load_records().unwrap(), vec![record("b", &[5])]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn backs_up_the_database() {
+        let directory = get_test_directory("sqlite-backup");
+        let mut backend = SqliteBackend::open(&directory).unwrap();
+        backend.commit("hash = \"a\"\n", &[1, 2]).unwrap();
+
+        let backup = backend.backup("schema-0").unwrap();
+        backend.commit("hash = \"b\"\n", &[3]).unwrap();
+        let second_backup = backend.backup("schema-0").unwrap();
+
+        let backup_directory = directory.join("backups").join("schema-0");
+        assert_eq!(
+            backup,
+            backup_directory.join(DATABASE_NAME).display().to_string()
+        );
+        assert!(second_backup.contains("schema-0-2"));
+        let backup = SqliteBackend::open(&backup_directory).unwrap();
+        assert_eq!(backup.load_secrets().unwrap(), "hash = \"a\"\n");
+        assert_eq!(backup.load_accounts().unwrap(), vec![1, 2]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
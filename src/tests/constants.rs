@@ -4,3 +4,19 @@ pub const ACCOUNT_NAME_3: &str = "test_account_3";
 pub const HOTP_KEY: &str = "FGCZ6RHPYYYFOEKRQNNF3Z2JKKANZXNX";
 pub const TOTP_KEY: &str = "NDVP6W4K6HKVUQJUY4F627PCSYUVQSNJF4BBTH2BQT24LONOLSXQ";
 pub const PIN: &str = "123456";
+pub const NEW_PIN: &str = "5555";
+pub const PASSPHRASE: &str = "correct horse battery staple";
+pub const NEW_PIN_PROMPTS: &str = "Enter a new pin:\nConfirm the new pin:\n";
+pub const NEW_PASSPHRASE_PROMPTS: &str = "Enter a new passphrase:\nConfirm the new passphrase:\n";
+
+use crate::crypto::{KdfParams, KdfVariant};
+
+// Cheap key derivation so tests don't pay for production Argon2 costs
+pub fn test_kdf_params() -> KdfParams {
+    KdfParams {
+        variant: KdfVariant::Argon2id,
+        mem_cost: 64,
+        time_cost: 1,
+        lanes: 1,
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::fmt::Write;
 use std::ops::Add;
 use std::time::{Duration, SystemTime};
@@ -31,23 +32,59 @@ impl OutErr for MockOtpWriter {
 
 pub struct MockOtpReader {
     pub input: Vec<u8>,
+    // How many lines were read with echo turned off
+    pub secret_reads: usize,
     expected: String,
+    // Lines handed out one per read, followed by the end of input
+    script: Option<VecDeque<String>>,
+    interactive: bool,
 }
 
 impl MockOtpReader {
     pub fn new(expected: &str) -> Self {
         MockOtpReader {
             input: Vec::new(),
+            secret_reads: 0,
             expected: expected.to_string(),
+            script: None,
+            interactive: false,
+        }
+    }
+
+    // Behaves like a terminal, so commands may prompt for choices
+    pub fn interactive(expected: &str) -> Self {
+        MockOtpReader {
+            interactive: true,
+            ..MockOtpReader::new(expected)
+        }
+    }
+
+    // Answers each read with the next line, then reaches the end of input
+    pub fn script(lines: &[&str]) -> Self {
+        MockOtpReader {
+            script: Some(lines.iter().map(|line| line.to_string()).collect()),
+            ..MockOtpReader::new("")
         }
     }
 }
 
 impl ReadLine for MockOtpReader {
-    fn read_line(&mut self, buffer: &mut String) -> String {
-		self.input.append(&mut self.expected.as_bytes().to_vec());
-		buffer.write_str(self.expected.as_str()).unwrap();
-		buffer.to_string()
+    fn read_line(&mut self, buffer: &mut String) {
+		let line = match &mut self.script {
+			Some(script) => script.pop_front().unwrap_or_default(),
+			None => self.expected.clone(),
+		};
+		self.input.append(&mut line.as_bytes().to_vec());
+		buffer.write_str(line.as_str()).unwrap();
+    }
+
+    fn read_secret(&mut self, buffer: &mut String) {
+		self.secret_reads += 1;
+		self.read_line(buffer);
+    }
+
+    fn is_interactive(&self) -> bool {
+        self.interactive
     }
 }
 
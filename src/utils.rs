@@ -1,8 +1,11 @@
 use data_encoding::BASE32_NOPAD;
 use rand::rngs::OsRng;
 use rand::RngCore;
+use std::fs;
 
 use crate::account::AccountStoreOperations;
+use crate::crypto::{hash_keyfile, MAX_MEMORY_MIB};
+use crate::secret::{Secret, SecretBytes};
 
 // Generate a 20 byte random base32 string
 pub fn generate_secret() -> String {
@@ -20,17 +23,94 @@ pub fn generate_secret_32() -> String {
 
 // Validate key provided in arguments is a valid base32 encoding
 pub fn is_base32_key(value: &str) -> Result<(), String> {
-    let value = value.to_uppercase();
-    match BASE32_NOPAD.decode(value.as_bytes()) {
-        Ok(_) => Ok(()),
-        Err(_) => Err(String::from("the key is not a valid base32 encoding")),
+    match decode_key(value) {
+        Some(_) => Ok(()),
+        None => Err(String::from("the key is not a valid base32 encoding")),
     }
 }
 
-pub fn validate_pin(pin: &str, account_store: &impl AccountStoreOperations) -> Result<(), String> {
-    if pin.len() < 4 || pin.len() > 6 {
-        return Err(String::from("PIN must be between 4 and 6 characters"));
+// The bytes a Base32 key stands for, in either case
+pub fn decode_key(key: &str) -> Option<Vec<u8>> {
+    BASE32_NOPAD.decode(key.to_uppercase().as_bytes()).ok()
+}
+
+// Escapes everything but the characters RFC 3986 leaves unreserved, for use in URIs
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                String::from(byte as char)
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// Validate numeric arguments such as Argon2 costs are greater than zero
+pub fn is_positive_number(value: &str) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(number) if number > 0 => Ok(()),
+        _ => Err(String::from("the value must be a positive number")),
+    }
+}
+
+pub fn is_kdf_memory(value: &str) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(mib) if mib > 0 && mib <= MAX_MEMORY_MIB => Ok(()),
+        _ => Err(format!(
+            "the value must be a number of MiB from 1 to {}",
+            MAX_MEMORY_MIB
+        )),
     }
+}
+
+// Validate numeric arguments such as HOTP counters that may be zero
+pub fn is_counter(value: &str) -> Result<(), String> {
+    match value.parse::<i32>() {
+        Ok(number) if number >= 0 => Ok(()),
+        _ => Err(String::from("the value must be zero or a positive number")),
+    }
+}
+
+// Format a unix timestamp as a UTC date and time, e.g. 2022-03-01 14:05:09 UTC
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// Validate tags are single words that can be typed on the command line
+pub fn is_tag(value: &str) -> Result<(), String> {
+    match value.is_empty() || value.chars().any(char::is_whitespace) {
+        true => Err(String::from("tags must not be empty or contain spaces")),
+        false => Ok(()),
+    }
+}
+
+pub fn validate_pin(pin: &str, account_store: &impl AccountStoreOperations) -> Result<(), String> {
+    account_store.get_pin_policy().validate(pin)?;
 
     if !account_store.is_initialized() {
         return Err(String::from(
@@ -39,32 +119,76 @@ pub fn validate_pin(pin: &str, account_store: &impl AccountStoreOperations) -> R
     }
 
     if !account_store.validate_pin(pin) {
-        return Err(String::from("Invalid pin"));
+        return Err(match account_store.has_keyfile() {
+            true => String::from("Invalid pin or keyfile"),
+            false => String::from("Invalid pin"),
+        });
     }
 
     Ok(())
 }
 
+pub fn read_keyfile(path: &str) -> Result<SecretBytes, String> {
+    let contents = fs::read(path)
+        .map(Secret::new)
+        .map_err(|err| format!("Unable to read keyfile {}: {}", path, err))?;
+    if contents.expose().is_empty() {
+        return Err(format!("Keyfile {} is empty", path));
+    }
+    Ok(hash_keyfile(contents.expose()))
+}
+
+// Identity files hold a comment with the public key, then the Base32 X25519 secret key
+pub fn read_identity(path: &str) -> Result<SecretBytes, String> {
+    let contents = fs::read_to_string(path)
+        .map(Secret::new)
+        .map_err(|err| format!("Unable to read identity {}: {}", path, err))?;
+    contents
+        .expose()
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .and_then(|line| BASE32_NOPAD.decode(line.as_bytes()).ok())
+        .map(Secret::new)
+        .filter(|identity| identity.expose().len() == 32)
+        .ok_or_else(|| format!("Identity {} holds no X25519 secret key", path))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::account::tests::create_empty_store;
-    use crate::account::MockAccountStore;
-    use crate::crypto::encrypt_pw;
-    use crate::tests::constants::PIN;
+    use crate::account::AccountStore;
+    use crate::tests::constants::{test_kdf_params, PIN};
 
-    fn get_mock_store(include_hash: bool) -> MockAccountStore {
+    fn get_mock_store(include_hash: bool) -> AccountStore {
         let mut account_store = create_empty_store();
         match include_hash {
             true => {
-                let hash = encrypt_pw(PIN).expect("Failed to encrypt pin");
-                account_store.set_secrets(&hash);
+                account_store
+                    .set_pin(PIN, test_kdf_params())
+                    .expect("Failed to encrypt pin");
                 account_store
             }
             false => account_store,
         }
     }
 
+    #[test]
+    fn formats_timestamps_in_utc() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(1646143509), "2022-03-01 14:05:09 UTC");
+    }
+
+    #[test]
+    fn is_tag_rejects_empty_tags_and_spaces() {
+        let expected = Err(String::from("tags must not be empty or contain spaces"));
+        assert_eq!(is_tag(""), expected);
+        assert_eq!(is_tag("on call"), expected);
+        assert_eq!(is_tag("on-call"), Ok(()));
+    }
+
     #[test]
     fn generates_a_20_byte_base32_secret() {
         let secret = generate_secret();
@@ -85,6 +209,36 @@ mod tests {
         assert_eq!(is_base32_key("ABCDEFGHIJKLMNOPQRSTUVWXYZ234567"), Ok(()));
     }
 
+    #[test]
+    fn is_counter_accepts_zero() {
+        let expected = Err(String::from("the value must be zero or a positive number"));
+        assert_eq!(is_counter("-1"), expected);
+        assert_eq!(is_counter("3000000000"), expected);
+        assert_eq!(is_counter("0"), Ok(()));
+        assert_eq!(is_counter("42"), Ok(()));
+    }
+
+    #[test]
+    fn is_positive_number_rejects_zero_and_non_numbers() {
+        let expected = Err(String::from("the value must be a positive number"));
+        assert_eq!(is_positive_number("0"), expected);
+        assert_eq!(is_positive_number("-1"), expected);
+        assert_eq!(is_positive_number("lots"), expected);
+        assert_eq!(is_positive_number("64"), Ok(()));
+    }
+
+    #[test]
+    fn is_kdf_memory_rejects_costs_that_overflow() {
+        let expected = Err(String::from(
+            "the value must be a number of MiB from 1 to 4096",
+        ));
+        assert_eq!(is_kdf_memory("0"), expected);
+        assert_eq!(is_kdf_memory("4097"), expected);
+        assert_eq!(is_kdf_memory("4000000"), expected);
+        assert_eq!(is_kdf_memory("4194304"), expected);
+        assert_eq!(is_kdf_memory("4096"), Ok(()));
+    }
+
     #[test]
     fn validate_pin_checks_pin_length() {
         let account_store = get_mock_store(false);
@@ -125,4 +279,28 @@ mod tests {
         let account_store = get_mock_store(true);
         assert_eq!(validate_pin("123456", &account_store), Ok(()));
     }
+
+    #[test]
+    fn reads_keyfiles() {
+        let directory = std::env::temp_dir().join(format!("otp-keyfile-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("keyfile");
+        let path = path.to_str().unwrap();
+
+        assert!(read_keyfile(path)
+            .unwrap_err()
+            .starts_with(&format!("Unable to read keyfile {}: ", path)));
+        fs::write(path, "").unwrap();
+        assert_eq!(
+            read_keyfile(path),
+            Err(format!("Keyfile {} is empty", path))
+        );
+        fs::write(path, "keyfile contents").unwrap();
+        assert_eq!(
+            read_keyfile(path).unwrap(),
+            hash_keyfile(b"keyfile contents")
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
 }
@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::account::{AccountStoreOperations, PRIMARY_CREDENTIAL};
+use crate::policy::{secret_weakness, MIN_SECRET_BITS};
+use crate::utils::decode_key;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Severity::Ok => "ok",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+// One result of checking the store, with a suggestion for anything that needs attention
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    pub fn ok(message: &str) -> Finding {
+        Finding {
+            severity: Severity::Ok,
+            message: String::from(message),
+            fix: None,
+        }
+    }
+
+    pub fn warning(message: &str, fix: &str) -> Finding {
+        Finding {
+            severity: Severity::Warning,
+            message: String::from(message),
+            fix: Some(String::from(fix)),
+        }
+    }
+
+    pub fn error(message: &str, fix: &str) -> Finding {
+        Finding {
+            severity: Severity::Error,
+            message: String::from(message),
+            fix: Some(String::from(fix)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_private(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o077 == 0
+}
+
+#[cfg(not(unix))]
+fn is_private(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn is_owned(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.uid() == unsafe { libc::getuid() }
+}
+
+#[cfg(not(unix))]
+fn is_owned(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
+// The directory and every file the backend keeps the vault in have to exist and be private
+pub fn check_files(paths: &[PathBuf]) -> Vec<Finding> {
+    paths
+        .iter()
+        .map(|path| match fs::metadata(path) {
+            Err(err) => Finding::error(
+                &format!("Unable to read {}: {}", path.display(), err),
+                "Restore the file from a backup",
+            ),
+            Ok(metadata) if !is_owned(&metadata) => Finding::error(
+                &format!("{} is owned by another user", path.display()),
+                "Check that nobody else has replaced it, then restore it from a backup",
+            ),
+            Ok(metadata) if !is_private(&metadata) => Finding::warning(
+                &format!("{} can be read by other users", path.display()),
+                &format!(
+                    "chmod {} {}",
+                    match metadata.is_dir() {
+                        true => 700,
+                        false => 600,
+                    },
+                    path.display()
+                ),
+            ),
+            Ok(_) => Finding::ok(&format!("{} is present and private", path.display())),
+        })
+        .collect()
+}
+
+// Secrets too short to be safe, and secrets shared by several accounts
+pub fn check_secrets(account_store: &impl AccountStoreOperations) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut owners: BTreeMap<Vec<u8>, Vec<String>> = BTreeMap::new();
+    for name in account_store.list() {
+        let account = match account_store.get(&name) {
+            Some(account) => account,
+            None => continue,
+        };
+        for (credential, key) in account.keys() {
+            let owner = match credential {
+                PRIMARY_CREDENTIAL => format!("\"{}\"", name),
+                credential => format!("credential \"{}\" of \"{}\"", credential, name),
+            };
+            let decoded = match decode_key(key.expose()) {
+                Some(decoded) => decoded,
+                None => {
+                    findings.push(Finding::error(
+                        &format!("The secret of {} is not valid Base32", owner),
+                        "Ask the service for a new secret and add the account again",
+                    ));
+                    continue;
+                }
+            };
+            if let Some(weakness) = secret_weakness(&decoded) {
+                findings.push(Finding::warning(
+                    &format!("The secret of {} is weak, {}", owner, weakness),
+                    &format!(
+                        "Ask the service for a new random secret of at least {} bits",
+                        MIN_SECRET_BITS
+                    ),
+                ));
+            }
+            owners.entry(decoded).or_default().push(owner);
+        }
+    }
+
+    for owners in owners.values().filter(|owners| owners.len() > 1) {
+        findings.push(Finding::warning(
+            &format!("Accounts {} share the same secret", owners.join(", ")),
+            "Check that each account was added with the secret its service issued",
+        ));
+    }
+    if findings.is_empty() {
+        findings.push(Finding::ok("No weak or duplicate secrets"));
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::tests::get_mock_store;
+    use crate::account::{Account, OtpType};
+    use crate::tests::constants::*;
+
+    #[test]
+    fn finds_weak_and_duplicate_secrets() {
+        let mut store = get_mock_store();
+        for name in [ACCOUNT_NAME_1, ACCOUNT_NAME_2] {
+            store.add(
+                String::from(name),
+                Account::new(String::from(TOTP_KEY), OtpType::TOTP),
+            );
+        }
+        store.add(
+            String::from(ACCOUNT_NAME_3),
+            Account::new(String::from("JBSWY3DPEHPK3PXP"), OtpType::TOTP),
+        );
+
+        let findings = check_secrets(&store);
+
+        assert_eq!(
+            findings,
+            vec![
+                Finding::warning(
+                    "The secret of \"test_account_3\" is weak, it is only 80 bits long, RFC 4226 requires at least 128",
+                    "Ask the service for a new random secret of at least 128 bits",
+                ),
+                Finding::warning(
+                    "Accounts \"test_account_1\", \"test_account_2\" share the same secret",
+                    "Check that each account was added with the secret its service issued",
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_secrets_shared_with_credentials() {
+        let mut store = get_mock_store();
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from(HOTP_KEY), OtpType::HOTP(Some(0))),
+        );
+        let mut account = Account::new(String::from(TOTP_KEY), OtpType::TOTP);
+        account
+            .add_credential("yubikey", String::from(HOTP_KEY), OtpType::TOTP)
+            .unwrap();
+        store.add(String::from(ACCOUNT_NAME_2), account);
+
+        assert_eq!(
+            check_secrets(&store),
+            vec![Finding::warning(
+                "Accounts \"test_account_1\", credential \"yubikey\" of \"test_account_2\" share the same secret",
+                "Check that each account was added with the secret its service issued",
+            )]
+        );
+    }
+
+    #[test]
+    fn accepts_strong_distinct_secrets() {
+        let mut store = get_mock_store();
+        store.add(
+            String::from(ACCOUNT_NAME_1),
+            Account::new(String::from(HOTP_KEY), OtpType::HOTP(Some(0))),
+        );
+        store.add(
+            String::from(ACCOUNT_NAME_2),
+            Account::new(String::from(TOTP_KEY), OtpType::TOTP),
+        );
+
+        assert_eq!(
+            check_secrets(&store),
+            vec![Finding::ok("No weak or duplicate secrets")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn checks_that_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = crate::storage::tests::get_test_directory("verify-files");
+        let path = directory.join("secrets.txt");
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(&directory, fs::Permissions::from_mode(0o755)).unwrap();
+        let missing = directory.join("accounts.txt");
+
+        let findings = check_files(&[directory.clone(), path.clone(), missing]);
+
+        assert_eq!(
+            findings[0].fix,
+            Some(format!("chmod 700 {}", directory.display()))
+        );
+        assert_eq!(findings[1].severity, Severity::Warning);
+        assert_eq!(
+            findings[1].fix,
+            Some(format!("chmod 600 {}", path.display()))
+        );
+        assert_eq!(findings[2].severity, Severity::Error);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
-use std::io::{self, Stderr, Stdin, Stdout, Write};
+use std::io::{self, IsTerminal, Stderr, Stdin, Stdout, Write};
+use zeroize::Zeroize;
 
 pub struct OtpWriter {
     pub out: Stdout,
@@ -48,18 +49,38 @@ impl OtpReader {
 	}
 }
 
+// Reads into the caller's buffer only, so secrets such as the pin are never copied
 pub trait ReadLine {
-	fn read_line(&mut self, b: &mut String) -> String;
+	fn read_line(&mut self, b: &mut String);
+	// Reads a line without echoing it when a person is typing, for pins and passphrases
+	fn read_secret(&mut self, b: &mut String);
+	// Whether a person is typing, rather than input being piped in
+	fn is_interactive(&self) -> bool;
 }
 
 impl ReadLine for OtpReader {
-	fn read_line(&mut self, buffer: &mut String) -> String {
-		match self.input.read_line(buffer) {
-			Ok(_) => buffer.to_string(),
-			Err(e) => {
-				eprintln!("{}", e);
-				String::new()
+	fn read_line(&mut self, buffer: &mut String) {
+		if let Err(e) = self.input.read_line(buffer) {
+			eprintln!("{}", e);
+		}
+	}
+
+	fn read_secret(&mut self, buffer: &mut String) {
+		if !self.is_interactive() {
+			return self.read_line(buffer);
+		}
+		match rpassword::read_password() {
+			Ok(mut secret) => {
+				// The line ending was dropped, so add it back to look like read_line
+				buffer.push_str(&secret);
+				buffer.push('\n');
+				secret.zeroize();
 			}
+			Err(e) => eprintln!("{}", e),
 		}
 	}
+
+	fn is_interactive(&self) -> bool {
+		self.input.is_terminal()
+	}
 }